@@ -0,0 +1,55 @@
+use crate::constants;
+use std::time::Duration;
+
+// How long to wait between reconnect attempts. Doubles after every failure, up to a max, so a Lichess outage doesn't
+// get hammered with requests. Ex: 1s, 2s, 4s, 8s, ... 60s.
+pub struct Backoff {
+    initial_delay_ms: u64,
+    max_delay_ms: u64,
+    next_delay_ms: u64,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        return Backoff::new(
+            constants::RECONNECT_INITIAL_DELAY_MS,
+            constants::RECONNECT_MAX_DELAY_MS,
+        );
+    }
+}
+
+impl Backoff {
+    pub fn new(initial_delay_ms: u64, max_delay_ms: u64) -> Self {
+        return Backoff {
+            initial_delay_ms,
+            max_delay_ms,
+            next_delay_ms: initial_delay_ms,
+        };
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let delay_ms = self.next_delay_ms;
+        self.next_delay_ms = u64::min(self.next_delay_ms.saturating_mul(2), self.max_delay_ms);
+        return Duration::from_millis(delay_ms);
+    }
+
+    // Call once a connection is working again.
+    pub fn reset(&mut self) {
+        self.next_delay_ms = self.initial_delay_ms;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn doubles_up_to_max() {
+        let mut backoff = Backoff::new(1000, 5000);
+        let delays: Vec<u128> = (0..5).map(|_| backoff.next_delay().as_millis()).collect();
+        assert_eq!(delays, vec![1000, 2000, 4000, 5000, 5000]);
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay().as_millis(), 1000);
+    }
+}
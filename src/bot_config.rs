@@ -0,0 +1,400 @@
+use crate::constants;
+use serde::{Deserialize, Serialize};
+use std::env;
+
+// Looked for in the working directory if no config file is given.
+pub const DEFAULT_CONFIG_PATH: &str = "bot.toml";
+
+// Environment overrides are the setting name, upper case, with this prefix. Ex: `CHESS_BOT_HASH_SIZE_MB=256`.
+pub const ENV_PREFIX: &str = "CHESS_BOT_";
+
+// Everything the Lichess bot can be set up with. Read from a TOML or JSON file, then environment variables, then the
+// command line, each overriding the last. Lists are comma separated in environment variables and on the command line.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct BotConfig {
+    // The bot's Lichess account. Needed to tell which side we are playing.
+    pub username: String,
+
+    // Where the Lichess API is. Only changed for testing against another server.
+    pub lichess_url: String,
+
+    // Only accept challenges from these players, or from anyone with `*`. Nobody can challenge if empty.
+    pub challenger_whitelist: Vec<String>,
+
+    // Never accept challenges from these players.
+    pub challenger_blocklist: Vec<String>,
+
+    // Lichess variant keys, like `standard` or `fromPosition`.
+    pub variants: Vec<String>,
+
+    // Lichess speeds: `ultraBullet`, `bullet`, `blitz`, `rapid`, `classical`, `correspondence`.
+    pub speeds: Vec<String>,
+
+    // Limits on the clock, in seconds. `None` means no limit.
+    pub min_initial_time_s: Option<u32>,
+    pub max_initial_time_s: Option<u32>,
+    pub min_increment_s: Option<u32>,
+    pub max_increment_s: Option<u32>,
+
+    pub accept_rated: bool,
+    pub accept_casual: bool,
+    pub accept_bots: bool,
+    pub accept_humans: bool,
+
+    // Challenger rating range. `None` means no limit.
+    pub min_rating: Option<u32>,
+    pub max_rating: Option<u32>,
+
+    pub max_concurrent_games: usize,
+
+    // Never think longer than this on one move, even with lots of time on the clock.
+    pub max_move_time_ms: Option<u64>,
+
+    // Transposition table size for each game.
+    pub hash_size_mb: usize,
+
+    // Polyglot opening book. Empty to play without one.
+    pub book_path: String,
+    pub max_book_depth_ply: u32,
+    pub book_best_move: bool,
+
+    // Claim the win once Lichess lets us, after the opponent leaves the game.
+    pub claim_victory: bool,
+
+    // Accept draw offers when our evaluation is at or below this, in centipawns. `None` declines every offer.
+    pub draw_accept_eval_cp: Option<i64>,
+
+    // Resign after this many moves in a row evaluated at or below the threshold. `None` never resigns.
+    pub resign_eval_cp: Option<i64>,
+    pub resign_after_moves: u32,
+
+    // Abort if the opponent hasn't made their first move after this long. `None` waits for Lichess to abort it.
+    pub abort_after_s: Option<u64>,
+
+    // Finished games are saved here as `<id>.pgn` and `<id>.json`. Empty to not save them.
+    pub game_log_dir: String,
+}
+
+impl Default for BotConfig {
+    fn default() -> Self {
+        BotConfig {
+            username: String::new(),
+            lichess_url: String::from(constants::LICHESS_URL),
+            challenger_whitelist: vec![],
+            challenger_blocklist: vec![],
+            variants: vec![String::from("standard")],
+            speeds: vec![
+                String::from("bullet"),
+                String::from("blitz"),
+                String::from("rapid"),
+                String::from("classical"),
+            ],
+            min_initial_time_s: None,
+            max_initial_time_s: None,
+            min_increment_s: None,
+            max_increment_s: None,
+            accept_rated: true,
+            accept_casual: true,
+            accept_bots: true,
+            accept_humans: true,
+            min_rating: None,
+            max_rating: None,
+            max_concurrent_games: 1,
+            max_move_time_ms: None,
+            hash_size_mb: constants::DEFAULT_TRANSPOSITION_TABLE_SIZE_MB,
+            book_path: String::from("book.bin"),
+            max_book_depth_ply: constants::DEFAULT_MAX_BOOK_DEPTH_PLY,
+            book_best_move: false,
+            claim_victory: true,
+            draw_accept_eval_cp: None,
+            resign_eval_cp: None,
+            resign_after_moves: 3,
+            abort_after_s: None,
+            game_log_dir: String::from("games"),
+        }
+    }
+}
+
+// Names used in config files, environment variables and command line flags.
+const SETTING_NAMES: [&str; 29] = [
+    "username",
+    "lichess_url",
+    "challenger_whitelist",
+    "challenger_blocklist",
+    "variants",
+    "speeds",
+    "min_initial_time_s",
+    "max_initial_time_s",
+    "min_increment_s",
+    "max_increment_s",
+    "accept_rated",
+    "accept_casual",
+    "accept_bots",
+    "accept_humans",
+    "min_rating",
+    "max_rating",
+    "max_concurrent_games",
+    "max_move_time_ms",
+    "hash_size_mb",
+    "book_path",
+    "max_book_depth_ply",
+    "book_best_move",
+    "claim_victory",
+    "draw_accept_eval_cp",
+    "resign_eval_cp",
+    "resign_after_moves",
+    "abort_after_s",
+    "game_log_dir",
+    "config",
+];
+
+impl BotConfig {
+    // Usage: `[--config <file>] [--<setting> <value>]...`. Settings use dashes on the command line, `--hash-size-mb 256`.
+    pub fn load(args: &[String]) -> Result<Self, String> {
+        let cli_overrides = parse_args(args)?;
+
+        // Find the config file. It is fine to not have one, unless it was asked for.
+        let cli_config_path = cli_overrides
+            .iter()
+            .find(|(name, _)| name == "config")
+            .map(|(_, v)| v.clone());
+        let env_config_path = env::var(format!("{ENV_PREFIX}CONFIG")).ok();
+        let mut config = match cli_config_path.or(env_config_path) {
+            Some(path) => BotConfig::from_file(&path)?,
+            None if std::path::Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                BotConfig::from_file(DEFAULT_CONFIG_PATH)?
+            }
+            None => BotConfig::default(),
+        };
+
+        for name in SETTING_NAMES.iter() {
+            if let Ok(value) = env::var(format!("{ENV_PREFIX}{}", name.to_ascii_uppercase())) {
+                config.set(name, &value)?;
+            }
+        }
+
+        for (name, value) in cli_overrides.iter() {
+            config.set(name, value)?;
+        }
+
+        if config.username.is_empty() {
+            return Err(format!(
+                "The bot username is not set. Add `username` to `{DEFAULT_CONFIG_PATH}`, set `{ENV_PREFIX}USERNAME`, or pass `--username`."
+            ));
+        }
+
+        return Ok(config);
+    }
+
+    // JSON if the file ends in `.json`, TOML otherwise.
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(t) => t,
+            Err(e) => return Err(format!("Unable to read config file `{path}`. Detail: {e}")),
+        };
+
+        let parsed = if path.ends_with(".json") {
+            serde_json::from_str(&text).map_err(|e| e.to_string())
+        } else {
+            toml::from_str(&text).map_err(|e| e.to_string())
+        };
+
+        return match parsed {
+            Ok(c) => Ok(c),
+            Err(e) => Err(format!("Unable to parse config file `{path}`. Detail: {e}")),
+        };
+    }
+
+    // Sets one setting from its text form, for environment and command line overrides.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name {
+            "username" => self.username = value.to_string(),
+            "lichess_url" => self.lichess_url = value.to_string(),
+            "challenger_whitelist" => self.challenger_whitelist = parse_list(value),
+            "challenger_blocklist" => self.challenger_blocklist = parse_list(value),
+            "variants" => self.variants = parse_list(value),
+            "speeds" => self.speeds = parse_list(value),
+            "min_initial_time_s" => self.min_initial_time_s = parse_optional(name, value)?,
+            "max_initial_time_s" => self.max_initial_time_s = parse_optional(name, value)?,
+            "min_increment_s" => self.min_increment_s = parse_optional(name, value)?,
+            "max_increment_s" => self.max_increment_s = parse_optional(name, value)?,
+            "accept_rated" => self.accept_rated = parse_value(name, value)?,
+            "accept_casual" => self.accept_casual = parse_value(name, value)?,
+            "accept_bots" => self.accept_bots = parse_value(name, value)?,
+            "accept_humans" => self.accept_humans = parse_value(name, value)?,
+            "min_rating" => self.min_rating = parse_optional(name, value)?,
+            "max_rating" => self.max_rating = parse_optional(name, value)?,
+            "max_concurrent_games" => self.max_concurrent_games = parse_value(name, value)?,
+            "max_move_time_ms" => self.max_move_time_ms = parse_optional(name, value)?,
+            "hash_size_mb" => self.hash_size_mb = parse_value(name, value)?,
+            "book_path" => self.book_path = value.to_string(),
+            "max_book_depth_ply" => self.max_book_depth_ply = parse_value(name, value)?,
+            "book_best_move" => self.book_best_move = parse_value(name, value)?,
+            "claim_victory" => self.claim_victory = parse_value(name, value)?,
+            "draw_accept_eval_cp" => self.draw_accept_eval_cp = parse_optional(name, value)?,
+            "resign_eval_cp" => self.resign_eval_cp = parse_optional(name, value)?,
+            "resign_after_moves" => self.resign_after_moves = parse_value(name, value)?,
+            "abort_after_s" => self.abort_after_s = parse_optional(name, value)?,
+            "game_log_dir" => self.game_log_dir = value.to_string(),
+
+            // Already used to find the file.
+            "config" => (),
+            _ => return Err(format!("Unknown setting: `{name}`.")),
+        }
+
+        return Ok(());
+    }
+
+    pub fn is_challenger_allowed(&self, name: &str) -> bool {
+        if self
+            .challenger_blocklist
+            .iter()
+            .any(|n| n.eq_ignore_ascii_case(name))
+        {
+            return false;
+        }
+        return self
+            .challenger_whitelist
+            .iter()
+            .any(|n| n == "*" || n.eq_ignore_ascii_case(name));
+    }
+
+    // Lichess user ids are the lower case username.
+    pub fn is_bot_user_id(&self, id: &str) -> bool {
+        return self.username.eq_ignore_ascii_case(id);
+    }
+}
+
+// `--hash-size-mb 256` becomes `("hash_size_mb", "256")`.
+fn parse_args(args: &[String]) -> Result<Vec<(String, String)>, String> {
+    let mut overrides: Vec<(String, String)> = vec![];
+    let mut i = 0;
+    while i < args.len() {
+        let name = match args[i].strip_prefix("--") {
+            Some(n) => n.replace('-', "_"),
+            None => {
+                return Err(format!(
+                    "Expected a `--<setting>` flag. Got: `{}`.",
+                    args[i]
+                ))
+            }
+        };
+        if !SETTING_NAMES.contains(&name.as_str()) {
+            return Err(format!("Unknown setting: `{}`.", args[i]));
+        }
+
+        let value = match args.get(i + 1) {
+            Some(v) => v.clone(),
+            None => return Err(format!("Missing value for `{}`.", args[i])),
+        };
+        overrides.push((name, value));
+        i += 2;
+    }
+
+    return Ok(overrides);
+}
+
+fn parse_list(value: &str) -> Vec<String> {
+    return value
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect();
+}
+
+fn parse_value<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    return match value.trim().parse() {
+        Ok(v) => Ok(v),
+        Err(_) => Err(format!("Invalid value for `{name}`: `{value}`.")),
+    };
+}
+
+// Empty or `none` clears the limit.
+fn parse_optional<T: std::str::FromStr>(name: &str, value: &str) -> Result<Option<T>, String> {
+    if value.trim().is_empty() || value.trim().eq_ignore_ascii_case("none") {
+        return Ok(None);
+    }
+    return Ok(Some(parse_value(name, value)?));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_then_overrides() {
+        let mut config: BotConfig = toml::from_str(
+            r#"
+            username = "botmasterj"
+            challenger_whitelist = ["The_Big_C"]
+            max_move_time_ms = 2000
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.variants, vec!["standard"]);
+        assert_eq!(
+            config.hash_size_mb,
+            constants::DEFAULT_TRANSPOSITION_TABLE_SIZE_MB
+        );
+        assert!(config.is_challenger_allowed("the_big_c"));
+        assert!(!config.is_challenger_allowed("someone_else"));
+        assert!(config.is_bot_user_id("botmasterj"));
+
+        let args: Vec<String> = [
+            "--hash-size-mb",
+            "256",
+            "--challenger-whitelist",
+            "*",
+            "--max-move-time-ms",
+            "none",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        for (name, value) in parse_args(&args).unwrap().iter() {
+            config.set(name, value).unwrap();
+        }
+        assert_eq!(config.hash_size_mb, 256);
+        assert!(config.is_challenger_allowed("someone_else"));
+        assert_eq!(config.max_move_time_ms, None);
+
+        config
+            .set("challenger_blocklist", "Someone_Else, other")
+            .unwrap();
+        assert!(!config.is_challenger_allowed("someone_else"));
+
+        // Nobody, unless they are listed.
+        config.set("challenger_whitelist", "").unwrap();
+        assert!(!config.is_challenger_allowed("the_big_c"));
+
+        assert!(parse_args(&["--hash".to_string(), "1".to_string()]).is_err());
+        assert!(config.set("hash_size_mb", "lots").is_err());
+    }
+
+    #[test]
+    fn example_config_parses() {
+        let config: BotConfig = toml::from_str(include_str!("../bot.example.toml")).unwrap();
+        assert_eq!(config.username, "botmasterj");
+        assert_eq!(config.challenger_whitelist, vec!["The_Big_C"]);
+        assert_eq!(config.draw_accept_eval_cp, Some(-50));
+        assert_eq!(config.resign_eval_cp, Some(-800));
+        assert_eq!(config.abort_after_s, Some(30));
+    }
+
+    #[test]
+    fn left_out_thresholds_are_off() {
+        // TOML has no null, so leaving a key out is the only way to turn these off in a file.
+        let path =
+            std::env::temp_dir().join(format!("chess-engine-config-{}.toml", std::process::id()));
+        std::fs::write(&path, "username = \"botmasterj\"\nresign_after_moves = 5\n").unwrap();
+        let config = BotConfig::from_file(&path.to_string_lossy());
+        let _ = std::fs::remove_file(&path);
+
+        let config = config.unwrap();
+        assert_eq!(config.resign_after_moves, 5);
+        assert_eq!(config.draw_accept_eval_cp, None);
+        assert_eq!(config.resign_eval_cp, None);
+        assert_eq!(config.abort_after_s, None);
+    }
+}
@@ -1,5 +1,34 @@
-#[derive(Copy, Clone, Debug)]
+use crate::color::Color;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum CastleSides {
     Short,
     Long,
 }
+
+impl CastleSides {
+    // Index for anything stored per castling right: white short, white long, black short, black long.
+    // Same order as the zobrist castling keys.
+    pub fn castling_rights_index(&self, color: &Color) -> usize {
+        let side_index = match self {
+            CastleSides::Short => 0,
+            CastleSides::Long => 1,
+        };
+        return color.idx() * 2 + side_index;
+    }
+
+    // Files the king and rook end up on, the same in Chess960 as in standard chess.
+    pub fn king_to_file(&self) -> usize {
+        return match self {
+            CastleSides::Short => 6,
+            CastleSides::Long => 2,
+        };
+    }
+
+    pub fn rook_to_file(&self) -> usize {
+        return match self {
+            CastleSides::Short => 5,
+            CastleSides::Long => 3,
+        };
+    }
+}
@@ -0,0 +1,281 @@
+use crate::bot_config::BotConfig;
+use crate::lichess_structs::InnerChallenge;
+
+// Fastest to slowest.
+pub const SPEEDS: [&str; 6] = [
+    "ultraBullet",
+    "bullet",
+    "blitz",
+    "rapid",
+    "classical",
+    "correspondence",
+];
+
+// Reasons Lichess shows the challenger when we decline. Ref: https://lichess.org/api#tag/Challenges/operation/challengeDecline
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DeclineReason {
+    Generic,
+    Later,
+    TooFast,
+    TooSlow,
+    TimeControl,
+    Rated,
+    Casual,
+    Standard,
+    Variant,
+    NoBot,
+    OnlyBot,
+}
+
+impl DeclineReason {
+    pub fn to_lichess_str(&self) -> &'static str {
+        return match self {
+            DeclineReason::Generic => "generic",
+            DeclineReason::Later => "later",
+            DeclineReason::TooFast => "tooFast",
+            DeclineReason::TooSlow => "tooSlow",
+            DeclineReason::TimeControl => "timeControl",
+            DeclineReason::Rated => "rated",
+            DeclineReason::Casual => "casual",
+            DeclineReason::Standard => "standard",
+            DeclineReason::Variant => "variant",
+            DeclineReason::NoBot => "noBot",
+            DeclineReason::OnlyBot => "onlyBot",
+        };
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ChallengeDecision {
+    Accept,
+    Decline(DeclineReason),
+}
+
+// Decides what to do with an incoming challenge, based on the config and how many games we are already playing.
+pub fn evaluate_challenge(
+    challenge: &InnerChallenge,
+    config: &BotConfig,
+    active_games: usize,
+) -> ChallengeDecision {
+    let challenger = &challenge.challenger;
+
+    if !config.is_challenger_allowed(&challenger.name) {
+        return ChallengeDecision::Decline(DeclineReason::Generic);
+    }
+
+    let is_bot = challenger.title.as_deref() == Some("BOT");
+    if is_bot && !config.accept_bots {
+        return ChallengeDecision::Decline(DeclineReason::NoBot);
+    }
+    if !is_bot && !config.accept_humans {
+        return ChallengeDecision::Decline(DeclineReason::OnlyBot);
+    }
+
+    // Lichess doesn't have a reason for ratings.
+    let is_rating_too_low = config.min_rating.is_some_and(|min| challenger.rating < min);
+    let is_rating_too_high = config.max_rating.is_some_and(|max| challenger.rating > max);
+    if is_rating_too_low || is_rating_too_high {
+        return ChallengeDecision::Decline(DeclineReason::Generic);
+    }
+
+    if !config.variants.contains(&challenge.variant.key) {
+        // If all we play is standard chess, say so. Otherwise just that this variant isn't one we play.
+        return match config.variants.as_slice() {
+            [only] if only == "standard" => ChallengeDecision::Decline(DeclineReason::Standard),
+            _ => ChallengeDecision::Decline(DeclineReason::Variant),
+        };
+    }
+
+    if let Some(reason) = check_time_control(challenge, config) {
+        return ChallengeDecision::Decline(reason);
+    }
+
+    if challenge.rated && !config.accept_rated {
+        return ChallengeDecision::Decline(DeclineReason::Casual);
+    }
+    if !challenge.rated && !config.accept_casual {
+        return ChallengeDecision::Decline(DeclineReason::Rated);
+    }
+
+    // Checked last, the challenger could try again later and be accepted.
+    if active_games >= config.max_concurrent_games {
+        return ChallengeDecision::Decline(DeclineReason::Later);
+    }
+
+    return ChallengeDecision::Accept;
+}
+
+fn check_time_control(challenge: &InnerChallenge, config: &BotConfig) -> Option<DeclineReason> {
+    if !config.speeds.contains(&challenge.speed) {
+        // Tell them which way to go, if every speed we play is on one side of theirs.
+        let speed_index = |speed: &str| SPEEDS.iter().position(|s| *s == speed);
+        let challenge_speed = speed_index(&challenge.speed);
+        let accepted_speeds: Vec<Option<usize>> =
+            config.speeds.iter().map(|s| speed_index(s)).collect();
+
+        if challenge_speed.is_some() && accepted_speeds.iter().all(|s| *s > challenge_speed) {
+            return Some(DeclineReason::TooFast);
+        }
+        if challenge_speed.is_some()
+            && accepted_speeds
+                .iter()
+                .all(|s| s.is_some() && *s < challenge_speed)
+        {
+            return Some(DeclineReason::TooSlow);
+        }
+        return Some(DeclineReason::TimeControl);
+    }
+
+    // Clock limits only apply to games with a clock.
+    let time_control = &challenge.time_control;
+    if time_control.r#type != "clock" {
+        return None;
+    }
+
+    let limit = time_control.limit.unwrap_or(0);
+    let increment = time_control.increment.unwrap_or(0);
+    if config.min_initial_time_s.is_some_and(|min| limit < min) {
+        return Some(DeclineReason::TooFast);
+    }
+    if config.max_initial_time_s.is_some_and(|max| limit > max) {
+        return Some(DeclineReason::TooSlow);
+    }
+    if config.min_increment_s.is_some_and(|min| increment < min)
+        || config.max_increment_s.is_some_and(|max| increment > max)
+    {
+        return Some(DeclineReason::TimeControl);
+    }
+
+    return None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn challenge() -> InnerChallenge {
+        let json = r#"{
+            "id": "abc123", "url": "https://lichess.org/abc123", "status": "created",
+            "challenger": {"id": "someone", "name": "Someone", "rating": 1500},
+            "destUser": {"id": "botmasterj", "name": "botmasterj", "title": "BOT", "rating": 1800},
+            "variant": {"key": "standard", "name": "Standard"},
+            "rated": true, "speed": "blitz",
+            "timeControl": {"type": "clock", "limit": 300, "increment": 3, "show": "5+3"}
+        }"#;
+        return serde_json::from_str(json).unwrap();
+    }
+
+    fn decline(reason: DeclineReason) -> ChallengeDecision {
+        return ChallengeDecision::Decline(reason);
+    }
+
+    // Anyone may challenge, everything else is the default.
+    fn open_config() -> BotConfig {
+        return BotConfig {
+            challenger_whitelist: vec![String::from("*")],
+            ..BotConfig::default()
+        };
+    }
+
+    #[test]
+    fn accepts_by_default() {
+        // Nobody is on the whitelist until the config adds them.
+        assert_eq!(
+            evaluate_challenge(&challenge(), &BotConfig::default(), 0),
+            decline(DeclineReason::Generic)
+        );
+
+        let config = open_config();
+        assert_eq!(
+            evaluate_challenge(&challenge(), &config, 0),
+            ChallengeDecision::Accept
+        );
+        assert_eq!(
+            evaluate_challenge(&challenge(), &config, 1),
+            decline(DeclineReason::Later)
+        );
+    }
+
+    #[test]
+    fn declines_with_reasons() {
+        let mut config = open_config();
+
+        let mut chess960 = challenge();
+        chess960.variant.key = String::from("chess960");
+        assert_eq!(
+            evaluate_challenge(&chess960, &config, 0),
+            decline(DeclineReason::Standard)
+        );
+        config.variants.push(String::from("fromPosition"));
+        assert_eq!(
+            evaluate_challenge(&chess960, &config, 0),
+            decline(DeclineReason::Variant)
+        );
+
+        let mut ultra_bullet = challenge();
+        ultra_bullet.speed = String::from("ultraBullet");
+        assert_eq!(
+            evaluate_challenge(&ultra_bullet, &config, 0),
+            decline(DeclineReason::TooFast)
+        );
+        let mut correspondence = challenge();
+        correspondence.speed = String::from("correspondence");
+        assert_eq!(
+            evaluate_challenge(&correspondence, &config, 0),
+            decline(DeclineReason::TooSlow)
+        );
+        config.speeds = vec![String::from("bullet"), String::from("rapid")];
+        assert_eq!(
+            evaluate_challenge(&challenge(), &config, 0),
+            decline(DeclineReason::TimeControl)
+        );
+        config.speeds.push(String::from("blitz"));
+
+        config.min_initial_time_s = Some(600);
+        assert_eq!(
+            evaluate_challenge(&challenge(), &config, 0),
+            decline(DeclineReason::TooFast)
+        );
+        config.min_initial_time_s = None;
+        config.max_increment_s = Some(2);
+        assert_eq!(
+            evaluate_challenge(&challenge(), &config, 0),
+            decline(DeclineReason::TimeControl)
+        );
+        config.max_increment_s = None;
+
+        config.accept_rated = false;
+        assert_eq!(
+            evaluate_challenge(&challenge(), &config, 0),
+            decline(DeclineReason::Casual)
+        );
+        config.accept_rated = true;
+
+        let mut bot = challenge();
+        bot.challenger.title = Some(String::from("BOT"));
+        config.accept_bots = false;
+        assert_eq!(
+            evaluate_challenge(&bot, &config, 0),
+            decline(DeclineReason::NoBot)
+        );
+        config.accept_humans = false;
+        assert_eq!(
+            evaluate_challenge(&challenge(), &config, 0),
+            decline(DeclineReason::OnlyBot)
+        );
+        config.accept_humans = true;
+
+        config.max_rating = Some(1400);
+        assert_eq!(
+            evaluate_challenge(&challenge(), &config, 0),
+            decline(DeclineReason::Generic)
+        );
+        config.max_rating = None;
+
+        config.challenger_blocklist.push(String::from("someone"));
+        assert_eq!(
+            evaluate_challenge(&challenge(), &config, 0),
+            decline(DeclineReason::Generic)
+        );
+    }
+}
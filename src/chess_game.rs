@@ -1,13 +1,25 @@
 use crate::castle_sides::CastleSides;
 use crate::color::Color;
 use crate::constants;
+use crate::evaluation_trace::{EvaluationTerm, EvaluationTrace};
 use crate::helpers::*;
+use crate::king_safety;
+use crate::opening_book::OpeningBook;
+use crate::pawn_hash_table::{PawnHashEntry, PawnHashTable};
+use crate::pawn_structure::{self, relative_rank, PawnStructure};
+use crate::piece_activity::{PieceActivityWeights, Weight};
 use crate::piece_type::PieceType;
 use crate::r#move::Move;
 use crate::runtime_calculated_constants::Constants;
+use crate::score;
+use crate::search_limits::SearchLimits;
+use crate::transposition_table::TranspositionTable;
 use crate::transposition_table_entry::{TranspositionTableEntry, TranspositionTableNodeType};
-use std::collections::HashMap;
+use std::cell::RefCell;
 use std::io;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Instant;
 
 // TODO: Research more on lifetime stuff.
 #[derive(Clone)]
@@ -16,11 +28,22 @@ pub struct ChessGame<'a> {
 
     pub zobrist_hash: u64,
 
-    pub transposition_table: HashMap<u64, TranspositionTableEntry>,
+    // Only the pawns, for the pawn hash table. Made of the same keys as `zobrist_hash`.
+    pub pawn_zobrist_hash: u64,
+    // Filled in by `evaluate_pawns`, which only borrows the game.
+    pub pawn_hash_table: RefCell<PawnHashTable>,
+
+    pub transposition_table: TranspositionTable,
 
     // En-Passant
     pub en_passant_target: Option<usize>,
 
+    // Draw tracking. The history holds the zobrist hash (and halfmove clock) from before each move made.
+    pub halfmove_clock: u32,
+    pub fullmove_number: u32,
+    pub zobrist_hash_history: Vec<u64>,
+    pub halfmove_clock_history: Vec<u32>,
+
     // Flags.
     pub white_to_move: bool,
     pub can_white_castle_long: bool,
@@ -28,6 +51,11 @@ pub struct ChessGame<'a> {
     pub can_black_castle_long: bool,
     pub can_black_castle_short: bool,
 
+    // Chess960. The rook each castling right belongs to, indexed by `CastleSides::castling_rights_index`. Set from the
+    // FEN, these are the corners in standard chess. `is_chess960` only changes how castling moves are written.
+    pub castling_rook_squares: [usize; 4],
+    pub is_chess960: bool,
+
     /*
         0 -> white_pawns
         1 -> white_bishops
@@ -49,6 +77,23 @@ pub struct ChessGame<'a> {
 
     pub legal_moves: Vec<Move>,
 
+    // Checked before searching, if set.
+    pub opening_book: Option<Arc<OpeningBook>>,
+
+    // Mobility, outposts and the like. Tunable, the search only reads them.
+    pub piece_activity_weights: PieceActivityWeights,
+
+    // Search control. Set `search_limits` before calling `iterative_deepening_minimax`.
+    pub search_limits: SearchLimits,
+    pub search_start_time: Instant,
+    pub search_nodes: u64,
+
+    // Deepest search finished in the last call to `iterative_deepening_minimax`.
+    pub search_depth: u32,
+    pub search_root_ply: usize,
+    pub is_search_aborted: bool,
+    pub print_uci_search_info: bool,
+
     pub debug_minimax_calls: u64,
     pub debug_mimimax_moves_made: Vec<Move>,
 }
@@ -59,21 +104,45 @@ impl<'a> ChessGame<'a> {
             bitboard_constants: c,
 
             zobrist_hash: 0,
-            transposition_table: HashMap::new(),
+            pawn_zobrist_hash: 0,
+            pawn_hash_table: RefCell::new(PawnHashTable::new(constants::PAWN_HASH_TABLE_SIZE_MB)),
+            transposition_table: TranspositionTable::new(
+                constants::INITIAL_TRANSPOSITION_TABLE_SIZE_MB,
+            ),
 
             en_passant_target: None,
 
+            halfmove_clock: 0,
+            fullmove_number: 1,
+            zobrist_hash_history: vec![],
+            halfmove_clock_history: vec![],
+
             white_to_move: true,
             can_white_castle_long: true,
             can_white_castle_short: true,
             can_black_castle_long: true,
             can_black_castle_short: true,
 
+            castling_rook_squares: constants::STANDARD_CASTLING_ROOK_SQUARES,
+            is_chess960: false,
+
             piece_bitboards: [0; 12],
             occupancy_bitboards: [0; 3],
 
             legal_moves: vec![],
 
+            opening_book: None,
+
+            piece_activity_weights: PieceActivityWeights::default(),
+
+            search_limits: SearchLimits::default(),
+            search_start_time: Instant::now(),
+            search_nodes: 0,
+            search_depth: 0,
+            search_root_ply: 0,
+            is_search_aborted: false,
+            print_uci_search_info: false,
+
             debug_minimax_calls: 0,
             debug_mimimax_moves_made: vec![],
         };
@@ -192,6 +261,13 @@ impl<'a> ChessGame<'a> {
 
         // Reset the zobrist hash.
         self.zobrist_hash = 0;
+        self.pawn_zobrist_hash = 0;
+
+        // Reset draw tracking, we don't know the moves that lead here.
+        self.halfmove_clock = 0;
+        self.fullmove_number = 1;
+        self.zobrist_hash_history.clear();
+        self.halfmove_clock_history.clear();
 
         // Trim the string.
         let trimmed_full_fen = fen.trim();
@@ -249,8 +325,12 @@ impl<'a> ChessGame<'a> {
                 self.place_piece_on_board(piece_color, piece_type, square);
 
                 // Update the zobrist hash.
-                self.zobrist_hash ^= self.bitboard_constants.zobrist_table
+                let piece_key = self.bitboard_constants.zobrist_table
                     [piece_type.bitboard_index() + piece_color.piece_bitboard_offset()][square];
+                self.zobrist_hash ^= piece_key;
+                if piece_type == PieceType::Pawn {
+                    self.pawn_zobrist_hash ^= piece_key;
+                }
 
                 x_pos += 1;
             }
@@ -295,7 +375,8 @@ impl<'a> ChessGame<'a> {
             ));
         }
 
-        // Castling.
+        // Castling. Standard `KQkq`, or Chess960 in Shredder-FEN (`HAha`, the rook files) or X-FEN (`KQkq` for the
+        // outermost rooks, files otherwise).
         let castling_rights_str = parts.next();
         match castling_rights_str {
             Some(s) => {
@@ -304,28 +385,64 @@ impl<'a> ChessGame<'a> {
                 self.can_white_castle_short = false;
                 self.can_black_castle_long = false;
                 self.can_black_castle_short = false;
+                self.castling_rook_squares = constants::STANDARD_CASTLING_ROOK_SQUARES;
 
                 // Update rights based on what we find in the string.
                 for c in s.chars() {
-                    match c {
-                        'K' => {
-                            self.can_white_castle_short = true;
-                            self.zobrist_hash ^= self.bitboard_constants.zobrist_castling_rights[0];
-                        }
-                        'Q' => {
-                            self.can_white_castle_long = true;
-                            self.zobrist_hash ^= self.bitboard_constants.zobrist_castling_rights[1];
-                        }
-                        'k' => {
-                            self.can_black_castle_short = true;
-                            self.zobrist_hash ^= self.bitboard_constants.zobrist_castling_rights[2];
-                        }
-                        'q' => {
-                            self.can_black_castle_long = true;
-                            self.zobrist_hash ^= self.bitboard_constants.zobrist_castling_rights[3];
+                    let color = if c.is_ascii_uppercase() {
+                        Color::White
+                    } else {
+                        Color::Black
+                    };
+                    let back_rank_start: usize = match color {
+                        Color::White => 56,
+                        Color::Black => 0,
+                    };
+                    let king_file = self.piece_bitboards
+                        [color.piece_bitboard_offset() + PieceType::King.bitboard_index()]
+                    .trailing_zeros() as usize
+                        % 8;
+                    let rook_files: Vec<usize> = (0..8)
+                        .filter(|file| {
+                            let (piece_type, piece_color) =
+                                self.get_piece_at_square(back_rank_start + file);
+                            piece_type == Some(PieceType::Rook)
+                                && piece_color.map(|p| p.idx()) == Some(color.idx())
+                        })
+                        .collect();
+
+                    let (side, rook_file) = match c.to_ascii_lowercase() {
+                        // Outermost rook on that side. Default to the corner if there isn't one.
+                        'k' => (
+                            CastleSides::Short,
+                            rook_files
+                                .iter()
+                                .filter(|f| **f > king_file)
+                                .max()
+                                .copied()
+                                .unwrap_or(7),
+                        ),
+                        'q' => (
+                            CastleSides::Long,
+                            rook_files
+                                .iter()
+                                .filter(|f| **f < king_file)
+                                .min()
+                                .copied()
+                                .unwrap_or(0),
+                        ),
+                        'a'..='h' => {
+                            let file = (c.to_ascii_lowercase() as u8 - b'a') as usize;
+                            if file > king_file {
+                                (CastleSides::Short, file)
+                            } else {
+                                (CastleSides::Long, file)
+                            }
                         }
-                        _ => (),
-                    }
+                        '-' => continue,
+                        _ => return Err(format!("Unexpected castling character: {c}.")),
+                    };
+                    self.grant_castling_right(&color, side, back_rank_start + rook_file);
                 }
             }
             None => return Ok(()),
@@ -348,9 +465,49 @@ impl<'a> ChessGame<'a> {
             None => return Ok(()),
         };
 
+        // Half moves since the last capture or pawn advance.
+        match parts.next() {
+            Some(s) => {
+                self.halfmove_clock = match s.parse() {
+                    Ok(n) => n,
+                    Err(e) => return Err(format!("Unable to parse halfmove clock `{s}`. {e}")),
+                };
+            }
+            None => return Ok(()),
+        };
+
+        // Full move number, starts at 1 and goes up after black moves.
+        match parts.next() {
+            Some(s) => {
+                self.fullmove_number = match s.parse() {
+                    Ok(n) => n,
+                    Err(e) => return Err(format!("Unable to parse fullmove number `{s}`. {e}")),
+                };
+            }
+            None => return Ok(()),
+        };
+
         return Ok(());
     }
 
+    // Used while importing a FEN. Adds the castling right, and remembers which rook it belongs to.
+    fn grant_castling_right(&mut self, color: &Color, side: CastleSides, rook_square: usize) {
+        let index = side.castling_rights_index(color);
+        let can_castle = match index {
+            0 => &mut self.can_white_castle_short,
+            1 => &mut self.can_white_castle_long,
+            2 => &mut self.can_black_castle_short,
+            _ => &mut self.can_black_castle_long,
+        };
+
+        // Some FENs list a right twice, like `KH`. Only hash it once.
+        if !*can_castle {
+            *can_castle = true;
+            self.zobrist_hash ^= self.bitboard_constants.zobrist_castling_rights[index];
+        }
+        self.castling_rook_squares[index] = rook_square;
+    }
+
     pub fn export_fen(&self) -> String {
         let mut fen = String::new();
         let mut prior_empty_count = 0;
@@ -402,18 +559,46 @@ impl<'a> ChessGame<'a> {
             fen += " b ";
         }
 
-        // Castling rights.
-        if self.can_white_castle_short {
-            fen += "K";
-        }
-        if self.can_white_castle_long {
-            fen += "Q";
-        }
-        if self.can_black_castle_short {
-            fen += "k";
-        }
-        if self.can_black_castle_long {
-            fen += "q";
+        // Castling rights. X-FEN, so standard positions come out as `KQkq`, and Chess960 positions only use the rook's
+        // file when another rook is further out on the same side.
+        for (color, side, can_castle) in [
+            (
+                Color::White,
+                CastleSides::Short,
+                self.can_white_castle_short,
+            ),
+            (Color::White, CastleSides::Long, self.can_white_castle_long),
+            (
+                Color::Black,
+                CastleSides::Short,
+                self.can_black_castle_short,
+            ),
+            (Color::Black, CastleSides::Long, self.can_black_castle_long),
+        ] {
+            if !can_castle {
+                continue;
+            }
+
+            let rook_square = self.castling_rook_squares[side.castling_rights_index(&color)];
+            let rook_bitboard = self.piece_bitboards
+                [color.piece_bitboard_offset() + PieceType::Rook.bitboard_index()];
+            let outer_files = match side {
+                CastleSides::Short => (rook_square % 8 + 1)..8,
+                CastleSides::Long => 0..(rook_square % 8),
+            };
+            let is_outermost = outer_files
+                .into_iter()
+                .all(|file| get_bit(rook_bitboard, rook_square / 8 * 8 + file) == 0);
+
+            let c = match (is_outermost, side) {
+                (true, CastleSides::Short) => 'k',
+                (true, CastleSides::Long) => 'q',
+                (false, _) => (b'a' + (rook_square % 8) as u8) as char,
+            };
+            fen.push(match color {
+                Color::White => c.to_ascii_uppercase(),
+                Color::Black => c,
+            });
         }
 
         // Special case if no casting rights available.
@@ -432,7 +617,8 @@ impl<'a> ChessGame<'a> {
             None => fen += "-",
         }
 
-        // Ehhh, at some point add the half moves since last capture or pawn advance. And the full move count.
+        // Half moves since last capture or pawn advance. And the full move count.
+        fen += &format!(" {} {}", self.halfmove_clock, self.fullmove_number);
 
         return fen;
     }
@@ -571,10 +757,17 @@ impl<'a> ChessGame<'a> {
     pub fn make_move(&mut self, this_move: &Move, update_legal_moves: bool) {
         //let debug_initial_game_state = self.clone();
 
+        // Remember where we came from, for repetitions and unmaking the move.
+        self.zobrist_hash_history.push(self.zobrist_hash);
+        self.halfmove_clock_history.push(self.halfmove_clock);
+
         let source_piece = this_move
             .from_piece_type
             .expect("This should always be here.");
 
+        // Castling is stored as the king taking its own rook, but the king lands somewhere else.
+        let to_square = this_move.destination_square();
+
         // Handle generic captures, and en-passant captures.
         let our_color: Color;
         let their_color: Color;
@@ -604,6 +797,8 @@ impl<'a> ChessGame<'a> {
                 );
                 self.zobrist_hash ^= self.bitboard_constants.zobrist_table
                     [our_piece_bitboard_index][this_move.from_square];
+                self.pawn_zobrist_hash ^= self.bitboard_constants.zobrist_table
+                    [our_piece_bitboard_index][this_move.from_square];
 
                 // Special logic for pawn promotion.
                 match this_move.pawn_promoting_to {
@@ -614,7 +809,7 @@ impl<'a> ChessGame<'a> {
                                     + piece_promoted_to.bitboard_index()] = set_bit(
                                     self.piece_bitboards[our_piece_bitboard_offset
                                         + piece_promoted_to.bitboard_index()],
-                                    this_move.to_square,
+                                    to_square,
                                 )
                             }
                             PieceType::Rook => {
@@ -622,7 +817,7 @@ impl<'a> ChessGame<'a> {
                                     + piece_promoted_to.bitboard_index()] = set_bit(
                                     self.piece_bitboards[our_piece_bitboard_offset
                                         + piece_promoted_to.bitboard_index()],
-                                    this_move.to_square,
+                                    to_square,
                                 )
                             }
                             PieceType::Bishop => {
@@ -630,7 +825,7 @@ impl<'a> ChessGame<'a> {
                                     + piece_promoted_to.bitboard_index()] = set_bit(
                                     self.piece_bitboards[our_piece_bitboard_offset
                                         + piece_promoted_to.bitboard_index()],
-                                    this_move.to_square,
+                                    to_square,
                                 )
                             }
                             PieceType::Knight => {
@@ -638,24 +833,24 @@ impl<'a> ChessGame<'a> {
                                     + piece_promoted_to.bitboard_index()] = set_bit(
                                     self.piece_bitboards[our_piece_bitboard_offset
                                         + piece_promoted_to.bitboard_index()],
-                                    this_move.to_square,
+                                    to_square,
                                 )
                             }
                             _ => panic!("Tried to promote to an illegal piece."),
                         }
                         self.zobrist_hash ^= self.bitboard_constants.zobrist_table
                             [our_piece_bitboard_offset + piece_promoted_to.bitboard_index()]
-                            [this_move.to_square];
+                            [to_square];
                     }
 
                     // Otherwise, it's a normal pawn move.
                     None => {
-                        self.piece_bitboards[our_piece_bitboard_index] = set_bit(
-                            self.piece_bitboards[our_piece_bitboard_index],
-                            this_move.to_square,
-                        );
+                        self.piece_bitboards[our_piece_bitboard_index] =
+                            set_bit(self.piece_bitboards[our_piece_bitboard_index], to_square);
                         self.zobrist_hash ^= self.bitboard_constants.zobrist_table
-                            [our_piece_bitboard_index][this_move.to_square];
+                            [our_piece_bitboard_index][to_square];
+                        self.pawn_zobrist_hash ^= self.bitboard_constants.zobrist_table
+                            [our_piece_bitboard_index][to_square];
                     }
                 }
             }
@@ -666,15 +861,13 @@ impl<'a> ChessGame<'a> {
                     self.piece_bitboards[our_piece_bitboard_index],
                     this_move.from_square,
                 );
-                self.piece_bitboards[our_piece_bitboard_index] = set_bit(
-                    self.piece_bitboards[our_piece_bitboard_index],
-                    this_move.to_square,
-                );
+                self.piece_bitboards[our_piece_bitboard_index] =
+                    set_bit(self.piece_bitboards[our_piece_bitboard_index], to_square);
 
                 self.zobrist_hash ^= self.bitboard_constants.zobrist_table
                     [our_piece_bitboard_index][this_move.from_square];
-                self.zobrist_hash ^= self.bitboard_constants.zobrist_table
-                    [our_piece_bitboard_index][this_move.to_square];
+                self.zobrist_hash ^=
+                    self.bitboard_constants.zobrist_table[our_piece_bitboard_index][to_square];
             }
         }
 
@@ -683,10 +876,8 @@ impl<'a> ChessGame<'a> {
             self.occupancy_bitboards[our_occupancies_index],
             this_move.from_square,
         );
-        self.occupancy_bitboards[our_occupancies_index] = set_bit(
-            self.occupancy_bitboards[our_occupancies_index],
-            this_move.to_square,
-        );
+        self.occupancy_bitboards[our_occupancies_index] =
+            set_bit(self.occupancy_bitboards[our_occupancies_index], to_square);
 
         // Update all occupancies, source piece always moves.
         self.occupancy_bitboards[2] = pop_bit(self.occupancy_bitboards[2], this_move.from_square);
@@ -694,7 +885,7 @@ impl<'a> ChessGame<'a> {
         // Figure out if we are capturing.
         let is_capture = this_move.to_piece_type.is_some();
         if !is_capture {
-            self.occupancy_bitboards[2] = set_bit(self.occupancy_bitboards[2], this_move.to_square);
+            self.occupancy_bitboards[2] = set_bit(self.occupancy_bitboards[2], to_square);
         } else {
             let their_piece = this_move
                 .to_piece_type
@@ -709,10 +900,10 @@ impl<'a> ChessGame<'a> {
                     if this_move.is_en_passant_capture {
                         // Place our pawn on the target square. Normal captures do not need to update this, but en-passant does.
                         self.occupancy_bitboards[2] =
-                            set_bit(self.occupancy_bitboards[2], this_move.to_square);
+                            set_bit(self.occupancy_bitboards[2], to_square);
                         let en_passant_target_pawn_index: usize = match their_color {
-                            Color::White => this_move.to_square - 8,
-                            Color::Black => this_move.to_square + 8,
+                            Color::White => to_square - 8,
+                            Color::Black => to_square + 8,
                         };
 
                         // Remove their pawn we captured en-passant.
@@ -725,6 +916,9 @@ impl<'a> ChessGame<'a> {
                         self.zobrist_hash ^= self.bitboard_constants.zobrist_table
                             [their_piece_bitboard_offset + their_piece.bitboard_index()]
                             [en_passant_target_pawn_index];
+                        self.pawn_zobrist_hash ^= self.bitboard_constants.zobrist_table
+                            [their_piece_bitboard_offset + their_piece.bitboard_index()]
+                            [en_passant_target_pawn_index];
 
                         // Remove their occupancy.
                         self.occupancy_bitboards[their_occupancies_index] = pop_bit(
@@ -737,36 +931,30 @@ impl<'a> ChessGame<'a> {
                             pop_bit(self.occupancy_bitboards[2], en_passant_target_pawn_index);
                     } else {
                         // Remove that piece from the board.
-                        self.piece_bitboards[their_piece_bitboard_index] = pop_bit(
-                            self.piece_bitboards[their_piece_bitboard_index],
-                            this_move.to_square,
-                        );
+                        self.piece_bitboards[their_piece_bitboard_index] =
+                            pop_bit(self.piece_bitboards[their_piece_bitboard_index], to_square);
                         self.zobrist_hash ^= self.bitboard_constants.zobrist_table
-                            [their_piece_bitboard_index][this_move.to_square];
+                            [their_piece_bitboard_index][to_square];
+                        self.pawn_zobrist_hash ^= self.bitboard_constants.zobrist_table
+                            [their_piece_bitboard_index][to_square];
 
                         // Update their occupancies.
-                        self.occupancy_bitboards[their_occupancies_index] = pop_bit(
-                            self.occupancy_bitboards[their_occupancies_index],
-                            this_move.to_square,
-                        );
+                        self.occupancy_bitboards[their_occupancies_index] =
+                            pop_bit(self.occupancy_bitboards[their_occupancies_index], to_square);
                     }
                 }
 
                 // For all non-pawn captures...
                 _ => {
                     // Remove that piece from the board.
-                    self.piece_bitboards[their_piece_bitboard_index] = pop_bit(
-                        self.piece_bitboards[their_piece_bitboard_index],
-                        this_move.to_square,
-                    );
+                    self.piece_bitboards[their_piece_bitboard_index] =
+                        pop_bit(self.piece_bitboards[their_piece_bitboard_index], to_square);
                     self.zobrist_hash ^= self.bitboard_constants.zobrist_table
-                        [their_piece_bitboard_index][this_move.to_square];
+                        [their_piece_bitboard_index][to_square];
 
                     // Update their occupancies.
-                    self.occupancy_bitboards[their_occupancies_index] = pop_bit(
-                        self.occupancy_bitboards[their_occupancies_index],
-                        this_move.to_square,
-                    );
+                    self.occupancy_bitboards[their_occupancies_index] =
+                        pop_bit(self.occupancy_bitboards[their_occupancies_index], to_square);
                 }
             }
         }
@@ -774,16 +962,11 @@ impl<'a> ChessGame<'a> {
         // Lastly, handle castling.
         match this_move.castle_side {
             None => (),
-            Some(side) => {
-                let king_from_position = this_move.from_square;
-                let rook_from_position = match side {
-                    CastleSides::Short => king_from_position + 3,
-                    CastleSides::Long => king_from_position - 4,
-                };
-                let rook_to_position = match side {
-                    CastleSides::Short => king_from_position + 1,
-                    CastleSides::Long => king_from_position - 1,
-                };
+            Some(_) => {
+                let rook_from_position = this_move.to_square;
+                let rook_to_position = this_move
+                    .castling_rook_destination_square()
+                    .expect("Castling moves have a rook destination.");
 
                 // Move our rook over.
                 let rook_bitboard_index =
@@ -800,21 +983,13 @@ impl<'a> ChessGame<'a> {
                 self.zobrist_hash ^=
                     self.bitboard_constants.zobrist_table[rook_bitboard_index][rook_to_position];
 
-                // Update our occupancies.
-                self.occupancy_bitboards[our_occupancies_index] = pop_bit(
-                    self.occupancy_bitboards[our_occupancies_index],
-                    rook_from_position,
-                );
-                self.occupancy_bitboards[our_occupancies_index] = set_bit(
-                    self.occupancy_bitboards[our_occupancies_index],
-                    rook_to_position,
-                );
-
-                // Update global occupancies.
+                // In Chess960 the king and rook can land on each other's squares, so rebuild our occupancies.
+                let our_pieces =
+                    &self.piece_bitboards[our_piece_bitboard_offset..our_piece_bitboard_offset + 6];
+                self.occupancy_bitboards[our_occupancies_index] =
+                    our_pieces.iter().fold(0, |all, bitboard| all | bitboard);
                 self.occupancy_bitboards[2] =
-                    pop_bit(self.occupancy_bitboards[2], rook_from_position);
-                self.occupancy_bitboards[2] =
-                    set_bit(self.occupancy_bitboards[2], rook_to_position);
+                    self.occupancy_bitboards[0] | self.occupancy_bitboards[1];
             }
         }
 
@@ -853,6 +1028,18 @@ impl<'a> ChessGame<'a> {
             None => (),
         }
 
+        // Captures and pawn moves can't be undone, so they reset the fifty move rule.
+        if source_piece == PieceType::Pawn || is_capture {
+            self.halfmove_clock = 0;
+        } else {
+            self.halfmove_clock += 1;
+        }
+
+        // The full move number goes up after black moves.
+        if !self.white_to_move {
+            self.fullmove_number += 1;
+        }
+
         // Important for checking if move is illegal.
         self.white_to_move = !self.white_to_move;
         self.zobrist_hash ^= self.bitboard_constants.zobrist_to_move;
@@ -873,6 +1060,9 @@ impl<'a> ChessGame<'a> {
             .from_piece_type
             .expect("This should always be here.");
 
+        // Castling is stored as the king taking its own rook, but the king lands somewhere else.
+        let to_square = this_move.destination_square();
+
         // Handle generic captures, and en-passant captures.
         let our_color: Color;
         let their_color: Color;
@@ -902,6 +1092,8 @@ impl<'a> ChessGame<'a> {
                 );
                 self.zobrist_hash ^= self.bitboard_constants.zobrist_table
                     [our_piece_bitboard_index][this_move.from_square];
+                self.pawn_zobrist_hash ^= self.bitboard_constants.zobrist_table
+                    [our_piece_bitboard_index][this_move.from_square];
 
                 // Special logic for pawn DEMOTION.
                 match this_move.pawn_promoting_to {
@@ -912,7 +1104,7 @@ impl<'a> ChessGame<'a> {
                                     + piece_promoted_to.bitboard_index()] = pop_bit(
                                     self.piece_bitboards[our_piece_bitboard_offset
                                         + piece_promoted_to.bitboard_index()],
-                                    this_move.to_square,
+                                    to_square,
                                 )
                             }
                             PieceType::Rook => {
@@ -920,7 +1112,7 @@ impl<'a> ChessGame<'a> {
                                     + piece_promoted_to.bitboard_index()] = pop_bit(
                                     self.piece_bitboards[our_piece_bitboard_offset
                                         + piece_promoted_to.bitboard_index()],
-                                    this_move.to_square,
+                                    to_square,
                                 )
                             }
                             PieceType::Bishop => {
@@ -928,7 +1120,7 @@ impl<'a> ChessGame<'a> {
                                     + piece_promoted_to.bitboard_index()] = pop_bit(
                                     self.piece_bitboards[our_piece_bitboard_offset
                                         + piece_promoted_to.bitboard_index()],
-                                    this_move.to_square,
+                                    to_square,
                                 )
                             }
                             PieceType::Knight => {
@@ -936,48 +1128,44 @@ impl<'a> ChessGame<'a> {
                                     + piece_promoted_to.bitboard_index()] = pop_bit(
                                     self.piece_bitboards[our_piece_bitboard_offset
                                         + piece_promoted_to.bitboard_index()],
-                                    this_move.to_square,
+                                    to_square,
                                 )
                             }
                             _ => panic!("Tried to promote to an illegal piece."),
                         }
                         self.zobrist_hash ^= self.bitboard_constants.zobrist_table
                             [our_piece_bitboard_offset + piece_promoted_to.bitboard_index()]
-                            [this_move.to_square];
+                            [to_square];
                     }
                     None => {
-                        self.piece_bitboards[our_piece_bitboard_index] = pop_bit(
-                            self.piece_bitboards[our_piece_bitboard_index],
-                            this_move.to_square,
-                        );
+                        self.piece_bitboards[our_piece_bitboard_index] =
+                            pop_bit(self.piece_bitboards[our_piece_bitboard_index], to_square);
                         self.zobrist_hash ^= self.bitboard_constants.zobrist_table
-                            [our_piece_bitboard_index][this_move.to_square];
+                            [our_piece_bitboard_index][to_square];
+                        self.pawn_zobrist_hash ^= self.bitboard_constants.zobrist_table
+                            [our_piece_bitboard_index][to_square];
                     }
                 }
             }
 
             // Every other piece, remove it from the destination, place it at the source.
             _ => {
-                self.piece_bitboards[our_piece_bitboard_index] = pop_bit(
-                    self.piece_bitboards[our_piece_bitboard_index],
-                    this_move.to_square,
-                );
+                self.piece_bitboards[our_piece_bitboard_index] =
+                    pop_bit(self.piece_bitboards[our_piece_bitboard_index], to_square);
                 self.piece_bitboards[our_piece_bitboard_index] = set_bit(
                     self.piece_bitboards[our_piece_bitboard_index],
                     this_move.from_square,
                 );
-                self.zobrist_hash ^= self.bitboard_constants.zobrist_table
-                    [our_piece_bitboard_index][this_move.to_square];
+                self.zobrist_hash ^=
+                    self.bitboard_constants.zobrist_table[our_piece_bitboard_index][to_square];
                 self.zobrist_hash ^= self.bitboard_constants.zobrist_table
                     [our_piece_bitboard_index][this_move.from_square];
             }
         }
 
         // Update our occupancies.
-        self.occupancy_bitboards[our_occupancies_index] = pop_bit(
-            self.occupancy_bitboards[our_occupancies_index],
-            this_move.to_square,
-        );
+        self.occupancy_bitboards[our_occupancies_index] =
+            pop_bit(self.occupancy_bitboards[our_occupancies_index], to_square);
         self.occupancy_bitboards[our_occupancies_index] = set_bit(
             self.occupancy_bitboards[our_occupancies_index],
             this_move.from_square,
@@ -989,7 +1177,7 @@ impl<'a> ChessGame<'a> {
         // Figure out if we are capturing.
         let is_capture = this_move.to_piece_type.is_some();
         if !is_capture {
-            self.occupancy_bitboards[2] = pop_bit(self.occupancy_bitboards[2], this_move.to_square);
+            self.occupancy_bitboards[2] = pop_bit(self.occupancy_bitboards[2], to_square);
         } else {
             let their_piece = this_move
                 .to_piece_type
@@ -1004,10 +1192,10 @@ impl<'a> ChessGame<'a> {
                     if this_move.is_en_passant_capture {
                         // Remove our pawn from the target square. Normal captures do not need to update this, but en-passant does.
                         self.occupancy_bitboards[2] =
-                            pop_bit(self.occupancy_bitboards[2], this_move.to_square);
+                            pop_bit(self.occupancy_bitboards[2], to_square);
                         let en_passant_target_pawn_index: usize = match their_color {
-                            Color::White => this_move.to_square - 8,
-                            Color::Black => this_move.to_square + 8,
+                            Color::White => to_square - 8,
+                            Color::Black => to_square + 8,
                         };
 
                         // Add their pawn we captured en-passant.
@@ -1020,6 +1208,9 @@ impl<'a> ChessGame<'a> {
                         self.zobrist_hash ^= self.bitboard_constants.zobrist_table
                             [their_piece_bitboard_offset + their_piece.bitboard_index()]
                             [en_passant_target_pawn_index];
+                        self.pawn_zobrist_hash ^= self.bitboard_constants.zobrist_table
+                            [their_piece_bitboard_offset + their_piece.bitboard_index()]
+                            [en_passant_target_pawn_index];
 
                         // Add their occupancy.
                         self.occupancy_bitboards[their_occupancies_index] = set_bit(
@@ -1032,36 +1223,30 @@ impl<'a> ChessGame<'a> {
                             set_bit(self.occupancy_bitboards[2], en_passant_target_pawn_index);
                     } else {
                         // Add that piece from the board.
-                        self.piece_bitboards[their_piece_bitboard_index] = set_bit(
-                            self.piece_bitboards[their_piece_bitboard_index],
-                            this_move.to_square,
-                        );
+                        self.piece_bitboards[their_piece_bitboard_index] =
+                            set_bit(self.piece_bitboards[their_piece_bitboard_index], to_square);
                         self.zobrist_hash ^= self.bitboard_constants.zobrist_table
-                            [their_piece_bitboard_index][this_move.to_square];
+                            [their_piece_bitboard_index][to_square];
+                        self.pawn_zobrist_hash ^= self.bitboard_constants.zobrist_table
+                            [their_piece_bitboard_index][to_square];
 
                         // Update their occupancies.
-                        self.occupancy_bitboards[their_occupancies_index] = set_bit(
-                            self.occupancy_bitboards[their_occupancies_index],
-                            this_move.to_square,
-                        );
+                        self.occupancy_bitboards[their_occupancies_index] =
+                            set_bit(self.occupancy_bitboards[their_occupancies_index], to_square);
                     }
                 }
 
                 // For all non-pawn captures...
                 _ => {
                     // Add that piece from the board.
-                    self.piece_bitboards[their_piece_bitboard_index] = set_bit(
-                        self.piece_bitboards[their_piece_bitboard_index],
-                        this_move.to_square,
-                    );
+                    self.piece_bitboards[their_piece_bitboard_index] =
+                        set_bit(self.piece_bitboards[their_piece_bitboard_index], to_square);
                     self.zobrist_hash ^= self.bitboard_constants.zobrist_table
-                        [their_piece_bitboard_index][this_move.to_square];
+                        [their_piece_bitboard_index][to_square];
 
                     // Update their occupancies.
-                    self.occupancy_bitboards[their_occupancies_index] = set_bit(
-                        self.occupancy_bitboards[their_occupancies_index],
-                        this_move.to_square,
-                    );
+                    self.occupancy_bitboards[their_occupancies_index] =
+                        set_bit(self.occupancy_bitboards[their_occupancies_index], to_square);
                 }
             }
         }
@@ -1069,47 +1254,34 @@ impl<'a> ChessGame<'a> {
         // Lastly, handle castling.
         match this_move.castle_side {
             None => (),
-            Some(side) => {
-                let king_from_position = this_move.from_square;
-                let rook_from_position = match side {
-                    CastleSides::Short => king_from_position + 3,
-                    CastleSides::Long => king_from_position - 4,
-                };
-                let rook_to_position = match side {
-                    CastleSides::Short => king_from_position + 1,
-                    CastleSides::Long => king_from_position - 1,
-                };
+            Some(_) => {
+                let rook_from_position = this_move.to_square;
+                let rook_to_position = this_move
+                    .castling_rook_destination_square()
+                    .expect("Castling moves have a rook destination.");
 
-                // Move our rook back.
+                // Move our rook back. Lift it first, in Chess960 it may not have moved at all.
                 let rook_bitboard_index =
                     our_piece_bitboard_offset + PieceType::Rook.bitboard_index();
+                self.piece_bitboards[rook_bitboard_index] =
+                    pop_bit(self.piece_bitboards[rook_bitboard_index], rook_to_position);
                 self.piece_bitboards[rook_bitboard_index] = set_bit(
                     self.piece_bitboards[rook_bitboard_index],
                     rook_from_position,
                 );
-                self.piece_bitboards[rook_bitboard_index] =
-                    pop_bit(self.piece_bitboards[rook_bitboard_index], rook_to_position);
 
                 self.zobrist_hash ^=
                     self.bitboard_constants.zobrist_table[rook_bitboard_index][rook_from_position];
                 self.zobrist_hash ^=
                     self.bitboard_constants.zobrist_table[rook_bitboard_index][rook_to_position];
 
-                // Update our occupancies.
-                self.occupancy_bitboards[our_occupancies_index] = set_bit(
-                    self.occupancy_bitboards[our_occupancies_index],
-                    rook_from_position,
-                );
-                self.occupancy_bitboards[our_occupancies_index] = pop_bit(
-                    self.occupancy_bitboards[our_occupancies_index],
-                    rook_to_position,
-                );
-
-                // Update global occupancies.
+                // In Chess960 the king and rook can land on each other's squares, so rebuild our occupancies.
+                let our_pieces =
+                    &self.piece_bitboards[our_piece_bitboard_offset..our_piece_bitboard_offset + 6];
+                self.occupancy_bitboards[our_occupancies_index] =
+                    our_pieces.iter().fold(0, |all, bitboard| all | bitboard);
                 self.occupancy_bitboards[2] =
-                    set_bit(self.occupancy_bitboards[2], rook_from_position);
-                self.occupancy_bitboards[2] =
-                    pop_bit(self.occupancy_bitboards[2], rook_to_position);
+                    self.occupancy_bitboards[0] | self.occupancy_bitboards[1];
             }
         }
 
@@ -1152,6 +1324,16 @@ impl<'a> ChessGame<'a> {
         self.white_to_move = !self.white_to_move;
         self.zobrist_hash ^= self.bitboard_constants.zobrist_to_move;
 
+        // Restore draw tracking.
+        if !self.white_to_move {
+            self.fullmove_number -= 1;
+        }
+        self.halfmove_clock = self
+            .halfmove_clock_history
+            .pop()
+            .expect("Unmade a move that was never made.");
+        self.zobrist_hash_history.pop();
+
         // Debugging!
         //self.debug_verify_board_state(this_move, debug_initial_game_state, "Unmake move");
     }
@@ -1188,12 +1370,12 @@ impl<'a> ChessGame<'a> {
             */
 
             // If moving to a square we are attacking, that is good. (destination is defended)
-            if self.is_square_attacked(m.to_square, &our_color) {
+            if self.is_square_attacked(m.destination_square(), &our_color) {
                 move_evaluation -= 2;
             }
 
             // If moving to a square they are attacking, that is bad. (destination is attacked)
-            if self.is_square_attacked(m.to_square, &their_color) {
+            if self.is_square_attacked(m.destination_square(), &their_color) {
                 move_evaluation += 2;
             }
 
@@ -1248,13 +1430,110 @@ impl<'a> ChessGame<'a> {
         return !self.is_king_attacked(&our_color);
     }
 
+    // The same position (same side to move, castling rights, and en-passant target) has appeared three times.
+    pub fn is_threefold_repetition(&self) -> bool {
+        // Only positions since the last capture or pawn move can repeat.
+        let positions_to_check = usize::min(
+            self.halfmove_clock as usize,
+            self.zobrist_hash_history.len(),
+        );
+
+        // Step back two plies at a time, so we only look at positions with the same side to move.
+        let mut occurrences = 1;
+        for hash in self
+            .zobrist_hash_history
+            .iter()
+            .rev()
+            .take(positions_to_check)
+            .skip(1)
+            .step_by(2)
+        {
+            if *hash == self.zobrist_hash {
+                occurrences += 1;
+            }
+        }
+
+        return occurrences >= 3;
+    }
+
+    // Fifty moves each, without a capture or pawn move. Checkmate on the last move still counts.
+    pub fn is_fifty_move_draw(&self) -> bool {
+        return self.halfmove_clock >= 100 && !self.is_checkmate();
+    }
+
+    // Neither side can possibly checkmate. K v K, K+minor v K, and K+B v K+B with bishops on the same color.
+    pub fn is_insufficient_material(&self) -> bool {
+        let white_offset = Color::White.piece_bitboard_offset();
+        let black_offset = Color::Black.piece_bitboard_offset();
+
+        // Any pawn, rook, or queen is enough.
+        for piece_type in [PieceType::Pawn, PieceType::Rook, PieceType::Queen] {
+            if self.piece_bitboards[white_offset + piece_type.bitboard_index()] != 0
+                || self.piece_bitboards[black_offset + piece_type.bitboard_index()] != 0
+            {
+                return false;
+            }
+        }
+
+        let knights = self.piece_bitboards[white_offset + PieceType::Knight.bitboard_index()]
+            | self.piece_bitboards[black_offset + PieceType::Knight.bitboard_index()];
+        let bishops = self.piece_bitboards[white_offset + PieceType::Bishop.bitboard_index()]
+            | self.piece_bitboards[black_offset + PieceType::Bishop.bitboard_index()];
+
+        // A single minor piece can't force mate.
+        if count_bits(knights) + count_bits(bishops) <= 1 {
+            return true;
+        }
+
+        // Only bishops left, and they all live on the same color.
+        return knights == 0
+            && (bishops & constants::LIGHT_SQUARES == 0
+                || bishops & !constants::LIGHT_SQUARES == 0);
+    }
+
+    // Any position that ends the game without a winner.
+    pub fn is_draw(&self) -> bool {
+        return self.is_stalemate()
+            || self.is_fifty_move_draw()
+            || self.is_threefold_repetition()
+            || self.is_insufficient_material();
+    }
+
+    // From `GAME_PHASE_MIDGAME` with every piece on the board, down to 0 with only kings and pawns.
+    pub fn game_phase(&self) -> i64 {
+        let mut phase: i64 = 0;
+        for (i, bitboard) in self.piece_bitboards.iter().enumerate() {
+            let piece = PieceType::bitboard_index_to_piece_type(i);
+            phase += piece.game_phase_weight() * count_bits(*bitboard) as i64;
+        }
+        return i64::min(phase, constants::GAME_PHASE_MIDGAME);
+    }
+
     pub fn evaluate_board(&self) -> i64 {
+        return self.evaluate(None);
+    }
+
+    // The same score as `evaluate_board`, with each term for each side written down. Slower, it skips the pawn hash
+    // table.
+    pub fn evaluate_board_traced(&self) -> EvaluationTrace {
+        let mut trace = EvaluationTrace::new(self.game_phase());
+        let total = self.evaluate(Some(&mut trace));
+        trace.total = total;
+        return trace;
+    }
+
+    // Fills in `trace` along the way, if there is one.
+    fn evaluate(&self, mut trace: Option<&mut EvaluationTrace>) -> i64 {
         // Variables shared by both functions.
         let mut square: usize;
         let mut occupancies: u64;
         let mut white_piece_value_total: i64 = 0;
         let mut black_piece_value_total: i64 = 0;
 
+        // Piece-square values, white minus black, kept apart until we know the phase.
+        let mut midgame_square_total: i64 = 0;
+        let mut endgame_square_total: i64 = 0;
+
         // Add up white pieces.
         occupancies = self.occupancy_bitboards[Color::White.occupancy_bitboard_index()];
         while occupancies != 0 {
@@ -1262,7 +1541,18 @@ impl<'a> ChessGame<'a> {
             let (piece_wrapped, _) = self.get_piece_at_square(square);
             let piece = piece_wrapped.expect("Not empty (white piece).");
             white_piece_value_total += piece.piece_base_value();
-            white_piece_value_total += piece.piece_happy_square_value(square, true);
+            let (midgame, endgame) = piece.piece_happy_square_values(square, true);
+            midgame_square_total += midgame;
+            endgame_square_total += endgame;
+            if let Some(t) = trace.as_deref_mut() {
+                t.add(
+                    Color::White,
+                    EvaluationTerm::Material,
+                    piece.piece_base_value(),
+                    piece.piece_base_value(),
+                );
+                t.add(Color::White, EvaluationTerm::PieceSquares, midgame, endgame);
+            }
             occupancies = pop_bit(occupancies, square)
         }
 
@@ -1283,12 +1573,337 @@ impl<'a> ChessGame<'a> {
 
             let piece = piece_wrapped.expect("Not empty (black piece).");
             black_piece_value_total += piece.piece_base_value();
-            black_piece_value_total += piece.piece_happy_square_value(square, false);
+            let (midgame, endgame) = piece.piece_happy_square_values(square, false);
+            midgame_square_total -= midgame;
+            endgame_square_total -= endgame;
+            if let Some(t) = trace.as_deref_mut() {
+                t.add(
+                    Color::Black,
+                    EvaluationTerm::Material,
+                    piece.piece_base_value(),
+                    piece.piece_base_value(),
+                );
+                t.add(Color::Black, EvaluationTerm::PieceSquares, midgame, endgame);
+            }
             occupancies = pop_bit(occupancies, square)
         }
 
+        let (midgame_pawn_total, endgame_pawn_total) = self.evaluate_pawns(trace.as_deref_mut());
+        let king_safety_total = self.evaluate_king_safety(trace.as_deref_mut());
+        let (midgame_activity_total, endgame_activity_total) = self.evaluate_piece_activity(trace);
+
+        // Slide from the midgame values to the endgame ones as pieces come off.
+        let phase = self.game_phase();
+        let midgame_total =
+            midgame_square_total + midgame_pawn_total + king_safety_total + midgame_activity_total;
+        let endgame_total = endgame_square_total + endgame_pawn_total + endgame_activity_total;
+        let positional_total = (midgame_total * phase
+            + endgame_total * (constants::GAME_PHASE_MIDGAME - phase))
+            / constants::GAME_PHASE_MIDGAME;
+
         // Add up the pieces, return the sum?
-        return white_piece_value_total - black_piece_value_total;
+        return white_piece_value_total - black_piece_value_total + positional_total;
+    }
+
+    // Mobility, bishop pair, rook files, knight outposts and trapped pieces, white minus black, as (midgame, endgame).
+    pub fn evaluate_piece_activity(&self, mut trace: Option<&mut EvaluationTrace>) -> (i64, i64) {
+        let w = &self.piece_activity_weights;
+        let c = self.bitboard_constants;
+        let pawns = [
+            self.piece_bitboards
+                [Color::White.piece_bitboard_offset() + PieceType::Pawn.bitboard_index()],
+            self.piece_bitboards
+                [Color::Black.piece_bitboard_offset() + PieceType::Pawn.bitboard_index()],
+        ];
+        let all_occupancies = self.occupancy_bitboards[2];
+        let mut midgame: i64 = 0;
+        let mut endgame: i64 = 0;
+
+        for (color, their_color, sign) in [
+            (Color::White, Color::Black, 1),
+            (Color::Black, Color::White, -1),
+        ] {
+            let ours = pawns[color.idx()];
+            let theirs = pawns[their_color.idx()];
+            let our_pieces = |piece: PieceType| {
+                self.piece_bitboards[color.piece_bitboard_offset() + piece.bitboard_index()]
+            };
+            let mut add = |term: EvaluationTerm, weight: Weight, count: i64| {
+                midgame += sign * count * weight.midgame;
+                endgame += sign * count * weight.endgame;
+                if let Some(t) = trace.as_deref_mut() {
+                    t.add(color, term, count * weight.midgame, count * weight.endgame);
+                }
+            };
+
+            let mut their_pawn_attacks: u64 = 0;
+            let mut remaining = theirs;
+            while remaining != 0 {
+                let square = get_lsb_index(remaining).expect("Guard clause.");
+                remaining = pop_bit(remaining, square);
+                their_pawn_attacks |= c.pawn_attacks[their_color.idx()][square];
+            }
+            let safe_squares =
+                !self.occupancy_bitboards[color.occupancy_bitboard_index()] & !their_pawn_attacks;
+
+            if count_bits(our_pieces(PieceType::Bishop)) >= 2 {
+                add(EvaluationTerm::BishopPair, w.bishop_pair, 1);
+            }
+
+            for (piece, weight, baseline) in [
+                (
+                    PieceType::Knight,
+                    w.knight_mobility,
+                    w.knight_mobility_baseline,
+                ),
+                (
+                    PieceType::Bishop,
+                    w.bishop_mobility,
+                    w.bishop_mobility_baseline,
+                ),
+                (PieceType::Rook, w.rook_mobility, w.rook_mobility_baseline),
+                (
+                    PieceType::Queen,
+                    w.queen_mobility,
+                    w.queen_mobility_baseline,
+                ),
+            ] {
+                let mut remaining = our_pieces(piece);
+                while remaining != 0 {
+                    let square = get_lsb_index(remaining).expect("Guard clause.");
+                    remaining = pop_bit(remaining, square);
+                    let file = square % 8;
+                    let rank = relative_rank(square, color);
+
+                    let attacks = match piece {
+                        PieceType::Knight => c.knight_attacks[square],
+                        PieceType::Bishop => self.get_bishop_attacks(square, all_occupancies),
+                        PieceType::Rook => self.get_rook_attacks(square, all_occupancies),
+                        _ => self.get_queen_attacks(square, all_occupancies),
+                    };
+                    let mobility = count_bits(attacks & safe_squares) as i64;
+                    add(EvaluationTerm::Mobility, weight, mobility - baseline);
+
+                    match piece {
+                        PieceType::Knight => {
+                            let is_defended = c.pawn_attacks[their_color.idx()][square] & ours != 0;
+                            // No pawn of theirs on a neighbouring file, further up, that could kick it out.
+                            let can_be_chased = c.passed_pawn_masks[color.idx()][square]
+                                & c.adjacent_file_masks[file]
+                                & theirs
+                                != 0;
+                            if (3..=5).contains(&rank) && is_defended && !can_be_chased {
+                                add(EvaluationTerm::KnightOutposts, w.knight_outpost, 1);
+                            }
+                        }
+                        PieceType::Bishop => {
+                            // a7/b6 and h7/g6 for white, a2/b3 and h2/g3 for black.
+                            let shut_in_by = match (color, square) {
+                                (Color::White, 8) => Some(17),
+                                (Color::White, 15) => Some(22),
+                                (Color::Black, 48) => Some(41),
+                                (Color::Black, 55) => Some(46),
+                                _ => None,
+                            };
+                            if shut_in_by
+                                .is_some_and(|pawn_square| get_bit(theirs, pawn_square) != 0)
+                            {
+                                add(EvaluationTerm::TrappedPieces, w.trapped_bishop, 1);
+                            }
+                        }
+                        PieceType::Rook => {
+                            let file_mask = c.file_masks[file];
+                            if (ours | theirs) & file_mask == 0 {
+                                add(EvaluationTerm::RookFiles, w.rook_open_file, 1);
+                            } else if ours & file_mask == 0 {
+                                add(EvaluationTerm::RookFiles, w.rook_semi_open_file, 1);
+                            }
+
+                            if rank == 6 {
+                                let their_king = self.piece_bitboards[their_color
+                                    .piece_bitboard_offset()
+                                    + PieceType::King.bitboard_index()];
+                                let seventh_rank = c.rank_masks[7 - square / 8];
+                                let back_rank = match color {
+                                    Color::White => c.rank_masks[7],
+                                    Color::Black => c.rank_masks[0],
+                                };
+                                if theirs & seventh_rank != 0 || their_king & back_rank != 0 {
+                                    add(EvaluationTerm::RookSeventhRank, w.rook_seventh_rank, 1);
+                                }
+                            }
+
+                            if rank == 0 && mobility <= w.trapped_rook_max_mobility {
+                                if let Some(king_square) =
+                                    get_lsb_index(our_pieces(PieceType::King))
+                                {
+                                    let king_file = king_square % 8;
+                                    let is_same_rank = relative_rank(king_square, color) == 0;
+                                    let (can_castle_short, can_castle_long) = match color {
+                                        Color::White => (
+                                            self.can_white_castle_short,
+                                            self.can_white_castle_long,
+                                        ),
+                                        Color::Black => (
+                                            self.can_black_castle_short,
+                                            self.can_black_castle_long,
+                                        ),
+                                    };
+                                    // The king stands between the rook and the middle of the board.
+                                    let is_boxed_in = (king_file >= 4
+                                        && file > king_file
+                                        && !can_castle_short)
+                                        || (king_file < 4 && file < king_file && !can_castle_long);
+                                    if is_same_rank && is_boxed_in {
+                                        add(EvaluationTerm::TrappedPieces, w.trapped_rook, 1);
+                                    }
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        return (midgame, endgame);
+    }
+
+    // Pawn shelter and pieces attacking the area around each king, white minus black. Midgame only.
+    pub fn evaluate_king_safety(&self, mut trace: Option<&mut EvaluationTrace>) -> i64 {
+        let pawns = [
+            self.piece_bitboards
+                [Color::White.piece_bitboard_offset() + PieceType::Pawn.bitboard_index()],
+            self.piece_bitboards
+                [Color::Black.piece_bitboard_offset() + PieceType::Pawn.bitboard_index()],
+        ];
+        let all_occupancies = self.occupancy_bitboards[2];
+        let mut total: i64 = 0;
+
+        for (color, their_color, sign) in [
+            (Color::White, Color::Black, 1),
+            (Color::Black, Color::White, -1),
+        ] {
+            let king_bitboard = self.piece_bitboards
+                [color.piece_bitboard_offset() + PieceType::King.bitboard_index()];
+            let king_square = match get_lsb_index(king_bitboard) {
+                Some(s) => s,
+                None => continue,
+            };
+
+            let shelter =
+                king_safety::pawn_shelter(king_square, color, pawns, self.bitboard_constants);
+
+            // Every piece of theirs that hits the king zone adds its weight for each square it hits.
+            let zone = king_safety::king_zone(king_square, color, self.bitboard_constants);
+            let mut attack_units: usize = 0;
+            let mut attackers: usize = 0;
+            for piece in [
+                PieceType::Pawn,
+                PieceType::Knight,
+                PieceType::Bishop,
+                PieceType::Rook,
+                PieceType::Queen,
+            ] {
+                let mut remaining = self.piece_bitboards
+                    [their_color.piece_bitboard_offset() + piece.bitboard_index()];
+                while remaining != 0 {
+                    let square = get_lsb_index(remaining).expect("Guard clause.");
+                    remaining = pop_bit(remaining, square);
+
+                    let attacks = match piece {
+                        PieceType::Pawn => {
+                            self.bitboard_constants.pawn_attacks[their_color.idx()][square]
+                        }
+                        PieceType::Knight => self.bitboard_constants.knight_attacks[square],
+                        PieceType::Bishop => self.get_bishop_attacks(square, all_occupancies),
+                        PieceType::Rook => self.get_rook_attacks(square, all_occupancies),
+                        PieceType::Queen => self.get_queen_attacks(square, all_occupancies),
+                        PieceType::King => 0,
+                    };
+                    let hits = count_bits(attacks & zone);
+                    if hits == 0 {
+                        continue;
+                    }
+
+                    attack_units += hits * piece.king_attack_weight();
+                    // Pawns add to an attack, but don't make one on their own.
+                    if !matches!(piece, PieceType::Pawn) {
+                        attackers += 1;
+                    }
+                }
+            }
+            let attack_penalty = king_safety::king_attack_penalty(attack_units, attackers);
+
+            if let Some(t) = trace.as_deref_mut() {
+                t.add(color, EvaluationTerm::KingShelter, shelter, 0);
+                t.add(color, EvaluationTerm::KingAttacks, attack_penalty, 0);
+            }
+            total += sign * (shelter + attack_penalty);
+        }
+
+        return total;
+    }
+
+    // Pawn structure, white minus black, as (midgame, endgame). The pawn-only part comes from the pawn hash table.
+    pub fn evaluate_pawns(&self, mut trace: Option<&mut EvaluationTrace>) -> (i64, i64) {
+        // A cached entry only has the total, the trace needs each kind of pawn.
+        let cached = match trace {
+            Some(_) => None,
+            None => self.pawn_hash_table.borrow().probe(self.pawn_zobrist_hash),
+        };
+
+        let entry = match cached {
+            Some(e) => e,
+            None => {
+                let pawns = [
+                    self.piece_bitboards
+                        [Color::White.piece_bitboard_offset() + PieceType::Pawn.bitboard_index()],
+                    self.piece_bitboards
+                        [Color::Black.piece_bitboard_offset() + PieceType::Pawn.bitboard_index()],
+                ];
+                let structure = PawnStructure::new(pawns, self.bitboard_constants);
+                let (midgame, endgame) = structure.score(trace.as_deref_mut());
+                let e = PawnHashEntry {
+                    pawn_zobrist_hash: self.pawn_zobrist_hash,
+                    midgame,
+                    endgame,
+                    passed_pawns: structure.passed,
+                };
+                self.pawn_hash_table.borrow_mut().store(e);
+                e
+            }
+        };
+
+        let mut midgame = entry.midgame;
+        let mut endgame = entry.endgame;
+
+        // Passed pawns with nothing in the way.
+        for (color, sign) in [(Color::White, 1), (Color::Black, -1)] {
+            let mut remaining = entry.passed_pawns[color.idx()];
+            while remaining != 0 {
+                let square = get_lsb_index(remaining).expect("Guard clause.");
+                remaining = pop_bit(remaining, square);
+                let path = self.bitboard_constants.front_span_masks[color.idx()][square];
+                if path & self.occupancy_bitboards[2] == 0 {
+                    let rank = relative_rank(square, color);
+                    let bonus_midgame = pawn_structure::FREE_PASSED_PAWN_BONUS_MIDGAME[rank];
+                    let bonus_endgame = pawn_structure::FREE_PASSED_PAWN_BONUS_ENDGAME[rank];
+                    midgame += sign * bonus_midgame;
+                    endgame += sign * bonus_endgame;
+                    if let Some(t) = trace.as_deref_mut() {
+                        t.add(
+                            color,
+                            EvaluationTerm::FreePassedPawns,
+                            bonus_midgame,
+                            bonus_endgame,
+                        );
+                    }
+                }
+            }
+        }
+
+        return (midgame, endgame);
     }
 
     // Meant for users/bots to pick a move, so it is populated with all the data we need.
@@ -1301,38 +1916,248 @@ impl<'a> ChessGame<'a> {
             }
         }
 
+        // Castling written as the king's move, `e1g1`. Not in Chess960, where that can be a normal king move.
+        if !self.is_chess960 {
+            for m in moves.iter() {
+                if m.castle_side.is_some()
+                    && m.from_square == this_move.from_square
+                    && m.destination_square() == this_move.to_square
+                {
+                    return Some(*m);
+                }
+            }
+        }
+
         return None;
     }
 
-    pub fn set_legal_moves(&mut self, moves: Option<Vec<Move>>) {
-        self.legal_moves.clear();
-        self.legal_moves = match moves {
-            Some(m) => m,
-            None => self.get_legal_moves(),
-        };
+    // True if a castling right can't be written as standard castling, the king isn't on e1/e8 or the rook isn't in the
+    // corner.
+    pub fn has_chess960_castling(&self) -> bool {
+        for (color, side, can_castle) in [
+            (
+                Color::White,
+                CastleSides::Short,
+                self.can_white_castle_short,
+            ),
+            (Color::White, CastleSides::Long, self.can_white_castle_long),
+            (
+                Color::Black,
+                CastleSides::Short,
+                self.can_black_castle_short,
+            ),
+            (Color::Black, CastleSides::Long, self.can_black_castle_long),
+        ] {
+            let index = side.castling_rights_index(&color);
+            let king_bitboard = self.piece_bitboards
+                [color.piece_bitboard_offset() + PieceType::King.bitboard_index()];
+            let standard_king_square = constants::STANDARD_CASTLING_ROOK_SQUARES[index] / 8 * 8 + 4;
+            if can_castle
+                && (self.castling_rook_squares[index]
+                    != constants::STANDARD_CASTLING_ROOK_SQUARES[index]
+                    || get_bit(king_bitboard, standard_king_square) == 0)
+            {
+                return true;
+            }
+        }
+
+        return false;
     }
 
-    pub fn get_legal_moves(&mut self) -> Vec<Move> {
-        let mut moves: Vec<Move> = vec![];
-        let mut possible_moves = self.get_psuedo_legal_moves();
-        let our_side: &Color;
-        let their_side: &Color;
+    // Long algebraic notation for UCI and Lichess. Chess960 castling is written as the king taking its own rook.
+    pub fn move_to_uci(&self, m: &Move) -> String {
+        if self.is_chess960 {
+            return m.move_to_str_chess960();
+        }
+        return m.move_to_str();
+    }
 
-        if self.white_to_move {
-            our_side = &Color::White;
-            their_side = &Color::Black;
+    // Standard Algebraic Notation for one of our legal moves. Ex: `Nbd7`, `exd6`, `O-O-O`, `e8=Q+`, `Qxf7#`.
+    // PGN needs plain SAN. For people reading chat or logs, en-passant captures can be marked like `exd6 e.p.`.
+    pub fn move_to_san(&mut self, m: &Move, mark_en_passant: bool) -> String {
+        let mut san = match m.castle_side {
+            Some(CastleSides::Short) => String::from("O-O"),
+            Some(CastleSides::Long) => String::from("O-O-O"),
+            None => {
+                let piece_type = m.from_piece_type.expect("Legal moves have a piece type.");
+                let from_coord = square_to_coord(m.from_square);
+                let mut s = String::new();
+
+                if piece_type == PieceType::Pawn {
+                    // Pawn captures start with the file they came from.
+                    if m.to_piece_type.is_some() {
+                        s.push_str(&from_coord[..1]);
+                    }
+                } else {
+                    s.push(piece_type.to_char(Color::White));
+
+                    // Another piece of the same type could go to the same square. Add the file, rank, or both.
+                    let others: Vec<&Move> = self
+                        .legal_moves
+                        .iter()
+                        .filter(|o| {
+                            o.from_piece_type == m.from_piece_type
+                                && o.to_square == m.to_square
+                                && o.from_square != m.from_square
+                        })
+                        .collect();
+                    if !others.is_empty() {
+                        let same_file = others
+                            .iter()
+                            .any(|o| o.from_square % 8 == m.from_square % 8);
+                        let same_rank = others
+                            .iter()
+                            .any(|o| o.from_square / 8 == m.from_square / 8);
+                        if !same_file {
+                            s.push_str(&from_coord[..1]);
+                        } else if !same_rank {
+                            s.push_str(&from_coord[1..]);
+                        } else {
+                            s.push_str(&from_coord);
+                        }
+                    }
+                }
+
+                if m.to_piece_type.is_some() {
+                    s.push('x');
+                }
+                s.push_str(&square_to_coord(m.to_square));
+
+                if let Some(promotion) = m.pawn_promoting_to {
+                    s.push('=');
+                    s.push(promotion.to_char(Color::White));
+                }
+                s
+            }
+        };
+
+        // Check and checkmate. We have to make the move to find out.
+        let legal_moves = self.legal_moves.clone();
+        self.make_move(m, true);
+        let their_color = if self.white_to_move {
+            Color::White
         } else {
-            our_side = &Color::Black;
-            their_side = &Color::White;
+            Color::Black
+        };
+        if self.is_king_attacked(&their_color) {
+            san.push(if self.legal_moves.is_empty() {
+                '#'
+            } else {
+                '+'
+            });
         }
+        self.unmake_move(m);
+        self.set_legal_moves(Some(legal_moves));
 
-        // Try the move, drop it if it's illegal.
-        for this_move in possible_moves.iter_mut() {
-            // How does this move impact castling rights?
-            this_move.removes_white_castling_rights_short = Some(false);
-            this_move.removes_white_castling_rights_long = Some(false);
-            this_move.removes_black_castling_rights_short = Some(false);
-            this_move.removes_black_castling_rights_long = Some(false);
+        if mark_en_passant && m.is_en_passant_capture {
+            san.push_str(" e.p.");
+        }
+
+        return san;
+    }
+
+    // Finds the legal move matching a SAN string, from a user or a PGN file. Ex: `Nbd7`, `exd6 e.p.`, `O-O-O`, `e8=Q+`.
+    // Check marks, move annotations (`!?`) and castling with zeros (`0-0`) are accepted, but not required.
+    pub fn san_to_move(&self, san: &str) -> Result<Move, String> {
+        let trimmed = san
+            .trim()
+            .trim_end_matches("e.p.")
+            .trim_end()
+            .trim_end_matches(['!', '?'])
+            .trim_end_matches(['+', '#']);
+
+        let castle_side = match trimmed {
+            "O-O" | "0-0" => Some(CastleSides::Short),
+            "O-O-O" | "0-0-0" => Some(CastleSides::Long),
+            _ => None,
+        };
+        if let Some(side) = castle_side {
+            for m in self.legal_moves.iter() {
+                match (m.castle_side, side) {
+                    (Some(CastleSides::Short), CastleSides::Short)
+                    | (Some(CastleSides::Long), CastleSides::Long) => return Ok(*m),
+                    _ => (),
+                }
+            }
+            return Err(format!("Castling is not legal here: `{san}`."));
+        }
+
+        // Promotion, `e8=Q` or `e8Q`.
+        let mut body = trimmed;
+        let mut pawn_promoting_to: Option<PieceType> = None;
+        if let Some(last) = body.chars().last() {
+            if "QRBN".contains(last) && body.len() > 2 {
+                pawn_promoting_to = Some(PieceType::char_to_piece_type(last)?);
+                body = body[..body.len() - 1].trim_end_matches('=');
+            }
+        }
+
+        // Piece letter. Pawns don't have one.
+        let from_piece_type = match body.chars().next() {
+            Some(c) if "KQRBN".contains(c) => {
+                body = &body[1..];
+                PieceType::char_to_piece_type(c)?
+            }
+            Some(_) => PieceType::Pawn,
+            None => return Err("Empty move.".to_string()),
+        };
+
+        // Destination is always the last two characters. Whatever is left (minus the `x`) disambiguates.
+        if body.len() < 2 || !body.is_char_boundary(body.len() - 2) {
+            return Err(format!("Unable to read move: `{san}`."));
+        }
+        let to_square = str_coord_to_square(&body[body.len() - 2..])?;
+        let disambiguation = body[..body.len() - 2].replace('x', "");
+
+        let matches: Vec<&Move> = self
+            .legal_moves
+            .iter()
+            .filter(|m| {
+                let from_coord = square_to_coord(m.from_square);
+                m.from_piece_type == Some(from_piece_type)
+                    && m.castle_side.is_none()
+                    && m.to_square == to_square
+                    && m.pawn_promoting_to == pawn_promoting_to
+                    && disambiguation.chars().all(|c| from_coord.contains(c))
+            })
+            .collect();
+
+        return match matches.len() {
+            1 => Ok(*matches[0]),
+            0 => Err(format!("No legal move matches `{san}`.")),
+            _ => Err(format!("Ambiguous move: `{san}`.")),
+        };
+    }
+
+    pub fn set_legal_moves(&mut self, moves: Option<Vec<Move>>) {
+        self.legal_moves.clear();
+        self.legal_moves = match moves {
+            Some(m) => m,
+            None => self.get_legal_moves(),
+        };
+    }
+
+    pub fn get_legal_moves(&mut self) -> Vec<Move> {
+        let mut moves: Vec<Move> = vec![];
+        let mut possible_moves = self.get_psuedo_legal_moves();
+        let our_side: &Color;
+        let their_side: &Color;
+
+        if self.white_to_move {
+            our_side = &Color::White;
+            their_side = &Color::Black;
+        } else {
+            our_side = &Color::Black;
+            their_side = &Color::White;
+        }
+
+        // Try the move, drop it if it's illegal.
+        for this_move in possible_moves.iter_mut() {
+            // How does this move impact castling rights?
+            this_move.removes_white_castling_rights_short = Some(false);
+            this_move.removes_white_castling_rights_long = Some(false);
+            this_move.removes_black_castling_rights_short = Some(false);
+            this_move.removes_black_castling_rights_long = Some(false);
 
             // Revoke our castling rights based on our move.
             match our_side {
@@ -1344,7 +2169,7 @@ impl<'a> ChessGame<'a> {
                         }
                         // If we are moving our rook from it's starting square, remove this right.
                         else if this_move.from_piece_type == Some(PieceType::Rook)
-                            && this_move.from_square == 63
+                            && this_move.from_square == self.castling_rook_squares[0]
                         {
                             this_move.removes_white_castling_rights_short = Some(true);
                         }
@@ -1357,7 +2182,7 @@ impl<'a> ChessGame<'a> {
                         }
                         // If we are moving our rook from it's starting square, remove this right.
                         else if this_move.from_piece_type == Some(PieceType::Rook)
-                            && this_move.from_square == 56
+                            && this_move.from_square == self.castling_rook_squares[1]
                         {
                             this_move.removes_white_castling_rights_long = Some(true);
                         }
@@ -1371,7 +2196,7 @@ impl<'a> ChessGame<'a> {
                         }
                         // If we are moving our rook from it's starting square, remove this right.
                         else if this_move.from_piece_type == Some(PieceType::Rook)
-                            && this_move.from_square == 7
+                            && this_move.from_square == self.castling_rook_squares[2]
                         {
                             this_move.removes_black_castling_rights_short = Some(true);
                         }
@@ -1384,7 +2209,7 @@ impl<'a> ChessGame<'a> {
                         }
                         // If we are moving our rook from it's starting square, remove this right.
                         else if this_move.from_piece_type == Some(PieceType::Rook)
-                            && this_move.from_square == 0
+                            && this_move.from_square == self.castling_rook_squares[3]
                         {
                             this_move.removes_black_castling_rights_long = Some(true);
                         }
@@ -1397,21 +2222,29 @@ impl<'a> ChessGame<'a> {
                 match their_side {
                     Color::Black => {
                         // If black can castle short, but we are capturing the rook on it's starting square; revoke.
-                        if self.can_black_castle_short && this_move.to_square == 7 {
+                        if self.can_black_castle_short
+                            && this_move.to_square == self.castling_rook_squares[2]
+                        {
                             this_move.removes_black_castling_rights_short = Some(true);
                         }
                         // If black can castle long, but we are capturing the rook on it's starting square; revoke.
-                        else if self.can_black_castle_long && this_move.to_square == 0 {
+                        else if self.can_black_castle_long
+                            && this_move.to_square == self.castling_rook_squares[3]
+                        {
                             this_move.removes_black_castling_rights_long = Some(true);
                         }
                     }
                     Color::White => {
                         // If white can castle short, but we are capturing the rook on it's starting square; revoke.
-                        if self.can_white_castle_short && this_move.to_square == 63 {
+                        if self.can_white_castle_short
+                            && this_move.to_square == self.castling_rook_squares[0]
+                        {
                             this_move.removes_white_castling_rights_short = Some(true);
                         }
                         // If white can castle long, but we are capturing the rook on it's starting square; revoke.
-                        else if self.can_white_castle_long && this_move.to_square == 56 {
+                        else if self.can_white_castle_long
+                            && this_move.to_square == self.castling_rook_squares[1]
+                        {
                             this_move.removes_white_castling_rights_long = Some(true);
                         }
                     }
@@ -1450,9 +2283,72 @@ impl<'a> ChessGame<'a> {
         return moves;
     }
 
+    // Will generate captures that put self in check. Sorted so the most valuable victims, taken by the cheapest attackers, come first.
+    pub fn get_psuedo_legal_captures(&self) -> Vec<Move> {
+        let mut captures: Vec<Move> = self
+            .get_psuedo_legal_moves()
+            .into_iter()
+            .filter(|m| m.to_piece_type.is_some())
+            .collect();
+
+        captures.sort_unstable_by_key(|m| {
+            let victim_value = m
+                .to_piece_type
+                .expect("Filtered to captures.")
+                .piece_base_value();
+            let attacker_value = m
+                .from_piece_type
+                .expect("Always populated.")
+                .piece_base_value();
+            return attacker_value - victim_value * 10;
+        });
+
+        return captures;
+    }
+
+    // Counts the leaf nodes of the legal move tree. Used to verify the move generator against known results.
+    pub fn perft(&mut self, depth: u32) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+
+        let moves = self.get_legal_moves();
+
+        // No need to make the moves at the last level, we just need to count them.
+        if depth == 1 {
+            return moves.len() as u64;
+        }
+
+        let mut nodes: u64 = 0;
+        for m in moves.iter() {
+            self.make_move(m, false);
+            nodes += self.perft(depth - 1);
+            self.unmake_move(m);
+        }
+
+        return nodes;
+    }
+
+    // Same as perft, but split by each root move. Makes it easy to find which move has the wrong count.
+    pub fn divide(&mut self, depth: u32) -> Vec<(Move, u64)> {
+        let mut results: Vec<(Move, u64)> = vec![];
+        if depth == 0 {
+            return results;
+        }
+
+        let moves = self.get_legal_moves();
+        for m in moves.iter() {
+            self.make_move(m, false);
+            results.push((*m, self.perft(depth - 1)));
+            self.unmake_move(m);
+        }
+
+        return results;
+    }
+
     pub fn print_legal_moves(&self) {
         for m in self.legal_moves.iter() {
-            print!("{} ", m.move_to_str());
+            print!("{} ", self.move_to_uci(m));
         }
         if self.legal_moves.len() == 0 {
             print!("There are no legal moves...");
@@ -1658,23 +2554,23 @@ impl<'a> ChessGame<'a> {
         let their_occupancies: u64;
         let can_castle_long: bool;
         let can_castle_short: bool;
-        let king_starting_square: usize;
+        let our_color: &Color;
         if self.white_to_move {
+            our_color = &Color::White;
             their_color = &Color::Black;
             their_occupancies = self.occupancy_bitboards[Color::Black.occupancy_bitboard_index()];
             bitboard = self.piece_bitboards
                 [Color::White.piece_bitboard_offset() + PieceType::King.bitboard_index()];
             can_castle_short = self.can_white_castle_short;
             can_castle_long = self.can_white_castle_long;
-            king_starting_square = 60;
         } else {
+            our_color = &Color::Black;
             their_color = &Color::White;
             their_occupancies = self.occupancy_bitboards[Color::White.occupancy_bitboard_index()];
             bitboard = self.piece_bitboards
                 [Color::Black.piece_bitboard_offset() + PieceType::King.bitboard_index()];
             can_castle_short = self.can_black_castle_short;
             can_castle_long = self.can_black_castle_long;
-            king_starting_square = 4;
         }
 
         if bitboard == 0 {
@@ -1733,66 +2629,57 @@ impl<'a> ChessGame<'a> {
             attacks = pop_bit(attacks, target_square);
         }
 
-        // Castling
-        if can_castle_short {
-            // 1. Make sure squares are empty.
-            let squares_should_be_empty =
-                set_bit(0, king_starting_square + 1) | set_bit(0, king_starting_square + 2);
+        // Castling. Not allowed out of check. The king can't have moved if we still have the right, so it is on its
+        // starting square. In Chess960 that, and the rooks' squares, can be anywhere on the back rank.
+        let is_king_in_check = self.is_square_attacked(source_square, their_color);
+        for (side, can_castle) in [
+            (CastleSides::Short, can_castle_short),
+            (CastleSides::Long, can_castle_long),
+        ] {
+            if !can_castle || is_king_in_check {
+                continue;
+            }
 
-            // 2. Make sure intermediary square is not attacked. Our final check for pins will handle checking the destination square.
-            let is_intermediary_square_attacked =
-                self.is_square_attacked(king_starting_square + 1, their_color);
+            let rook_square = self.castling_rook_squares[side.castling_rights_index(our_color)];
+            let back_rank_start = rook_square / 8 * 8;
+            let king_to_square = back_rank_start + side.king_to_file();
+            let rook_to_square = back_rank_start + side.rook_to_file();
 
-            // If both conditions are met, we can castle.
-            target_square = king_starting_square + 2;
-            if (squares_should_be_empty & self.occupancy_bitboards[2]) == 0
-                && !is_intermediary_square_attacked
-            {
-                moves.push(Move {
-                    from_square: source_square,
-                    from_piece_type: Some(PieceType::King),
-                    to_square: target_square,
-                    to_piece_type: None,
-                    is_check: None,
-                    last_en_passant_target_coord: self.en_passant_target,
-                    next_en_passant_target_coord: None,
-                    is_en_passant_capture: false,
-                    pawn_promoting_to: None,
-                    castle_side: Some(CastleSides::Short),
-                    removes_white_castling_rights_short: None,
-                    removes_white_castling_rights_long: None,
-                    removes_black_castling_rights_short: None,
-                    removes_black_castling_rights_long: None,
-                });
+            // Our rook must still be there, and the king on the same rank.
+            let our_rooks = self.piece_bitboards
+                [our_color.piece_bitboard_offset() + PieceType::Rook.bitboard_index()];
+            if get_bit(our_rooks, rook_square) == 0 || source_square / 8 != rook_square / 8 {
+                continue;
             }
-        }
 
-        if can_castle_long {
-            // 1. Make sure squares are empty.
-            let squares_should_be_empty = set_bit(0, king_starting_square - 1)
-                | set_bit(0, king_starting_square - 2)
-                | set_bit(0, king_starting_square - 3);
+            // 1. Make sure every square the king and rook cross, or land on, is empty. Except for the two of them.
+            let span = |a: usize, b: usize| (usize::min(a, b)..=usize::max(a, b)).fold(0, set_bit);
+            let squares_should_be_empty = (span(source_square, king_to_square)
+                | span(rook_square, rook_to_square))
+                & !set_bit(0, source_square)
+                & !set_bit(0, rook_square);
 
-            // 2. Make sure intermediary square is not attacked. Our final check for pins will handle checking the destination square.
-            let is_intermediary_square_attacked =
-                self.is_square_attacked(king_starting_square - 1, their_color);
+            // 2. Make sure the squares the king crosses are not attacked. Our final check for pins will handle checking
+            // the destination square.
+            let is_intermediary_square_attacked = (usize::min(source_square, king_to_square) + 1
+                ..usize::max(source_square, king_to_square))
+                .any(|square| self.is_square_attacked(square, their_color));
 
             // If both conditions are met, we can castle.
-            target_square = king_starting_square - 2;
             if (squares_should_be_empty & self.occupancy_bitboards[2]) == 0
                 && !is_intermediary_square_attacked
             {
                 moves.push(Move {
                     from_square: source_square,
                     from_piece_type: Some(PieceType::King),
-                    to_square: target_square,
+                    to_square: rook_square,
                     to_piece_type: None,
                     is_check: None,
                     last_en_passant_target_coord: self.en_passant_target,
                     next_en_passant_target_coord: None,
                     is_en_passant_capture: false,
                     pawn_promoting_to: None,
-                    castle_side: Some(CastleSides::Long),
+                    castle_side: Some(side),
                     removes_white_castling_rights_short: None,
                     removes_white_castling_rights_long: None,
                     removes_black_castling_rights_short: None,
@@ -2145,17 +3032,19 @@ impl<'a> ChessGame<'a> {
                 continue;
             }
 
-            let user_move_raw = match Move::str_to_move(&input) {
-                Ok(m) => m,
-                Err(msg) => {
-                    println!("{}", msg);
-                    continue;
-                }
+            // Take SAN (`Nf3`), or long algebraic (`g1f3`).
+            let user_move: Option<Move> = match self.san_to_move(&input) {
+                Ok(m) => Some(m),
+                Err(_) => match Move::str_to_move(&input) {
+                    // Grab the move from the game, it has more data (capture, en-passant, etc).
+                    Ok(m) => self.choose_move_from_legal_move(&m),
+                    Err(msg) => {
+                        println!("{}", msg);
+                        continue;
+                    }
+                },
             };
 
-            // Grab the move from the game, it has more data (capture, en-passant, etc).
-            let user_move: Option<Move> = self.choose_move_from_legal_move(&user_move_raw);
-
             match user_move {
                 Some(m) => self.make_move(&m, true),
                 None => {
@@ -2175,7 +3064,7 @@ impl<'a> ChessGame<'a> {
 
             // Let the bot make a move.
             let bot_move = self.get_bot_move();
-            println!("Bot is playing {}", bot_move.move_to_str());
+            println!("Bot is playing {}", self.move_to_san(&bot_move, true));
             self.make_move(&bot_move, true);
 
             // Temporary guard for oopsies...
@@ -2191,26 +3080,51 @@ impl<'a> ChessGame<'a> {
             panic!("Something has gone wrong, called get_bot_move when no legal moves were available...");
         }
 
+        if let Some(m) = self.get_book_move() {
+            return m;
+        }
+
         //let (evaluation, best_move) = self.minimax(4, std::i64::MIN, std::i64::MAX);
-        let (evaluation, best_move) = self.iterative_deepening_minimax();
+        let (_, best_move) = self.iterative_deepening_minimax();
 
         return best_move.unwrap();
     }
 
-    pub fn get_bot_move_debug(&mut self) -> Move {
+    // Also gives back the search evaluation, from white's side. `None` for book moves, which aren't searched.
+    pub fn get_bot_move_debug(&mut self) -> (Move, Option<i64>) {
         if self.legal_moves.len() == 0 {
             panic!("Something has gone wrong, called get_bot_move when no legal moves were available...");
         }
 
+        if let Some(m) = self.get_book_move() {
+            println!("Playing a book move.");
+            return (m, None);
+        }
+
         let (evaluation, best_move) = self.iterative_deepening_minimax();
 
-        println!("Best move evaluation {evaluation}");
+        println!("Best move evaluation {}", score::to_display_str(evaluation));
 
-        return best_move.unwrap();
+        return (best_move.unwrap(), Some(evaluation));
+    }
+
+    pub fn get_book_move(&mut self) -> Option<Move> {
+        // Polyglot books are for standard chess.
+        if self.is_chess960 {
+            return None;
+        }
+        let book = self.opening_book.clone()?;
+        return book.choose_move(self);
     }
 
     pub fn iterative_deepening_minimax(&mut self) -> (i64, Option<Move>) {
-        let start_time = std::time::SystemTime::now();
+        self.search_start_time = Instant::now();
+        self.search_nodes = 0;
+        self.search_depth = 0;
+        self.search_root_ply = self.zobrist_hash_history.len();
+        self.is_search_aborted = false;
+        self.transposition_table.new_search();
+
         let mut best_evaluation: i64 = 0;
         let mut best_move: Option<Move> = None;
         let mut search_depth = 1;
@@ -2220,17 +3134,37 @@ impl<'a> ChessGame<'a> {
             //println!("Currently searching depth {search_depth}");
 
             // Search at the current depth.
-            (best_evaluation, best_move) = self.minimax(search_depth, std::i64::MIN, std::i64::MAX);
+            let (evaluation, found_move) =
+                self.minimax(search_depth, -score::INFINITY, score::INFINITY);
 
-            // See how long that last operation took. If it was too long, stop the search.
-            let time_spent_ms = std::time::SystemTime::now()
-                .duration_since(start_time)
-                .expect("Time went back?")
-                .as_millis();
-            if time_spent_ms >= 5_000 {
+            // An aborted search only looked at part of the tree, keep the result from the last full depth.
+            if self.is_search_aborted {
                 break;
             }
 
+            best_evaluation = evaluation;
+            best_move = found_move;
+            self.search_depth = search_depth;
+
+            // See how long that last operation took. If it was too long, stop the search.
+            let time_spent_ms = self.search_start_time.elapsed().as_millis();
+
+            if self.print_uci_search_info {
+                self.print_uci_info(search_depth, best_evaluation, best_move, time_spent_ms);
+            }
+
+            if let Some(max_depth) = self.search_limits.max_depth {
+                if search_depth >= max_depth {
+                    break;
+                }
+            }
+
+            if let Some(soft_time_limit_ms) = self.search_limits.soft_time_limit_ms {
+                if time_spent_ms >= soft_time_limit_ms {
+                    break;
+                }
+            }
+
             //println!("Currently spent {time_spent_ms}ms");
 
             // Otherwise, increase our depth and continue!
@@ -2239,21 +3173,81 @@ impl<'a> ChessGame<'a> {
 
         //println!("This search reached depth {search_depth}");
 
+        // If we were stopped before finishing the first depth, any legal move is better than none.
+        if best_move.is_none() {
+            best_move = self.legal_moves.first().copied();
+        }
+
         // Return the best moves we found.
         return (best_evaluation, best_move);
     }
 
+    // Half moves made since the start of the current search.
+    fn search_ply(&self) -> u32 {
+        return self
+            .zobrist_hash_history
+            .len()
+            .saturating_sub(self.search_root_ply) as u32;
+    }
+
+    // Checked at every node, so the search can be stopped part way through a depth.
+    fn should_stop_search(&self) -> bool {
+        if self.search_limits.stop_flag.load(Ordering::Relaxed) {
+            return true;
+        }
+
+        if let Some(max_nodes) = self.search_limits.max_nodes {
+            if self.search_nodes >= max_nodes {
+                return true;
+            }
+        }
+
+        // Reading the clock is slow compared to everything else here, only do it every so often.
+        if self.search_nodes % 1024 == 0 {
+            if let Some(hard_time_limit_ms) = self.search_limits.hard_time_limit_ms {
+                if self.search_start_time.elapsed().as_millis() >= hard_time_limit_ms {
+                    return true;
+                }
+            }
+        }
+
+        return false;
+    }
+
+    fn print_uci_info(
+        &self,
+        depth: u32,
+        evaluation: i64,
+        best_move: Option<Move>,
+        time_spent_ms: u128,
+    ) {
+        let pv = match best_move {
+            Some(m) => self.move_to_uci(&m),
+            None => String::from("0000"),
+        };
+
+        println!(
+            "info depth {depth} score {} nodes {} time {time_spent_ms} pv {pv}",
+            score::to_uci_str(evaluation, self.white_to_move),
+            self.search_nodes
+        );
+    }
+
     pub fn minimax(&mut self, depth: u32, mut alpha: i64, mut beta: i64) -> (i64, Option<Move>) {
         self.debug_minimax_calls += 1;
+        self.search_nodes += 1;
+
+        // Unwind as fast as possible once the search is stopped. Callers throw this result away.
+        if self.is_search_aborted || self.should_stop_search() {
+            self.is_search_aborted = true;
+            return (0, None);
+        }
 
-        let zobrist_hash_index = self.zobrist_hash % 10_000;
+        let cached_entry = self.transposition_table.probe(self.zobrist_hash);
 
-        if self.transposition_table.contains_key(&zobrist_hash_index) {
-            let entry = self
-                .transposition_table
-                .get(&zobrist_hash_index)
-                .expect("Guard clause filters this.");
-            if entry.depth >= depth && self.zobrist_hash == entry.zobrist_hash {
+        if let Some(mut entry) = cached_entry {
+            entry.evaluation = score::from_transposition_table(entry.evaluation, self.search_ply());
+            if entry.depth >= depth {
                 //println!("{}Cache hit at good depth!", debug_depth_to_tabs(depth));
                 match entry.node_type {
                     TranspositionTableNodeType::Exact => {
@@ -2276,13 +3270,13 @@ impl<'a> ChessGame<'a> {
         if self.legal_moves.len() == 0 {
             if self.white_to_move {
                 if self.is_king_attacked(&Color::White) {
-                    return (std::i64::MIN, None);
+                    return (score::checkmated(true, self.search_ply()), None);
                 } else {
                     return (0, None);
                 }
             } else {
                 if self.is_king_attacked(&Color::Black) {
-                    return (std::i64::MAX, None);
+                    return (score::checkmated(false, self.search_ply()), None);
                 } else {
                     return (0, None);
                 }
@@ -2290,7 +3284,7 @@ impl<'a> ChessGame<'a> {
         }
 
         if depth == 0 {
-            return (self.evaluate_board(), None);
+            return (self.quiescence(alpha, beta), None);
         }
 
         // Clone legal moves? Bad?
@@ -2301,13 +3295,22 @@ impl<'a> ChessGame<'a> {
         let mut temp_evaluation: i64;
 
         if self.white_to_move {
-            best_evaluation = std::i64::MIN;
+            best_evaluation = -score::INFINITY;
             for legal_move in temp_legal_move_clone.iter() {
                 // Make the move.
                 self.make_move(legal_move, true);
 
-                // Get the evaluation of that position.
-                (temp_evaluation, _) = self.minimax(depth - 1, alpha, beta);
+                // Get the evaluation of that position. Draws by rule depend on how we got here, so check them before the transposition table can.
+                if self.is_draw() {
+                    temp_evaluation = 0;
+                } else {
+                    (temp_evaluation, _) = self.minimax(depth - 1, alpha, beta);
+                }
+
+                if self.is_search_aborted {
+                    self.unmake_move(legal_move);
+                    break;
+                }
 
                 // See if it's better.
                 if temp_evaluation > best_evaluation {
@@ -2326,13 +3329,22 @@ impl<'a> ChessGame<'a> {
                 alpha = i64::max(alpha, best_evaluation);
             }
         } else {
-            best_evaluation = std::i64::MAX;
+            best_evaluation = score::INFINITY;
             for legal_move in temp_legal_move_clone.iter() {
                 // Make the move.
                 self.make_move(legal_move, true);
 
-                // Get the evaluation of that position.
-                (temp_evaluation, _) = self.minimax(depth - 1, alpha, beta);
+                // Get the evaluation of that position. Draws by rule depend on how we got here, so check them before the transposition table can.
+                if self.is_draw() {
+                    temp_evaluation = 0;
+                } else {
+                    (temp_evaluation, _) = self.minimax(depth - 1, alpha, beta);
+                }
+
+                if self.is_search_aborted {
+                    self.unmake_move(legal_move);
+                    break;
+                }
 
                 // See if it's better.
                 if temp_evaluation < best_evaluation {
@@ -2355,6 +3367,11 @@ impl<'a> ChessGame<'a> {
         // Restore legal moves before exiting.
         self.set_legal_moves(Some(temp_legal_move_clone));
 
+        // Don't pollute the transposition table with a partial search.
+        if self.is_search_aborted {
+            return (best_evaluation, best_move);
+        }
+
         // Find out transposition table node type.
         let node: TranspositionTableNodeType;
         if best_evaluation <= alpha {
@@ -2366,26 +3383,80 @@ impl<'a> ChessGame<'a> {
         }
 
         // Update transposition table.
-        let time = std::time::SystemTime::now();
-        self.transposition_table.insert(
-            zobrist_hash_index,
-            TranspositionTableEntry {
-                zobrist_hash: self.zobrist_hash,
-                best_move: best_move,
-                depth: depth,
-                node_type: node,
-                evaluation: best_evaluation,
-                age: time
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .expect("Time went back?")
-                    .as_millis(),
-            },
-        );
+        self.transposition_table.store(TranspositionTableEntry {
+            zobrist_hash: self.zobrist_hash,
+            best_move: best_move,
+            depth: depth,
+            node_type: node,
+            evaluation: score::to_transposition_table(best_evaluation, self.search_ply()),
+            generation: 0,
+        });
         //println!("{}Set data in transposition table. Minimax call: {}", debug_depth_to_tabs(depth), self.debug_minimax_calls);
 
         return (best_evaluation, best_move);
     }
 
+    // Keeps searching captures past the depth limit, so we never evaluate a position in the middle of a trade.
+    pub fn quiescence(&mut self, mut alpha: i64, mut beta: i64) -> i64 {
+        self.search_nodes += 1;
+
+        if self.is_search_aborted || self.should_stop_search() {
+            self.is_search_aborted = true;
+            return 0;
+        }
+
+        // Stand pat. The side to move can always decline to capture, so the static evaluation is a bound.
+        let stand_pat = self.evaluate_board();
+        let our_color: Color;
+        if self.white_to_move {
+            if stand_pat >= beta {
+                return stand_pat;
+            }
+            alpha = i64::max(alpha, stand_pat);
+            our_color = Color::White;
+        } else {
+            if stand_pat <= alpha {
+                return stand_pat;
+            }
+            beta = i64::min(beta, stand_pat);
+            our_color = Color::Black;
+        }
+
+        let mut best_evaluation = stand_pat;
+        for capture in self.get_psuedo_legal_captures().iter() {
+            self.make_move(capture, false);
+
+            // Psuedo legal, skip captures that leave our king in check.
+            if self.is_king_attacked(&our_color) {
+                self.unmake_move(capture);
+                continue;
+            }
+
+            let evaluation = self.quiescence(alpha, beta);
+            self.unmake_move(capture);
+
+            if self.is_search_aborted {
+                return 0;
+            }
+
+            if self.white_to_move {
+                if evaluation >= beta {
+                    return evaluation;
+                }
+                best_evaluation = i64::max(best_evaluation, evaluation);
+                alpha = i64::max(alpha, evaluation);
+            } else {
+                if evaluation <= alpha {
+                    return evaluation;
+                }
+                best_evaluation = i64::min(best_evaluation, evaluation);
+                beta = i64::min(beta, evaluation);
+            }
+        }
+
+        return best_evaluation;
+    }
+
     pub fn minimax_debug(
         &mut self,
         depth: u32,
@@ -2398,13 +3469,13 @@ impl<'a> ChessGame<'a> {
         if self.legal_moves.len() == 0 {
             if self.white_to_move {
                 if self.is_king_attacked(&Color::White) {
-                    return (std::i64::MIN, None);
+                    return (score::checkmated(true, self.search_ply()), None);
                 } else {
                     return (0, None);
                 }
             } else {
                 if self.is_king_attacked(&Color::Black) {
-                    return (std::i64::MAX, None);
+                    return (score::checkmated(false, self.search_ply()), None);
                 } else {
                     return (0, None);
                 }
@@ -2412,7 +3483,7 @@ impl<'a> ChessGame<'a> {
         }
 
         if depth == 0 {
-            return (self.evaluate_board(), None);
+            return (self.quiescence(alpha, beta), None);
         }
 
         // Clone legal moves? Bad?
@@ -2423,7 +3494,7 @@ impl<'a> ChessGame<'a> {
         let mut temp_evaluation: i64;
 
         if self.white_to_move {
-            best_evaluation = std::i64::MIN;
+            best_evaluation = -score::INFINITY;
             for legal_move in temp_legal_move_clone.iter() {
                 // Make the move.
                 self.make_move(legal_move, true);
@@ -2459,7 +3530,7 @@ impl<'a> ChessGame<'a> {
                 alpha = i64::max(alpha, best_evaluation);
             }
         } else {
-            best_evaluation = std::i64::MAX;
+            best_evaluation = score::INFINITY;
             for legal_move in temp_legal_move_clone.iter() {
                 // Make the move.
                 self.make_move(legal_move, true);
@@ -2505,10 +3576,14 @@ impl<'a> ChessGame<'a> {
     pub fn print_debug_game_state_str(&self) {
         self.print_board();
         println!("White to move?: {}", self.white_to_move);
+        println!("Halfmove clock: {}", self.halfmove_clock);
+        println!("Fullmove number: {}", self.fullmove_number);
         println!("Zobrist Hash: {}", self.zobrist_hash);
+        let transposition_table = &self.transposition_table;
         println!(
-            "Transposition Table Size: {}",
-            self.transposition_table.len()
+            "Transposition Table Size: {} / {}",
+            transposition_table.used_entries(),
+            transposition_table.capacity()
         );
 
         print!("En-Passant Target Square: ");
@@ -2529,3 +3604,527 @@ impl<'a> ChessGame<'a> {
         println!("FEN: {}", self.export_fen());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn game_from_fen<'a>(c: &'a Constants, fen: &str) -> ChessGame<'a> {
+        let mut game = ChessGame::new(c);
+        game.import_fen(fen).unwrap();
+        game.set_legal_moves(None);
+        return game;
+    }
+
+    // Plays a move given in long algebraic notation. Ex: `g1f3`.
+    fn play_uci(game: &mut ChessGame, uci: &str) -> Move {
+        let m = game
+            .get_legal_moves()
+            .into_iter()
+            .find(|m| m.move_to_str() == uci)
+            .unwrap_or_else(|| panic!("{uci} is not legal in {}", game.export_fen()));
+        game.make_move(&m, true);
+        return m;
+    }
+
+    fn search_to_depth(game: &mut ChessGame, depth: u32) -> (i64, Option<Move>) {
+        game.search_limits = SearchLimits {
+            max_depth: Some(depth),
+            ..SearchLimits::infinite()
+        };
+        return game.iterative_deepening_minimax();
+    }
+
+    #[test]
+    fn mate_scores_from_search() {
+        let c = Constants::new();
+
+        // Back rank mate, for either side. Always from white's point of view.
+        let mut white_mates = game_from_fen(&c, "6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1");
+        let (mate_in_one, best_move) = search_to_depth(&mut white_mates, 3);
+        assert_eq!(mate_in_one, score::MATE_SCORE - 1);
+        assert_eq!(best_move.unwrap().move_to_str(), "a1a8");
+
+        let mut black_mates = game_from_fen(&c, "r5k1/8/8/8/8/8/5PPP/6K1 b - - 0 1");
+        assert_eq!(
+            search_to_depth(&mut black_mates, 3).0,
+            -(score::MATE_SCORE - 1)
+        );
+
+        // Morphy: 1. Ra6 and 2. b7# whatever black does. Longer mates score lower.
+        let mut mate_in_two = game_from_fen(&c, "kbK5/pp6/1P6/8/8/8/8/R7 w - - 0 1");
+        let (evaluation, best_move) = search_to_depth(&mut mate_in_two, 4);
+        assert_eq!(evaluation, score::MATE_SCORE - 3);
+        assert!(evaluation < mate_in_one);
+        assert_eq!(best_move.unwrap().move_to_str(), "a1a6");
+    }
+
+    #[test]
+    fn threefold_repetition() {
+        let c = Constants::new();
+        let mut game = game_from_fen(&c, constants::INITIAL_GAME_STATE_FEN);
+
+        // The knights go out and back. The starting position is seen again after 4 plies, and a third time after 8.
+        for uci in ["g1f3", "g8f6", "f3g1", "f6g8"] {
+            play_uci(&mut game, uci);
+        }
+        assert!(!game.is_threefold_repetition());
+        for uci in ["g1f3", "g8f6", "f3g1"] {
+            play_uci(&mut game, uci);
+            assert!(!game.is_threefold_repetition());
+        }
+        play_uci(&mut game, "f6g8");
+        assert!(game.is_threefold_repetition());
+        assert!(game.is_draw());
+    }
+
+    #[test]
+    fn fifty_move_draw_and_unmake() {
+        let c = Constants::new();
+        let fen = "4k3/8/8/8/8/8/n7/R3K3 w - - 99 80";
+        let mut game = game_from_fen(&c, fen);
+        assert!(!game.is_fifty_move_draw());
+
+        // One more quiet move makes it a hundred plies.
+        let quiet = play_uci(&mut game, "a1b1");
+        assert_eq!(game.halfmove_clock, 100);
+        assert_eq!(game.zobrist_hash_history.len(), 1);
+        assert!(game.is_fifty_move_draw());
+
+        game.unmake_move(&quiet);
+        assert_eq!(game.halfmove_clock, 99);
+        assert_eq!(game.zobrist_hash_history.len(), 0);
+        assert_eq!(game.halfmove_clock_history.len(), 0);
+
+        // A capture starts the count again. Unmaking it brings the old count back.
+        let capture = play_uci(&mut game, "a1a2");
+        assert_eq!(game.halfmove_clock, 0);
+        assert!(!game.is_fifty_move_draw());
+        game.unmake_move(&capture);
+        assert_eq!(game.halfmove_clock, 99);
+        assert_eq!(game.export_fen(), fen);
+    }
+
+    #[test]
+    fn insufficient_material() {
+        let c = Constants::new();
+        for (fen, expected) in [
+            ("4k3/8/8/8/8/8/8/4K3 w - - 0 1", true),
+            ("4k3/8/8/8/8/8/8/2B1K3 w - - 0 1", true),
+            ("4k3/8/8/8/8/8/8/1N2K3 w - - 0 1", true),
+            // c1 and f8 are both dark squares, c8 is a light one.
+            ("4kb2/8/8/8/8/8/8/2B1K3 w - - 0 1", true),
+            ("2b1k3/8/8/8/8/8/8/2B1K3 w - - 0 1", false),
+            ("4k3/8/8/8/8/8/8/1NB1K3 w - - 0 1", false),
+            ("4k3/8/8/8/8/8/8/R3K3 w - - 0 1", false),
+            ("4k3/7p/8/8/8/8/8/4K3 w - - 0 1", false),
+        ] {
+            assert_eq!(
+                game_from_fen(&c, fen).is_insufficient_material(),
+                expected,
+                "{fen}"
+            );
+        }
+    }
+
+    #[test]
+    fn quiescence_sees_the_recapture() {
+        let c = Constants::new();
+
+        // The knight on d5 is defended by the pawn on e6. Qxd5 looks like a free knight until exd5.
+        let fen = "4k3/8/4p3/3n4/8/8/8/3QK3 w - - 0 1";
+        let mut game = game_from_fen(&c, fen);
+        let before = game.evaluate_board();
+
+        let grab = play_uci(&mut game, "d1d5");
+        assert!(
+            game.evaluate_board() > before,
+            "A depth 1 search without quiescence takes the knight."
+        );
+        assert!(game.quiescence(-score::INFINITY, score::INFINITY) < before);
+        game.unmake_move(&grab);
+        game.set_legal_moves(None);
+
+        let (_, best_move) = search_to_depth(&mut game, 1);
+        assert_ne!(best_move.unwrap().move_to_str(), "d1d5");
+    }
+
+    #[test]
+    fn psuedo_legal_captures() {
+        let c = Constants::new();
+
+        // exd6 en passant, Rxa8, and both castles available. Only the first two are captures.
+        let game = game_from_fen(&c, "r3k3/8/8/3pP3/8/8/8/R3K2R w KQq d6 0 1");
+        let captures = game.get_psuedo_legal_captures();
+        let captures_str: Vec<String> = captures.iter().map(|m| m.move_to_str()).collect();
+
+        assert_eq!(captures.len(), 2, "{captures_str:?}");
+        assert!(captures
+            .iter()
+            .any(|m| m.is_en_passant_capture && m.move_to_str() == "e5d6"));
+        assert!(captures_str.contains(&"a1a8".to_string()));
+        assert!(captures
+            .iter()
+            .all(|m| m.castle_side.is_none() && m.to_piece_type.is_some()));
+
+        // Castling is still in the full move list.
+        assert!(game
+            .get_psuedo_legal_moves()
+            .iter()
+            .any(|m| m.castle_side.is_some()));
+    }
+
+    #[test]
+    fn san_round_trips_every_legal_move() {
+        let c = Constants::new();
+        let positions = [
+            constants::INITIAL_GAME_STATE_FEN,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+        ];
+
+        for fen in positions.iter() {
+            let mut game = game_from_fen(&c, fen);
+            for m in game.legal_moves.clone().iter() {
+                let san = game.move_to_san(m, false);
+                assert_eq!(game.san_to_move(&san).unwrap(), *m, "{san} in {fen}");
+            }
+        }
+    }
+
+    #[test]
+    fn san_formatting() {
+        let c = Constants::new();
+
+        // Knights on b8 and f6 can both reach d7. Rooks on a1 and a5 can both reach a3.
+        let mut game = game_from_fen(&c, "1n2k3/8/5n2/R2pP3/8/8/8/R3K3 w Q d6 0 1");
+        let cases = [
+            ("b8d7", "Nbd7"),
+            ("a1a3", "R1a3"),
+            ("e5d6", "exd6 e.p."),
+            ("e1c1", "O-O-O"),
+        ];
+        for (long, san) in cases.iter() {
+            game.white_to_move = !long.starts_with('b');
+            game.set_legal_moves(None);
+            let m = game
+                .choose_move_from_legal_move(&Move::str_to_move(long).unwrap())
+                .unwrap();
+            assert_eq!(game.move_to_san(&m, true), *san);
+        }
+
+        let mut game = game_from_fen(&c, "4k3/P7/8/8/8/8/8/4K3 w - - 0 1");
+        let m = game.san_to_move("a8=Q+").unwrap();
+        assert_eq!(game.move_to_san(&m, false), "a8=Q+");
+        assert!(game.san_to_move("a8").is_err());
+
+        let mut game = game_from_fen(
+            &c,
+            "r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4",
+        );
+        let m = game.san_to_move("Qxf7").unwrap();
+        assert_eq!(game.move_to_san(&m, false), "Qxf7#");
+        assert!(game.san_to_move("Qf7").is_ok());
+        assert!(game.san_to_move("Ke3").is_err());
+    }
+
+    #[test]
+    fn tapered_evaluation() {
+        let c = Constants::new();
+        assert_eq!(
+            game_from_fen(&c, constants::INITIAL_GAME_STATE_FEN).game_phase(),
+            constants::GAME_PHASE_MIDGAME
+        );
+        assert_eq!(
+            game_from_fen(&c, "r3k3/8/8/8/8/8/8/4K2Q w - - 0 1").game_phase(),
+            6
+        );
+
+        // Just kings and pawns. The king belongs in the middle now, not in the corner.
+        let central_king = game_from_fen(&c, "8/p7/8/8/3K4/8/P7/7k w - - 0 1");
+        let corner_king = game_from_fen(&c, "8/p7/8/8/8/8/P7/K6k w - - 0 1");
+        assert_eq!(central_king.game_phase(), 0);
+        assert!(central_king.evaluate_board() > corner_king.evaluate_board());
+
+        // With every piece on the board, the castled king is the happy one.
+        let castled = game_from_fen(
+            &c,
+            "r1bq1rk1/pppp1ppp/2n2n2/2b1p3/2B1P3/2N2N2/PPPP1PPP/R1BQ1RK1 w - - 0 1",
+        );
+        let walked = game_from_fen(
+            &c,
+            "r1bq1rk1/pppp1ppp/2n2n2/2b1p3/2B1P3/2NK1N2/PPPP1PPP/R1BQ3R w - - 0 1",
+        );
+        assert!(castled.evaluate_board() > walked.evaluate_board());
+
+        // Mirrored positions score the same for both sides.
+        let white = game_from_fen(&c, "4k3/8/8/8/8/2N5/1P6/4K3 w - - 0 1");
+        let black = game_from_fen(&c, "4k3/1p6/2n5/8/8/8/8/4K3 w - - 0 1");
+        assert_eq!(white.evaluate_board(), -black.evaluate_board());
+    }
+
+    // Walks every line `depth` plies deep, checking the pawn key against one made from scratch in `fresh`.
+    fn check_pawn_zobrist_hash(game: &mut ChessGame, fresh: &mut ChessGame, depth: u32) {
+        fresh.import_fen(&game.export_fen()).unwrap();
+        assert_eq!(
+            game.pawn_zobrist_hash,
+            fresh.pawn_zobrist_hash,
+            "{}",
+            game.export_fen()
+        );
+        if depth == 0 {
+            return;
+        }
+
+        for m in game.get_legal_moves().iter() {
+            let before = game.pawn_zobrist_hash;
+            game.make_move(m, true);
+            check_pawn_zobrist_hash(game, fresh, depth - 1);
+            game.unmake_move(m);
+            assert_eq!(game.pawn_zobrist_hash, before);
+        }
+    }
+
+    #[test]
+    fn pawn_structure_evaluation() {
+        let c = Constants::new();
+
+        // En passant, promotions with and without a capture, and plenty of pawn captures.
+        let mut fresh = ChessGame::new(&c);
+        for fen in [
+            "4k3/1P6/8/3pP3/8/8/6p1/4K2R w K d6 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+        ] {
+            check_pawn_zobrist_hash(&mut game_from_fen(&c, fen), &mut fresh, 3);
+        }
+
+        // Same pawns, same key, whatever else is on the board.
+        let rooks = game_from_fen(&c, "4k3/pp6/8/8/8/8/PP6/R3K3 w - - 0 1");
+        let knights = game_from_fen(&c, "4k3/pp6/8/8/8/8/PP6/1N2K3 w - - 0 1");
+        assert_eq!(rooks.pawn_zobrist_hash, knights.pawn_zobrist_hash);
+
+        // A passed pawn is worth more with nothing in front of it.
+        let free = game_from_fen(&c, "4k3/8/8/8/3P4/8/8/4K3 w - - 0 1");
+        let blocked = game_from_fen(&c, "4k3/8/3n4/8/3P4/8/8/4K3 w - - 0 1");
+        let (free_midgame, _) = free.evaluate_pawns(None);
+        let (blocked_midgame, _) = blocked.evaluate_pawns(None);
+        assert!(free_midgame > blocked_midgame);
+
+        // Doubled, isolated pawns are worse than a healthy pair.
+        let doubled = game_from_fen(&c, "4k3/8/8/8/8/3P4/3P4/4K3 w - - 0 1");
+        let connected = game_from_fen(&c, "4k3/8/8/8/8/3P4/4P3/4K3 w - - 0 1");
+        assert!(connected.evaluate_pawns(None).1 > doubled.evaluate_pawns(None).1);
+
+        // Mirrored positions still score the same for both sides.
+        let white = game_from_fen(&c, "4k3/8/8/8/3P4/2P5/P7/4K3 w - - 0 1");
+        let black = game_from_fen(&c, "4k3/p7/2p5/3p4/8/8/8/4K3 w - - 0 1");
+        assert_eq!(white.evaluate_board(), -black.evaluate_board());
+    }
+
+    #[test]
+    fn king_safety_evaluation() {
+        let c = Constants::new();
+
+        // The same queen and knight, next to the king or far from it.
+        let attacked = game_from_fen(&c, "6k1/5ppp/8/8/6nq/8/5PPP/6K1 w - - 0 1");
+        let distant = game_from_fen(&c, "6k1/5ppp/8/q7/n7/8/5PPP/6K1 w - - 0 1");
+        assert!(attacked.evaluate_king_safety(None) < distant.evaluate_king_safety(None));
+
+        // A king with its pawns pushed away from it is worse off.
+        let sheltered = game_from_fen(&c, "6k1/5ppp/8/8/8/8/5PPP/6K1 w - - 0 1");
+        let exposed = game_from_fen(&c, "6k1/5ppp/8/8/5PPP/8/8/6K1 w - - 0 1");
+        assert!(sheltered.evaluate_king_safety(None) > exposed.evaluate_king_safety(None));
+
+        let mirrored = game_from_fen(&c, "6k1/5ppp/8/6NQ/8/8/5PPP/6K1 w - - 0 1");
+        assert_eq!(
+            attacked.evaluate_king_safety(None),
+            -mirrored.evaluate_king_safety(None)
+        );
+        assert_eq!(attacked.evaluate_board(), -mirrored.evaluate_board());
+    }
+
+    // What a single activity term adds, found by zeroing it in `without`.
+    fn activity_term(c: &Constants, fen: &str, without: PieceActivityWeights) -> (i64, i64) {
+        let mut game = game_from_fen(c, fen);
+        let (midgame, endgame) = game.evaluate_piece_activity(None);
+        game.piece_activity_weights = without;
+        let (midgame_without, endgame_without) = game.evaluate_piece_activity(None);
+        return (midgame - midgame_without, endgame - endgame_without);
+    }
+
+    // Flips the board top to bottom and swaps the colours. Only the board part of the FEN is kept.
+    fn mirror_fen(fen: &str) -> String {
+        let board = fen.split(' ').next().unwrap();
+        let ranks: Vec<String> = board
+            .split('/')
+            .rev()
+            .map(|rank| {
+                rank.chars()
+                    .map(|ch| {
+                        if ch.is_uppercase() {
+                            ch.to_ascii_lowercase()
+                        } else {
+                            ch.to_ascii_uppercase()
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        return format!("{} w - - 0 1", ranks.join("/"));
+    }
+
+    fn negate((midgame, endgame): (i64, i64)) -> (i64, i64) {
+        return (-midgame, -endgame);
+    }
+
+    #[test]
+    fn piece_activity_evaluation() {
+        let c = Constants::new();
+        let zero = Weight::new(0, 0);
+        let default = PieceActivityWeights::default;
+
+        let bishop_pair = PieceActivityWeights {
+            bishop_pair: zero,
+            ..default()
+        };
+        assert_eq!(
+            activity_term(&c, "4k3/8/8/8/8/8/8/2B1KB2 w - - 0 1", bishop_pair),
+            (30, 50)
+        );
+
+        let open_file = PieceActivityWeights {
+            rook_open_file: zero,
+            ..default()
+        };
+        assert_eq!(
+            activity_term(&c, "4k3/pp6/8/8/8/8/PP6/4K2R w - - 0 1", open_file),
+            (25, 10)
+        );
+        let semi_open_file = PieceActivityWeights {
+            rook_semi_open_file: zero,
+            ..default()
+        };
+        assert_eq!(
+            activity_term(&c, "4k3/pp5p/8/8/8/8/PP6/4K2R w - - 0 1", semi_open_file),
+            (12, 6)
+        );
+
+        let seventh_rank = PieceActivityWeights {
+            rook_seventh_rank: zero,
+            ..default()
+        };
+        assert_eq!(
+            activity_term(
+                &c,
+                "4k3/pp1R4/8/8/8/8/PP6/4K3 w - - 0 1",
+                seventh_rank.clone()
+            ),
+            (10, 25)
+        );
+        assert_eq!(
+            activity_term(&c, "8/3R4/8/k7/8/8/PP6/4K3 w - - 0 1", seventh_rank),
+            (0, 0)
+        );
+
+        // d5 is held by e4, and nothing on c7 or e7 can chase the knight away. Until there's a pawn on c7.
+        let outpost = PieceActivityWeights {
+            knight_outpost: zero,
+            ..default()
+        };
+        assert_eq!(
+            activity_term(&c, "4k3/p6p/8/3N4/4P3/8/8/4K3 w - - 0 1", outpost.clone()),
+            (20, 10)
+        );
+        assert_eq!(
+            activity_term(&c, "4k3/p1p4p/8/3N4/4P3/8/8/4K3 w - - 0 1", outpost),
+            (0, 0)
+        );
+
+        let trapped_bishop = PieceActivityWeights {
+            trapped_bishop: zero,
+            ..default()
+        };
+        assert_eq!(
+            activity_term(&c, "4k3/B7/1p6/8/8/8/8/4K3 w - - 0 1", trapped_bishop),
+            (-100, -100)
+        );
+        let trapped_rook = PieceActivityWeights {
+            trapped_rook: zero,
+            ..default()
+        };
+        assert_eq!(
+            activity_term(&c, "4k3/8/8/8/8/8/6PP/5K1R w - - 0 1", trapped_rook),
+            (-40, -10)
+        );
+
+        // A knight in the middle does more than one in the corner.
+        let centre = game_from_fen(&c, "4k3/8/8/8/3N4/8/8/4K3 w - - 0 1");
+        let corner = game_from_fen(&c, "4k3/8/8/8/8/8/8/N3K3 w - - 0 1");
+        assert!(centre.evaluate_piece_activity(None).0 > corner.evaluate_piece_activity(None).0);
+
+        // Mirrored positions score the same for both sides.
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w - - 0 1";
+        let white = game_from_fen(&c, fen);
+        let black = game_from_fen(&c, &mirror_fen(fen));
+        assert_eq!(
+            white.evaluate_piece_activity(None),
+            negate(black.evaluate_piece_activity(None))
+        );
+        assert_eq!(white.evaluate_board(), -black.evaluate_board());
+    }
+
+    #[test]
+    fn chess960_castling() {
+        let c = Constants::new();
+
+        // Shredder-FEN in, X-FEN out. Only the rook with another one further out keeps its file.
+        let mut game = game_from_fen(&c, "1r3kr1/pppppppp/8/8/8/8/PPPPPPPP/1R3KR1 w GBgb - 0 1");
+        assert_eq!(
+            game.export_fen(),
+            "1r3kr1/pppppppp/8/8/8/8/PPPPPPPP/1R3KR1 w KQkq - 0 1"
+        );
+        assert!(game.has_chess960_castling());
+        let two_rooks = game_from_fen(&c, "rr2k3/8/8/8/8/8/8/RR2K3 w Bq - 0 1");
+        assert_eq!(two_rooks.export_fen(), "rr2k3/8/8/8/8/8/8/RR2K3 w Bq - 0 1");
+        assert_eq!(two_rooks.castling_rook_squares, [63, 57, 7, 0]);
+
+        // Castling is the king taking its own rook. Short swaps the king and rook here.
+        game.is_chess960 = true;
+        let short = game
+            .choose_move_from_legal_move(&Move::str_to_move("f1g1").unwrap())
+            .unwrap();
+        assert_eq!(game.move_to_uci(&short), "f1g1");
+        assert_eq!(game.move_to_san(&short, false), "O-O");
+        let long = game
+            .choose_move_from_legal_move(&Move::str_to_move("f1b1").unwrap())
+            .unwrap();
+        assert_eq!(game.move_to_san(&long, false), "O-O-O");
+        game.make_move(&long, true);
+        assert_eq!(
+            game.export_fen(),
+            "1r3kr1/pppppppp/8/8/8/8/PPPPPPPP/2KR2R1 b kq - 1 1"
+        );
+        game.unmake_move(&long);
+        assert_eq!(
+            game.export_fen(),
+            "1r3kr1/pppppppp/8/8/8/8/PPPPPPPP/1R3KR1 w KQkq - 0 1"
+        );
+
+        // Standard chess takes either way of writing it, and sends the king's move.
+        let mut standard = game_from_fen(&c, "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+        assert!(!standard.has_chess960_castling());
+        let castle = standard
+            .choose_move_from_legal_move(&Move::str_to_move("e1g1").unwrap())
+            .unwrap();
+        assert_eq!(castle.castle_side, Some(CastleSides::Short));
+        assert_eq!(standard.move_to_uci(&castle), "e1g1");
+        assert!(standard
+            .choose_move_from_legal_move(&Move::str_to_move("e1a1").unwrap())
+            .is_some());
+        standard.is_chess960 = true;
+        assert_eq!(standard.move_to_uci(&castle), "e1h1");
+        assert!(standard
+            .choose_move_from_legal_move(&Move::str_to_move("e1g1").unwrap())
+            .is_none());
+    }
+}
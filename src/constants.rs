@@ -1,9 +1,48 @@
 pub const INITIAL_GAME_STATE_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
-pub const LICHESS_BOT_USERNAME: &str = "botmasterj";
-pub const LICHESS_CHALLENGER_WHITELIST: [&str; 1] = ["The_Big_C"];
+
+// h1, a1, h8, a8. Indexed by `CastleSides::castling_rights_index`.
+pub const STANDARD_CASTLING_ROOK_SQUARES: [usize; 4] = [63, 56, 7, 0];
+pub const DEFAULT_TRANSPOSITION_TABLE_SIZE_MB: usize = 64;
+
+// Pawn structure barely changes during a search, so its evaluation is cached in a small table of its own.
+pub const PAWN_HASH_TABLE_SIZE_MB: usize = 2;
+
+pub const DEFAULT_MAX_BOOK_DEPTH_PLY: u32 = 16;
+
+// What `ChessGame::new` starts with. Anything that searches for real resizes it, so tests and tools stay cheap.
+pub const INITIAL_TRANSPOSITION_TABLE_SIZE_MB: usize = 1;
+
+// An accepted challenge holds a game slot until its gameStart arrives, or this long if it never does.
+pub const ACCEPTED_CHALLENGE_TIMEOUT_S: u64 = 60;
+
+// Lichess streams.
+pub const LICHESS_URL: &str = "https://lichess.org";
+pub const RECONNECT_INITIAL_DELAY_MS: u64 = 1000;
+pub const RECONNECT_MAX_DELAY_MS: u64 = 60_000;
+
+// Lichess sends a keep alive newline every few seconds. Nothing for this long means the connection is dead.
+pub const STREAM_INACTIVITY_TIMEOUT_S: u64 = 20;
+
+// Lichess asks for a full minute without requests after a 429. Ref: https://lichess.org/page/api-tips
+pub const RATE_LIMIT_WAIT_S: u64 = 60;
+
+// Retrying a POST, like a move, after a connection or server error. Short, our clock is running. A 429 still waits
+// the full minute.
+pub const POST_RETRY_INITIAL_DELAY_MS: u64 = 250;
+pub const POST_RETRY_MAX_DELAY_MS: u64 = 4000;
+pub const POST_MAX_ATTEMPTS: u32 = 5;
+
+// How often a game checks its timers (claiming victory, aborting) while the stream is quiet.
+pub const GAME_TIMER_TICK_MS: u64 = 1000;
+
+// Evaluation terms sent back for the `debug` chat command. Lichess cuts chat messages off at 140 characters.
+pub const DEBUG_CHAT_EVALUATION_TERMS: usize = 3;
 
 // Bitboard implementation.
 
+// a8 is a light square, and a8 is square 0.
+pub const LIGHT_SQUARES: u64 = 0xAA55AA55AA55AA55;
+
 // TODO: Thoroughly document this info set.
 // These are precalculated, they are expensive to calculate.
 pub const ROOK_MAGIC_NUMBERS: [u64; 64] = [
@@ -301,7 +340,11 @@ pub const ROOK_MASKED_ATTACKS: [u64; 64] = [
     9115426935197958144,
 ];
 
-pub const KING_HAPPY_SQUARES_NON_ENDGAME: [i64; 64] = [
+// Piece-square tables, from white's side (a8 first). Black looks them up mirrored. Each piece has a midgame and an
+// endgame table, `evaluate_board` blends the two by how much material is left.
+
+// Tucked away behind the pawns while there are pieces around to attack it.
+pub const KING_HAPPY_SQUARES_MIDGAME: [i64; 64] = [
     030, 030, 025, -10, -10, -10, 030, 030,
     -20, -10, -10, -10, -10, -10, -10, -20,
     -30, -30, -30, -30, -30, -30, -30, -30,
@@ -312,7 +355,19 @@ pub const KING_HAPPY_SQUARES_NON_ENDGAME: [i64; 64] = [
     030, 030, 025, -10, -10, -10, 030, 030,
 ];
 
-pub const QUEEN_HAPPY_SQUARES: [i64; 64] = [
+// With the pieces gone, the king is a fighting piece. Get it to the middle.
+pub const KING_HAPPY_SQUARES_ENDGAME: [i64; 64] = [
+    -50, -40, -30, -20, -20, -30, -40, -50,
+    -30, -20, -10,  000,  000, -10, -20, -30,
+    -30, -10,  020,  030,  030,  020, -10, -30,
+    -30, -10,  030,  040,  040,  030, -10, -30,
+    -30, -10,  030,  040,  040,  030, -10, -30,
+    -30, -10,  020,  030,  030,  020, -10, -30,
+    -30, -20, -10,  000,  000, -10, -20, -30,
+    -50, -40, -30, -20, -20, -30, -40, -50,
+];
+
+pub const QUEEN_HAPPY_SQUARES_MIDGAME: [i64; 64] = [
     -05,  000,  000,  000,  000,  000,  000, -05,
     000,  000,  000,  000,  000,  000,  000, 000,
     000,  000,  025,  025,  025,  025,  000, 000,
@@ -323,8 +378,19 @@ pub const QUEEN_HAPPY_SQUARES: [i64; 64] = [
     -05,  000,  000,  000,  000,  000,  000, -05,
 ];
 
+pub const QUEEN_HAPPY_SQUARES_ENDGAME: [i64; 64] = [
+    -20, -10, -10, -05, -05, -10, -10, -20,
+    -10,  000,  000,  000,  000,  000,  000, -10,
+    -10,  000,  010,  010,  010,  010,  000, -10,
+    -05,  000,  010,  020,  020,  010,  000, -05,
+    -05,  000,  010,  020,  020,  010,  000, -05,
+    -10,  000,  010,  010,  010,  010,  000, -10,
+    -10,  000,  000,  000,  000,  000,  000, -10,
+    -20, -10, -10, -05, -05, -10, -10, -20,
+];
+
 // TODO: Think about better rook happy squares.
-pub const ROOK_HAPPY_SQUARES: [i64; 64] = [
+pub const ROOK_HAPPY_SQUARES_MIDGAME: [i64; 64] = [
     000,  000,  000,  000,  000,  000,  000, 000,
     005,  005,  005,  005,  005,  005,  005, 005,
     000,  000,  000,  000,  000,  000,  000, 000,
@@ -335,7 +401,19 @@ pub const ROOK_HAPPY_SQUARES: [i64; 64] = [
     000,  000,  000,  000,  000,  000,  000, 000,
 ];
 
-pub const BISHOP_HAPPY_SQUARES: [i64; 64] = [
+// Where a rook stands matters less in the endgame, it mostly wants files and ranks to itself.
+pub const ROOK_HAPPY_SQUARES_ENDGAME: [i64; 64] = [
+    000,  000,  000,  000,  000,  000,  000, 000,
+    000,  000,  000,  000,  000,  000,  000, 000,
+    000,  000,  005,  005,  005,  005,  000, 000,
+    000,  000,  005,  005,  005,  005,  000, 000,
+    000,  000,  005,  005,  005,  005,  000, 000,
+    000,  000,  005,  005,  005,  005,  000, 000,
+    000,  000,  000,  000,  000,  000,  000, 000,
+    000,  000,  000,  000,  000,  000,  000, 000,
+];
+
+pub const BISHOP_HAPPY_SQUARES_MIDGAME: [i64; 64] = [
     -05,  000,  000,  000,  000,  000,  000, -05,
     000,  005,  005,  005,  005,  005,  005, 000,
     000,  005,  025,  025,  025,  025,  005, 000,
@@ -346,8 +424,18 @@ pub const BISHOP_HAPPY_SQUARES: [i64; 64] = [
     -05,  000,  000,  000,  000,  000,  000, -05,
 ];
 
+pub const BISHOP_HAPPY_SQUARES_ENDGAME: [i64; 64] = [
+    -10, -05, -05, -05, -05, -05, -05, -10,
+    -05,  000,  000,  000,  000,  000,  000, -05,
+    -05,  000,  010,  010,  010,  010,  000, -05,
+    -05,  000,  010,  015,  015,  010,  000, -05,
+    -05,  000,  010,  015,  015,  010,  000, -05,
+    -05,  000,  010,  010,  010,  010,  000, -05,
+    -05,  000,  000,  000,  000,  000,  000, -05,
+    -10, -05, -05, -05, -05, -05, -05, -10,
+];
 
-pub const KNIGHT_HAPPY_SQUARES: [i64; 64] = [
+pub const KNIGHT_HAPPY_SQUARES_MIDGAME: [i64; 64] = [
     -030, -020, -020, -020, -020, -020, -020, -030,
     -020, -010,  000,  000,  000,  000, -010, -020,
     -020,  000,  025,  025,  025,  025,  000, -020,
@@ -358,9 +446,19 @@ pub const KNIGHT_HAPPY_SQUARES: [i64; 64] = [
     -030, -020, -020, -020, -020, -020, -020, -030,
 ];
 
+// A knight on the rim is even worse with play on both wings.
+pub const KNIGHT_HAPPY_SQUARES_ENDGAME: [i64; 64] = [
+    -040, -030, -020, -020, -020, -020, -030, -040,
+    -030, -020,  000,  000,  000,  000, -020, -030,
+    -020,  000,  015,  015,  015,  015,  000, -020,
+    -020,  000,  015,  020,  020,  015,  000, -020,
+    -020,  000,  015,  020,  020,  015,  000, -020,
+    -020,  000,  015,  015,  015,  015,  000, -020,
+    -030, -020,  000,  000,  000,  000, -020, -030,
+    -040, -030, -020, -020, -020, -020, -030, -040,
+];
 
-// From white's perspective.
-pub const PAWN_HAPPY_SQUARES: [i64; 64] = [
+pub const PAWN_HAPPY_SQUARES_MIDGAME: [i64; 64] = [
     000,  000,  000,  000,  000,  000,  000,  000,
     100,  100,  100,  100,  100,  100,  100,  100,
     040,  040,  040,  040,  040,  040,  040,  040,
@@ -369,4 +467,20 @@ pub const PAWN_HAPPY_SQUARES: [i64; 64] = [
     005,  005,  005,  005,  005,  005,  005,  005,
     000,  000,  000,  000,  000,  000,  000,  000,
     000,  000,  000,  000,  000,  000,  000,  000,
-];
\ No newline at end of file
+];
+
+// Every step closer to queening counts for more once there is nothing left to stop it.
+pub const PAWN_HAPPY_SQUARES_ENDGAME: [i64; 64] = [
+    000,  000,  000,  000,  000,  000,  000,  000,
+    150,  150,  150,  150,  150,  150,  150,  150,
+    080,  080,  080,  080,  080,  080,  080,  080,
+    050,  050,  050,  050,  050,  050,  050,  050,
+    030,  030,  030,  030,  030,  030,  030,  030,
+    015,  015,  015,  015,  015,  015,  015,  015,
+    000,  000,  000,  000,  000,  000,  000,  000,
+    000,  000,  000,  000,  000,  000,  000,  000,
+];
+
+// Game phase, counted from the pieces left. Each knight and bishop is 1, rook 2 and queen 4. All of them is the
+// midgame, none is the endgame. Extra pieces from promotions are capped at the midgame.
+pub const GAME_PHASE_MIDGAME: i64 = 24;
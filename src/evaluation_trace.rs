@@ -0,0 +1,248 @@
+use crate::chess_game::ChessGame;
+use crate::color::Color;
+use crate::constants;
+use crate::piece_activity::Weight;
+use crate::runtime_calculated_constants::Constants;
+use crate::score;
+
+// Print the breakdown for a position: `eval [fen]`. The starting position if no FEN is given.
+pub fn main(args: &[String]) -> Result<(), String> {
+    let fen = if args.is_empty() {
+        constants::INITIAL_GAME_STATE_FEN.to_string()
+    } else {
+        args.join(" ")
+    };
+
+    let c = Constants::new();
+    let mut game = ChessGame::new(&c);
+    game.import_fen(&fen)?;
+
+    game.print_board();
+    println!("FEN: {}", game.export_fen());
+    println!();
+    print!("{}", game.evaluate_board_traced().to_table());
+
+    return Ok(());
+}
+
+// Everything `evaluate_board` adds up, in the order it's printed.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum EvaluationTerm {
+    Material,
+    PieceSquares,
+    PassedPawns,
+    FreePassedPawns,
+    ConnectedPawns,
+    DoubledPawns,
+    IsolatedPawns,
+    BackwardPawns,
+    KingShelter,
+    KingAttacks,
+    Mobility,
+    BishopPair,
+    RookFiles,
+    RookSeventhRank,
+    KnightOutposts,
+    TrappedPieces,
+}
+
+pub const EVALUATION_TERM_COUNT: usize = 16;
+
+impl EvaluationTerm {
+    pub const ALL: [EvaluationTerm; EVALUATION_TERM_COUNT] = [
+        Self::Material,
+        Self::PieceSquares,
+        Self::PassedPawns,
+        Self::FreePassedPawns,
+        Self::ConnectedPawns,
+        Self::DoubledPawns,
+        Self::IsolatedPawns,
+        Self::BackwardPawns,
+        Self::KingShelter,
+        Self::KingAttacks,
+        Self::Mobility,
+        Self::BishopPair,
+        Self::RookFiles,
+        Self::RookSeventhRank,
+        Self::KnightOutposts,
+        Self::TrappedPieces,
+    ];
+
+    pub fn idx(&self) -> usize {
+        return *self as usize;
+    }
+
+    pub fn name(&self) -> &'static str {
+        return match self {
+            Self::Material => "Material",
+            Self::PieceSquares => "Piece squares",
+            Self::PassedPawns => "Passed pawns",
+            Self::FreePassedPawns => "Free passed pawns",
+            Self::ConnectedPawns => "Connected pawns",
+            Self::DoubledPawns => "Doubled pawns",
+            Self::IsolatedPawns => "Isolated pawns",
+            Self::BackwardPawns => "Backward pawns",
+            Self::KingShelter => "King shelter",
+            Self::KingAttacks => "King attacks",
+            Self::Mobility => "Mobility",
+            Self::BishopPair => "Bishop pair",
+            Self::RookFiles => "Rook files",
+            Self::RookSeventhRank => "Rook on 7th",
+            Self::KnightOutposts => "Knight outposts",
+            Self::TrappedPieces => "Trapped pieces",
+        };
+    }
+}
+
+// What `ChessGame::evaluate_board_traced` found, term by term.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EvaluationTrace {
+    // From `GAME_PHASE_MIDGAME` down to 0, see `ChessGame::game_phase`.
+    pub phase: i64,
+
+    // [color][term], indexed by `Color::idx` and `EvaluationTerm::idx`. Each side from its own point of view, so a
+    // positive value is good for that side.
+    pub terms: [[Weight; EVALUATION_TERM_COUNT]; 2],
+
+    // What `evaluate_board` returns, from white's point of view.
+    pub total: i64,
+}
+
+impl EvaluationTrace {
+    pub fn new(phase: i64) -> Self {
+        return EvaluationTrace {
+            phase,
+            terms: [[Weight::new(0, 0); EVALUATION_TERM_COUNT]; 2],
+            total: 0,
+        };
+    }
+
+    pub fn add(&mut self, color: Color, term: EvaluationTerm, midgame: i64, endgame: i64) {
+        let weight = &mut self.terms[color.idx()][term.idx()];
+        weight.midgame += midgame;
+        weight.endgame += endgame;
+    }
+
+    // White minus black, blended for the phase the same way `evaluate_board` does.
+    pub fn term_total(&self, term: EvaluationTerm) -> i64 {
+        let white = self.terms[Color::White.idx()][term.idx()];
+        let black = self.terms[Color::Black.idx()][term.idx()];
+        let midgame = white.midgame - black.midgame;
+        let endgame = white.endgame - black.endgame;
+        return (midgame * self.phase + endgame * (constants::GAME_PHASE_MIDGAME - self.phase))
+            / constants::GAME_PHASE_MIDGAME;
+    }
+
+    // One row per term, in centipawns. Rounding means the rows can be a centipawn or two off the total.
+    pub fn to_table(&self) -> String {
+        let mut table = format!(
+            "{:<18} {:>7} {:>7} {:>7} {:>7} {:>7}\n",
+            "Term", "White", "", "Black", "", "Total"
+        );
+        table += &format!(
+            "{:<18} {:>7} {:>7} {:>7} {:>7} {:>7}\n",
+            "", "MG", "EG", "MG", "EG", ""
+        );
+        for term in EvaluationTerm::ALL.iter() {
+            let white = self.terms[Color::White.idx()][term.idx()];
+            let black = self.terms[Color::Black.idx()][term.idx()];
+            table += &format!(
+                "{:<18} {:>7} {:>7} {:>7} {:>7} {:>7}\n",
+                term.name(),
+                white.midgame,
+                white.endgame,
+                black.midgame,
+                black.endgame,
+                self.term_total(*term)
+            );
+        }
+        table += &format!(
+            "Phase {}/{}. Total {} for white.\n",
+            self.phase,
+            constants::GAME_PHASE_MIDGAME,
+            score::to_display_str(self.total)
+        );
+        return table;
+    }
+
+    // Short enough for a Lichess chat message. The total, then the terms that matter most. Ex:
+    // `Eval +0.35. Material +1.00, King attacks -0.50, Mobility -0.12`.
+    pub fn to_summary(&self, max_terms: usize) -> String {
+        let mut terms: Vec<(EvaluationTerm, i64)> = EvaluationTerm::ALL
+            .iter()
+            .map(|term| (*term, self.term_total(*term)))
+            .filter(|(_, total)| *total != 0)
+            .collect();
+        terms.sort_by_key(|(_, total)| -total.abs());
+
+        let parts: Vec<String> = terms
+            .iter()
+            .take(max_terms)
+            .map(|(term, total)| format!("{} {}", term.name(), score::to_display_str(*total)))
+            .collect();
+        return format!(
+            "Eval {}. {}",
+            score::to_display_str(self.total),
+            parts.join(", ")
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trace_adds_up_to_evaluate_board() {
+        let c = Constants::new();
+        let mut game = ChessGame::new(&c);
+
+        for fen in [
+            constants::INITIAL_GAME_STATE_FEN,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "6k1/5ppp/8/8/6nq/8/5PPP/6K1 w - - 0 1",
+            "4k3/1P6/8/3pP3/8/8/6p1/4K2R w K d6 0 1",
+        ] {
+            game.import_fen(fen).unwrap();
+            let trace = game.evaluate_board_traced();
+            assert_eq!(trace.total, game.evaluate_board(), "{fen}");
+
+            // Every point `evaluate_board` counted is in one of the terms. Material isn't blended, the rest is.
+            let (mut midgame, mut endgame) = (0, 0);
+            for term in EvaluationTerm::ALL.iter().skip(1) {
+                let white = trace.terms[Color::White.idx()][term.idx()];
+                let black = trace.terms[Color::Black.idx()][term.idx()];
+                midgame += white.midgame - black.midgame;
+                endgame += white.endgame - black.endgame;
+            }
+            let material = trace.term_total(EvaluationTerm::Material);
+            let phase = trace.phase;
+            let positional = (midgame * phase + endgame * (constants::GAME_PHASE_MIDGAME - phase))
+                / constants::GAME_PHASE_MIDGAME;
+            assert_eq!(material + positional, trace.total, "{fen}");
+        }
+    }
+
+    #[test]
+    fn table_and_summary() {
+        let c = Constants::new();
+        let mut game = ChessGame::new(&c);
+        game.import_fen("6k1/5ppp/8/8/6nq/8/5PPP/6K1 w - - 0 1")
+            .unwrap();
+        let trace = game.evaluate_board_traced();
+
+        let table = trace.to_table();
+        for term in EvaluationTerm::ALL.iter() {
+            assert!(table.contains(term.name()), "{table}");
+        }
+
+        // Black is a queen and a knight up, that comes first.
+        let summary = trace.to_summary(constants::DEBUG_CHAT_EVALUATION_TERMS);
+        let expected_start = format!(
+            "Eval {}. Material -12.00, ",
+            score::to_display_str(trace.total)
+        );
+        assert!(summary.starts_with(&expected_start), "{summary}");
+        assert!(summary.len() <= 140, "{summary}");
+    }
+}
@@ -0,0 +1,85 @@
+use crate::bot_config::BotConfig;
+
+// Decides how to answer the opponent's draw offer. Evaluations are in centipawns, from our side. `None` means we have no
+// search to go on yet, like when playing from the opening book.
+pub fn should_accept_draw(our_evaluation: Option<i64>, config: &BotConfig) -> bool {
+    return match (our_evaluation, config.draw_accept_eval_cp) {
+        (Some(evaluation), Some(threshold)) => evaluation <= threshold,
+        _ => false,
+    };
+}
+
+// Counts how many of our moves in a row the search thought were hopeless. One bad evaluation could just be the search
+// missing something, so we wait for a few.
+pub struct ResignCounter {
+    hopeless_moves: u32,
+}
+
+impl Default for ResignCounter {
+    fn default() -> Self {
+        ResignCounter { hopeless_moves: 0 }
+    }
+}
+
+impl ResignCounter {
+    // Call once for each of our moves. Returns true when it is time to resign.
+    pub fn should_resign(&mut self, our_evaluation: Option<i64>, config: &BotConfig) -> bool {
+        let is_hopeless = match (our_evaluation, config.resign_eval_cp) {
+            (Some(evaluation), Some(threshold)) => evaluation <= threshold,
+            _ => false,
+        };
+
+        if !is_hopeless {
+            self.hopeless_moves = 0;
+            return false;
+        }
+
+        self.hopeless_moves += 1;
+        return self.hopeless_moves >= config.resign_after_moves;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draw_offers() {
+        let mut config = BotConfig {
+            draw_accept_eval_cp: Some(-50),
+            ..BotConfig::default()
+        };
+        assert!(should_accept_draw(Some(-200), &config));
+        assert!(should_accept_draw(Some(-50), &config));
+        assert!(!should_accept_draw(Some(30), &config));
+        assert!(!should_accept_draw(None, &config));
+
+        config.draw_accept_eval_cp = None;
+        assert!(!should_accept_draw(Some(-200), &config));
+    }
+
+    #[test]
+    fn resigns_after_hopeless_moves_in_a_row() {
+        let mut config = BotConfig {
+            resign_eval_cp: Some(-800),
+            resign_after_moves: 3,
+            ..BotConfig::default()
+        };
+
+        let mut counter = ResignCounter::default();
+        assert!(!counter.should_resign(Some(-900), &config));
+        assert!(!counter.should_resign(Some(-900), &config));
+
+        // The search found something, start counting again.
+        assert!(!counter.should_resign(Some(-100), &config));
+        assert!(!counter.should_resign(Some(-900), &config));
+        assert!(!counter.should_resign(Some(-1000), &config));
+        assert!(counter.should_resign(Some(-1000), &config));
+
+        config.resign_eval_cp = None;
+        let mut counter = ResignCounter::default();
+        for _ in 0..5 {
+            assert!(!counter.should_resign(Some(-10_000), &config));
+        }
+    }
+}
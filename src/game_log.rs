@@ -0,0 +1,380 @@
+use crate::chess_game::ChessGame;
+use crate::lichess_structs::{GameFull, GameState, UserBasic};
+use crate::pgn::{PgnGame, PgnMove};
+use crate::r#move::Move;
+use crate::{constants, score};
+use serde::{Deserialize, Serialize};
+
+// Everything we know about one Lichess game, saved when it ends as PGN for reviewing and JSON for tuning scripts.
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SearchStats {
+    // Centipawns, from white's point of view.
+    pub evaluation: i64,
+    pub depth: u32,
+    pub nodes: u64,
+    pub time_ms: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LoggedMove {
+    // Long algebraic, as Lichess sends it.
+    pub uci: String,
+    pub is_bot_move: bool,
+
+    // Only set for moves we searched. Book moves and the opponent's moves have none.
+    pub search: Option<SearchStats>,
+
+    // Time left on the mover's clock after the move. Missing for moves we only caught up on after a reconnect.
+    pub clock_ms: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GameLog {
+    pub id: String,
+    pub variant: String,
+    pub initial_fen: String,
+    pub rated: bool,
+    pub speed: String,
+
+    // Milliseconds. Not set for correspondence and unlimited games.
+    pub clock_initial_ms: Option<u32>,
+    pub clock_increment_ms: Option<u32>,
+    pub created_at_ms: u64,
+
+    pub white: UserBasic,
+    pub black: UserBasic,
+    pub is_bot_white: bool,
+
+    pub moves: Vec<LoggedMove>,
+
+    // The Lichess status, like `mate` or `resign`. `started` until the game ends.
+    pub status: String,
+    pub winner: Option<String>,
+
+    // Stats for the move we just sent, until Lichess sends it back.
+    #[serde(skip)]
+    pending_search: Option<(String, SearchStats)>,
+}
+
+impl Default for GameLog {
+    fn default() -> Self {
+        GameLog {
+            id: String::new(),
+            variant: String::from("standard"),
+            initial_fen: String::from(constants::INITIAL_GAME_STATE_FEN),
+            rated: false,
+            speed: String::new(),
+            clock_initial_ms: None,
+            clock_increment_ms: None,
+            created_at_ms: 0,
+            white: UserBasic::default(),
+            black: UserBasic::default(),
+            is_bot_white: true,
+            moves: vec![],
+            status: String::from("started"),
+            winner: None,
+            pending_search: None,
+        }
+    }
+}
+
+impl GameLog {
+    pub fn new(game_full: &GameFull, is_bot_white: bool) -> Self {
+        return GameLog {
+            id: game_full.id.clone(),
+            variant: game_full.variant.key.clone(),
+            initial_fen: game_full.initial_position_fen().to_string(),
+            rated: game_full.rated,
+            speed: game_full.speed.clone(),
+            clock_initial_ms: game_full.clock.as_ref().map(|c| c.initial),
+            clock_increment_ms: game_full.clock.as_ref().map(|c| c.increment),
+            created_at_ms: game_full.created_at,
+            white: game_full.white.clone(),
+            black: game_full.black.clone(),
+            is_bot_white,
+            ..GameLog::default()
+        };
+    }
+
+    // Call before sending our move. The stats are attached when the move comes back from Lichess.
+    pub fn set_pending_search(&mut self, uci: &str, stats: SearchStats) {
+        self.pending_search = Some((uci.to_string(), stats));
+    }
+
+    // Matches our moves to the full move list from Lichess. Anything after where they differ, like a takeback, is
+    // dropped. Then the mover's clock is taken from the state for the newest move.
+    pub fn sync_moves(&mut self, moves: &[String], state: &GameState) {
+        let matching = self
+            .moves
+            .iter()
+            .zip(moves.iter())
+            .take_while(|(logged, m)| logged.uci == **m)
+            .count();
+        if matching == moves.len() && matching == self.moves.len() {
+            return;
+        }
+        self.moves.truncate(matching);
+
+        for m in moves[matching..].iter() {
+            let is_bot_move = self.is_white_move(self.moves.len()) == self.is_bot_white;
+            let search = match self.pending_search.take() {
+                Some((uci, stats)) if is_bot_move && uci == *m => Some(stats),
+                other => {
+                    self.pending_search = other;
+                    None
+                }
+            };
+            self.moves.push(LoggedMove {
+                uci: m.clone(),
+                is_bot_move,
+                search,
+                clock_ms: None,
+            });
+        }
+
+        let newest = match self.moves.len().checked_sub(1) {
+            Some(n) => n,
+            None => return,
+        };
+        let is_white_move = self.is_white_move(newest);
+        self.moves[newest].clock_ms = Some(if is_white_move {
+            state.wtime
+        } else {
+            state.btime
+        });
+    }
+
+    pub fn finish(&mut self, state: &GameState) {
+        self.status = state.status.clone();
+        self.winner = state.winner.clone();
+    }
+
+    // Games that never really started, or haven't ended, have no result.
+    pub fn result(&self) -> &str {
+        return match (self.winner.as_deref(), self.status.as_str()) {
+            (Some("white"), _) => "1-0",
+            (Some("black"), _) => "0-1",
+            (_, "created" | "started" | "aborted" | "noStart" | "unknownFinish") => "*",
+            _ => "1/2-1/2",
+        };
+    }
+
+    // Which side played the move at this index. Games from a position can start with black to move.
+    fn is_white_move(&self, index: usize) -> bool {
+        let is_white_first = self.initial_fen.split_whitespace().nth(1) != Some("b");
+        return index.is_multiple_of(2) == is_white_first;
+    }
+
+    // `game` is only used as a scratch board to work out the SAN of each move.
+    pub fn to_pgn(&self, game: &mut ChessGame) -> Result<String, String> {
+        let mut pgn = PgnGame::default();
+        let rated = if self.rated { "Rated" } else { "Casual" };
+        pgn.set_tag("Event", &format!("{rated} {} game", self.speed));
+        pgn.set_tag("Site", &format!("{}/{}", constants::LICHESS_URL, self.id));
+        pgn.set_tag("Date", &pgn_date(self.created_at_ms));
+        pgn.set_tag("Round", "-");
+        pgn.set_tag("White", &self.white.name);
+        pgn.set_tag("Black", &self.black.name);
+        pgn.set_tag("WhiteElo", &self.white.rating.to_string());
+        pgn.set_tag("BlackElo", &self.black.rating.to_string());
+        if let Some(title) = &self.white.title {
+            pgn.set_tag("WhiteTitle", title);
+        }
+        if let Some(title) = &self.black.title {
+            pgn.set_tag("BlackTitle", title);
+        }
+        let time_control = match (self.clock_initial_ms, self.clock_increment_ms) {
+            (Some(initial), Some(increment)) => format!("{}+{}", initial / 1000, increment / 1000),
+            _ => String::from("-"),
+        };
+        pgn.set_tag("TimeControl", &time_control);
+        pgn.set_tag("Termination", termination(&self.status));
+        if self.variant == "chess960" {
+            pgn.set_tag("Variant", "Chess960");
+        }
+        if self.initial_fen != constants::INITIAL_GAME_STATE_FEN {
+            pgn.set_tag("SetUp", "1");
+            pgn.set_tag("FEN", &self.initial_fen);
+        }
+        pgn.result = self.result().to_string();
+
+        for logged in self.moves.iter() {
+            let mut pgn_move = PgnMove::new(Move::str_to_move(&logged.uci)?);
+
+            let mut commands: Vec<String> = vec![];
+            if let Some(search) = &logged.search {
+                commands.push(format!(
+                    "[%eval {}]",
+                    score::to_pgn_eval_str(search.evaluation)
+                ));
+            }
+            if let Some(clock_ms) = logged.clock_ms {
+                commands.push(format!("[%clk {}]", pgn_clock(clock_ms)));
+            }
+            if !commands.is_empty() {
+                pgn_move.comments.push(commands.join(" "));
+            }
+            pgn.moves.push(pgn_move);
+        }
+
+        game.is_chess960 = self.variant == "chess960";
+        return pgn.export(game);
+    }
+
+    // Writes `<id>.pgn` and `<id>.json` to `dir`, creating it if needed.
+    pub fn save(&self, dir: &str, game: &mut ChessGame) -> Result<(), String> {
+        let pgn = self.to_pgn(game)?;
+        let json = match serde_json::to_string_pretty(self) {
+            Ok(j) => j,
+            Err(e) => {
+                return Err(format!(
+                    "Unable to write game {} as JSON. Detail: {e}",
+                    self.id
+                ))
+            }
+        };
+
+        let dir_path = std::path::Path::new(dir);
+        let written = std::fs::create_dir_all(dir_path)
+            .and_then(|_| std::fs::write(dir_path.join(format!("{}.pgn", self.id)), pgn))
+            .and_then(|_| std::fs::write(dir_path.join(format!("{}.json", self.id)), json));
+        if let Err(e) = written {
+            return Err(format!(
+                "Unable to save game {} to `{dir}`. Detail: {e}",
+                self.id
+            ));
+        }
+
+        return Ok(());
+    }
+}
+
+// The PGN standard's Termination values.
+fn termination(status: &str) -> &'static str {
+    return match status {
+        "created" | "started" => "unterminated",
+        "outoftime" => "time forfeit",
+        "aborted" | "noStart" | "timeout" => "abandoned",
+        "cheat" => "rules infraction",
+        _ => "normal",
+    };
+}
+
+// `H:MM:SS`, the way Lichess writes `[%clk]`.
+fn pgn_clock(ms: u32) -> String {
+    let seconds = ms / 1000;
+    return format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    );
+}
+
+// `YYYY.MM.DD` from a Unix timestamp in milliseconds. Ref: https://howardhinnant.github.io/date_algorithms.html
+fn pgn_date(unix_ms: u64) -> String {
+    if unix_ms == 0 {
+        return String::from("????.??.??");
+    }
+
+    let days = (unix_ms / 86_400_000) as i64 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    return format!("{year:04}.{month:02}.{day:02}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime_calculated_constants::Constants;
+
+    fn state(moves: &str, wtime: u32, btime: u32) -> GameState {
+        return GameState {
+            moves: moves.to_string(),
+            wtime,
+            btime,
+            ..GameState::default()
+        };
+    }
+
+    #[test]
+    fn logs_moves_clocks_and_searches() {
+        let mut log = GameLog {
+            id: String::from("game0001"),
+            rated: true,
+            speed: String::from("blitz"),
+            clock_initial_ms: Some(300_000),
+            clock_increment_ms: Some(3000),
+            created_at_ms: 1_700_000_000_000,
+            ..GameLog::default()
+        };
+        log.white.name = String::from("botmasterj");
+        log.black.name = String::from("Someone");
+
+        let stats = SearchStats {
+            evaluation: 35,
+            depth: 7,
+            nodes: 120_000,
+            time_ms: 900,
+        };
+        log.set_pending_search("e2e4", stats.clone());
+        let e4 = state("e2e4", 299_000, 300_000);
+        log.sync_moves(&e4.moves_to_vec(), &e4);
+        let e5 = state("e2e4 e7e5", 299_000, 297_500);
+        log.sync_moves(&e5.moves_to_vec(), &e5);
+
+        // Takeback of black's move, then a different one.
+        let c5 = state("e2e4 c7c5", 299_000, 290_000);
+        log.sync_moves(&c5.moves_to_vec(), &c5);
+        assert_eq!(log.moves.len(), 2);
+        assert_eq!(log.moves[0].search, Some(stats));
+        assert!(log.moves[0].is_bot_move && !log.moves[1].is_bot_move);
+        assert_eq!(log.moves[1].clock_ms, Some(290_000));
+
+        let mut end = state("e2e4 c7c5", 299_000, 290_000);
+        end.status = String::from("resign");
+        end.winner = Some(String::from("white"));
+        log.finish(&end);
+
+        let c = Constants::new();
+        let mut game = ChessGame::new(&c);
+        let pgn = log.to_pgn(&mut game).unwrap();
+        assert!(pgn.contains("[Event \"Rated blitz game\"]"));
+        assert!(pgn.contains("[Date \"2023.11.14\"]"));
+        assert!(pgn.contains("[TimeControl \"300+3\"]"));
+        assert!(pgn.contains("1. e4 {[%eval 0.35] [%clk 0:04:59]} 1... c5 {[%clk 0:04:50]} 1-0"));
+
+        let json: serde_json::Value =
+            serde_json::from_str(&serde_json::to_string(&log).unwrap()).unwrap();
+        assert_eq!(json["moves"][0]["search"]["nodes"], 120_000);
+        assert_eq!(json["status"], "resign");
+    }
+
+    #[test]
+    fn black_moves_first_from_position() {
+        let mut log = GameLog {
+            initial_fen: String::from("4k3/8/8/8/8/8/4P3/4K3 b - - 0 1"),
+            is_bot_white: false,
+            ..GameLog::default()
+        };
+        let first = state("e8d7", 60_000, 59_000);
+        log.sync_moves(&first.moves_to_vec(), &first);
+        assert!(log.moves[0].is_bot_move);
+        assert_eq!(log.moves[0].clock_ms, Some(59_000));
+        assert_eq!(log.result(), "*");
+        assert_eq!(pgn_date(951_782_400_000), "2000.02.29");
+    }
+}
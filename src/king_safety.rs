@@ -0,0 +1,141 @@
+use crate::color::Color;
+use crate::helpers::*;
+use crate::pawn_structure::relative_rank;
+use crate::runtime_calculated_constants::Constants;
+
+// All in centipawns, and midgame only. With the queens and rooks gone there's nothing left to mate with.
+
+// Indexed by how far in front of the king our closest pawn on the file is. 0 when there isn't one within two squares.
+pub const PAWN_SHIELD_BONUS: [i64; 3] = [-20, 15, 8];
+
+// Indexed by how far in front of the king their closest pawn on the file is. 0 when there isn't one within four squares.
+// Right in front of the king it's usually stuck against our own pawns.
+pub const PAWN_STORM_PENALTY: [i64; 5] = [0, -5, -30, -15, -5];
+
+// Files on or next to the king with no pawns at all, or with only theirs.
+pub const OPEN_FILE_NEAR_KING_PENALTY: i64 = -25;
+pub const HALF_OPEN_FILE_NEAR_KING_PENALTY: i64 = -12;
+
+// Needs this many pieces going after the king before any attack units count. One piece alone rarely mates.
+pub const MIN_KING_ATTACKERS: usize = 2;
+
+// Indexed by attack units, from `PieceType::king_attack_weight` for each square of the king zone a piece hits. Grows
+// slowly at first, then quickly once several pieces join in.
+pub const KING_ATTACK_PENALTY: [i64; 64] = [
+    0, 0, -1, -2, -3, -5, -7, -9, -12, -15, -18, -22, -26, -30, -35, -39, -44, -50, -56, -62, -68,
+    -75, -82, -85, -89, -97, -105, -113, -122, -131, -140, -150, -169, -180, -191, -202, -213,
+    -225, -237, -248, -260, -272, -283, -295, -307, -319, -330, -342, -354, -366, -377, -389, -401,
+    -412, -424, -436, -448, -459, -471, -483, -494, -500, -500, -500,
+];
+
+// The squares around the king, and one more row towards the enemy.
+pub fn king_zone(king_square: usize, color: Color, c: &Constants) -> u64 {
+    let zone = set_bit(c.king_attacks[king_square], king_square);
+    return match color {
+        Color::White => zone | (zone >> 8),
+        Color::Black => zone | (zone << 8),
+    };
+}
+
+// Shield, storm and open files on the king's file and either side of it. `pawns` is indexed by `Color::idx`.
+pub fn pawn_shelter(king_square: usize, color: Color, pawns: [u64; 2], c: &Constants) -> i64 {
+    let (ours, theirs) = match color {
+        Color::White => (pawns[0], pawns[1]),
+        Color::Black => (pawns[1], pawns[0]),
+    };
+    let king_file = king_square % 8;
+    let king_rank = relative_rank(king_square, color);
+
+    let mut score: i64 = 0;
+    for file in king_file.saturating_sub(1)..=usize::min(king_file + 1, 7) {
+        // Same rank as the king, on this file.
+        let square = king_square - king_file + file;
+        let in_front = c.front_span_masks[color.idx()][square];
+
+        let shield_distance = closest_distance(ours & in_front, king_rank, color);
+        score += match shield_distance {
+            Some(d) if d < PAWN_SHIELD_BONUS.len() => PAWN_SHIELD_BONUS[d],
+            _ => PAWN_SHIELD_BONUS[0],
+        };
+
+        let storm_distance = closest_distance(theirs & in_front, king_rank, color);
+        score += match storm_distance {
+            Some(d) if d < PAWN_STORM_PENALTY.len() => PAWN_STORM_PENALTY[d],
+            _ => PAWN_STORM_PENALTY[0],
+        };
+
+        let file_mask = c.file_masks[file];
+        if (ours | theirs) & file_mask == 0 {
+            score += OPEN_FILE_NEAR_KING_PENALTY;
+        } else if ours & file_mask == 0 {
+            score += HALF_OPEN_FILE_NEAR_KING_PENALTY;
+        }
+    }
+
+    return score;
+}
+
+// Ranks between the king and the nearest pawn in `pawns`, all of which are in front of it.
+fn closest_distance(pawns: u64, king_rank: usize, color: Color) -> Option<usize> {
+    let mut closest: Option<usize> = None;
+    let mut remaining = pawns;
+    while remaining != 0 {
+        let square = get_lsb_index(remaining).expect("Guard clause.");
+        remaining = pop_bit(remaining, square);
+        let distance = relative_rank(square, color) - king_rank;
+        closest = Some(closest.map_or(distance, |c| usize::min(c, distance)));
+    }
+    return closest;
+}
+
+pub fn king_attack_penalty(attack_units: usize, attackers: usize) -> i64 {
+    if attackers < MIN_KING_ATTACKERS {
+        return 0;
+    }
+    return KING_ATTACK_PENALTY[usize::min(attack_units, KING_ATTACK_PENALTY.len() - 1)];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess_game::ChessGame;
+
+    fn game_from_fen<'a>(c: &'a Constants, fen: &str) -> ChessGame<'a> {
+        let mut game = ChessGame::new(c);
+        game.import_fen(fen).unwrap();
+        return game;
+    }
+
+    #[test]
+    fn pawn_shield_and_storm() {
+        let c = Constants::new();
+        let shelter = |fen: &str| {
+            let game = game_from_fen(&c, fen);
+            let pawns = [game.piece_bitboards[0], game.piece_bitboards[6]];
+            let king_square = get_lsb_index(game.piece_bitboards[5]).unwrap();
+            return pawn_shelter(king_square, Color::White, pawns, &c);
+        };
+
+        // f2, g2, h2 untouched, against g3 pushed, and against nothing at all.
+        let intact = shelter("6k1/5ppp/8/8/8/8/5PPP/6K1 w - - 0 1");
+        let pushed = shelter("6k1/5ppp/8/8/8/6P1/5P1P/6K1 w - - 0 1");
+        let bare = shelter("6k1/5ppp/8/8/8/8/8/6K1 w - - 0 1");
+        assert_eq!(intact, 3 * PAWN_SHIELD_BONUS[1]);
+        assert_eq!(pushed, 2 * PAWN_SHIELD_BONUS[1] + PAWN_SHIELD_BONUS[2]);
+        assert_eq!(
+            bare,
+            3 * (PAWN_SHIELD_BONUS[0] + HALF_OPEN_FILE_NEAR_KING_PENALTY)
+        );
+
+        // Black pawns on g4 and h4, two squares from the shield and three from the king.
+        let stormed = shelter("6k1/5p2/8/8/6pp/8/5PPP/6K1 w - - 0 1");
+        assert_eq!(stormed, intact + 2 * PAWN_STORM_PENALTY[3]);
+    }
+
+    #[test]
+    fn king_attacks_need_two_pieces() {
+        assert_eq!(king_attack_penalty(12, 1), 0);
+        assert_eq!(king_attack_penalty(12, 2), KING_ATTACK_PENALTY[12]);
+        assert_eq!(king_attack_penalty(1000, 5), KING_ATTACK_PENALTY[63]);
+    }
+}
@@ -1,10 +1,26 @@
+use crate::backoff::Backoff;
+use crate::bot_config::BotConfig;
+use crate::challenge_policy::{self, ChallengeDecision};
+use crate::game_end_policy::{self, ResignCounter};
+use crate::game_log::{GameLog, SearchStats};
+use crate::lichess_client::LichessClient;
+use crate::lichess_structs::LichessEvent;
+use crate::ndjson::NdjsonDecoder;
+use crate::opening_book::{BookSelection, OpeningBook};
 use crate::r#move::Move;
-use crate::{chess_game, constants, lichess_structs, runtime_calculated_constants};
-use core::str;
-use std::collections::HashMap;
+use crate::time_manager::TimeManager;
+use crate::{chess_game, constants, lichess_structs, runtime_calculated_constants, score};
+use std::collections::{HashMap, HashSet};
 use std::env;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+// Worked out once, the first time a game starts. Boards borrow them on the search threads too, so they live forever.
+static RUNTIME_CONSTANTS: OnceLock<runtime_calculated_constants::Constants> = OnceLock::new();
+
+pub async fn main(args: &[String]) -> Result<(), String> {
+    let config = BotConfig::load(args)?;
 
-pub async fn main() -> Result<(), String> {
     // Try to get the bearer auth token.
     let lichess_auth_token = match env::var("LICHESS_BOT_API_TOKEN") {
         Ok(s) => s,
@@ -16,349 +32,824 @@ pub async fn main() -> Result<(), String> {
     };
 
     println!("Calling run function for Lichess Bot...");
-    run(&lichess_auth_token).await;
-
-    return Ok(());
+    let client = Arc::new(LichessClient::new(&config.lichess_url, &lichess_auth_token));
+    return run(client, Arc::new(config)).await;
 }
 
-async fn play_game(token: &str, game_id: &str, fen: &str) {
-    let lichess_url = format!("https://lichess.org/api/bot/game/stream/{game_id}");
-    let client: reqwest::Client = reqwest::Client::new();
-    let response_result: Result<reqwest::Response, reqwest::Error> =
-        client.get(lichess_url).bearer_auth(token).send().await;
-
-    // TODO: Handle errors with game stream?
-    let mut response = match response_result {
-        Ok(r) => r,
-        Err(e) => {
-            print!("Error: {}", e);
-            return;
-        }
-    };
+async fn play_game(
+    client: &LichessClient,
+    game_id: &str,
+    config: &BotConfig,
+    opening_book: Option<Arc<OpeningBook>>,
+) {
+    let stream_path = format!("/api/bot/game/stream/{game_id}");
+    let mut backoff = Backoff::default();
 
-    // This function will run forever, when calling a streamed API.
     let mut lichess_game: lichess_structs::GameFull = lichess_structs::GameFull::default();
-    let runtime_constant = runtime_calculated_constants::Constants::new();
-    let mut game = chess_game::ChessGame::new(&runtime_constant);
+    let runtime_constant =
+        RUNTIME_CONSTANTS.get_or_init(runtime_calculated_constants::Constants::new);
+    let mut game = chess_game::ChessGame::new(runtime_constant);
     let mut is_bot_white: bool = true;
-    while let Some(chunk) = response.chunk().await.unwrap() {
-        // We just received the '\n' from the API to keep the connection alive. Ignore processing.
-        if chunk.len() == 1 {
-            println!("Play Game Thread: Waiting for new data...");
-            continue;
-        }
 
-        // Attempt to convert bytes to string.
-        let full_str = match str::from_utf8(&chunk) {
-            Ok(s) => s,
+    // Every move our board has had played on it, in long algebraic notation. Compared against what Lichess sends.
+    let mut played_moves: Vec<String> = vec![];
+    game.transposition_table.resize(config.hash_size_mb);
+    game.opening_book = opening_book;
+
+    // Saved when the game ends.
+    let mut game_log = GameLog::default();
+
+    // Our search evaluation from our last move, from our side. Used to answer draw offers.
+    let mut our_evaluation: Option<i64> = None;
+    let mut resign_counter = ResignCounter::default();
+    let mut is_draw_offer_answered = false;
+
+    // When we can claim the win from an opponent who left, and when we give up on an opponent who never moved.
+    let mut claim_victory_at: Option<Instant> = None;
+    let mut abort_at: Option<Instant> = None;
+
+    // Runs until the game is over. If the stream drops, reconnect. Lichess starts every connection with a gameFull.
+    loop {
+        let mut response = match client.open_stream(&stream_path, &mut backoff).await {
+            Ok(r) => r,
             Err(e) => {
-                println!("Unable to convert byte array to string (utf8). Error: {e}");
-                continue;
+                println!("{e}");
+                return;
             }
         };
 
-        // Look through the bytes we got, se expect a few possible items here.
-        if full_str.contains("\"type\":\"opponentGone\"") {
-            println!("Opponent left. Code not set up to handle this.");
-            continue;
-        } else if full_str.contains("\"type\":\"chatLine\"") {
-            let lichess_chat_line_parse_attempt: Result<
-                lichess_structs::ChatLineEvent,
-                serde_json::Error,
-            > = serde_json::from_str(full_str);
-            let chat_event: lichess_structs::ChatLineEvent = match lichess_chat_line_parse_attempt {
-                Ok(g) => g,
-                Err(e) => {
-                    println!("Unable to parse lichess chat line. Error: {e}");
-                    continue;
+        let mut decoder = NdjsonDecoder::new();
+        let mut last_data_at = Instant::now();
+        'read: loop {
+            // Don't wait on the stream forever, the timers at the bottom need checking even when nothing happens.
+            let tick = Duration::from_millis(constants::GAME_TIMER_TICK_MS);
+            let lines = match tokio::time::timeout(tick, response.chunk()).await {
+                Ok(Ok(Some(c))) => {
+                    backoff.reset();
+                    last_data_at = Instant::now();
+                    let lines = decoder.push(&c);
+
+                    // We just received the '\n' from the API to keep the connection alive. Nothing to process.
+                    if lines.is_empty() {
+                        println!("Play Game Thread: Waiting for new data...");
+                    }
+                    lines
+                }
+                Ok(Ok(None)) => {
+                    println!("Game stream closed. Reconnecting...");
+                    break;
+                }
+                Ok(Err(e)) => {
+                    println!("Game stream dropped. Reconnecting... Detail: {e}");
+                    break;
                 }
-            };
 
-            // If player types debug in the chat, print some info to the screen.
-            if chat_event.text == "debug" {
-                game.print_debug_game_state_str();
-                let _ = write_chat_message(
-                    token,
-                    &lichess_game.id,
-                    "Message recieved. Check the console.",
-                )
-                .await;
-            }
-            continue;
-        } else if full_str.contains("\"type\":\"gameFull\"") {
-            println!("Handling game state full...");
-            let lichess_game_parse_attempt: Result<lichess_structs::GameFull, serde_json::Error> =
-                serde_json::from_str(full_str);
-            lichess_game = match lichess_game_parse_attempt {
-                Ok(g) => g,
-                Err(e) => {
-                    println!("Unable to parse lichess full game. Error: {}", e);
-                    continue;
+                // Not even a keep alive. The connection is gone without telling us.
+                Err(_)
+                    if last_data_at.elapsed()
+                        >= Duration::from_secs(constants::STREAM_INACTIVITY_TIMEOUT_S) =>
+                {
+                    println!("Game stream went quiet. Reconnecting...");
+                    break;
                 }
+                Err(_) => vec![],
             };
 
-            // The first time we load the game, we need go get the game state aligned...
-            if lichess_game.initial_fen != "startpos" {
-                println!("Game did not start in the initial condition. Cannot continue.");
-                break;
-            }
+            for line in lines.iter() {
+                let event: LichessEvent = match serde_json::from_str(line) {
+                    Ok(e) => e,
+                    Err(e) => {
+                        println!("Unable to parse lichess game event. Error: {e}\n{line}");
+                        continue;
+                    }
+                };
+
+                match event {
+                    LichessEvent::OpponentGone(opponent_gone) => {
+                        // Lichess counts down while they are gone. Sent again if they come back.
+                        claim_victory_at =
+                            match (opponent_gone.gone, opponent_gone.claim_win_in_seconds) {
+                                (true, Some(s)) if config.claim_victory => {
+                                    println!("Opponent left. We can claim the win in {s} seconds.");
+                                    Some(Instant::now() + Duration::from_secs(s as u64))
+                                }
+                                _ => None,
+                            };
+                        continue;
+                    }
+                    LichessEvent::ChatLine(chat_event) => {
+                        // If player types debug in the chat, print some info to the screen. The biggest evaluation
+                        // terms go back in the chat.
+                        if chat_event.text == "debug" {
+                            game.print_debug_game_state_str();
+                            let trace = game.evaluate_board_traced();
+                            print!("{}", trace.to_table());
+                            let _ = client
+                                .write_chat_message(
+                                    &lichess_game.id,
+                                    &trace.to_summary(constants::DEBUG_CHAT_EVALUATION_TERMS),
+                                )
+                                .await;
+                        }
+                        continue;
+                    }
+                    LichessEvent::GameFull(game_full) => {
+                        println!("Handling game state full...");
+                        lichess_game = game_full;
+
+                        is_bot_white = config.is_bot_user_id(&lichess_game.white.id);
+                        game.is_chess960 = lichess_game.variant.key == "chess960";
+
+                        // Replay every move from where the game started, which may be a custom position. After a
+                        // reconnect, this catches us up on anything we missed.
+                        let moves = lichess_game.state.moves_to_vec();
+                        if let Err(e) =
+                            load_position(&mut game, lichess_game.initial_position_fen(), &moves)
+                        {
+                            println!("Unable to load the game. {e}");
+                            return;
+                        }
+                        played_moves = moves;
+
+                        // Keep what we logged before a reconnect.
+                        if game_log.id != lichess_game.id {
+                            game_log = GameLog::new(&lichess_game, is_bot_white);
+                        }
+                        game_log.sync_moves(&played_moves, &lichess_game.state);
+                    }
+                    LichessEvent::GameState(lichess_game_state) => {
+                        println!("Handling game state...");
+
+                        // Someone made a move. Check the whole move list against ours, not just the newest move.
+                        let moves = lichess_game_state.moves_to_vec();
+                        lichess_game.state = lichess_game_state;
+                        let is_board_changed = match sync_moves(
+                            &mut game,
+                            lichess_game.initial_position_fen(),
+                            &mut played_moves,
+                            &moves,
+                        ) {
+                            Ok(changed) => changed,
+                            Err(e) => {
+                                // Playing on would mean sending moves from a position that isn't the real one.
+                                println!(
+                                    "Our board is out of sync with Lichess. Cannot continue. {e}"
+                                );
+                                return;
+                            }
+                        };
+                        game_log.sync_moves(&played_moves, &lichess_game.state);
+
+                        // Lichess keeps the flag set until the offer is answered or a move is made. Answer it once.
+                        let is_draw_offered = if is_bot_white {
+                            lichess_game.state.bdraw
+                        } else {
+                            lichess_game.state.wdraw
+                        };
+                        if !is_draw_offered {
+                            is_draw_offer_answered = false;
+                        } else if !is_draw_offer_answered && lichess_game.state.status == "started"
+                        {
+                            is_draw_offer_answered = true;
+                            let accept =
+                                game_end_policy::should_accept_draw(our_evaluation, config);
+                            println!("Opponent offers a draw. Accepting: {accept}");
+                            let answer = if accept { "draw/yes" } else { "draw/no" };
+                            match client.post_game_action(&lichess_game.id, answer).await {
+                                // Wait for Lichess to tell us the game is over.
+                                Ok(()) if accept => continue,
+                                Ok(()) => (),
+                                Err(e) => println!("{e}"),
+                            }
+                        }
+
+                        // Same moves as before, like a draw offer or a resignation. Nothing new to respond to.
+                        if !is_board_changed && lichess_game.state.status == "started" {
+                            continue;
+                        }
+
+                        // Print our evaluation after each move.
+                        println!(
+                            "Our evaluation of the position: {}",
+                            score::to_display_str(game.evaluate_board())
+                        );
+                    }
+                    _ => {
+                        println!("Unexpected event type. See what went wrong.\n{line}");
+                        continue;
+                    }
+                }
 
-            is_bot_white = lichess_game.white.id == constants::LICHESS_BOT_USERNAME;
-
-            // Import the FEN, let the rest below handle the rest. Game state is now set.
-            _ = game.import_fen(fen);
-            game.set_legal_moves(None);
-        } else if full_str.contains("\"type\":\"gameState\"") {
-            println!("Handling game state...");
-
-            let lichess_game_state_parse_attempt: Result<
-                lichess_structs::GameState,
-                serde_json::Error,
-            > = serde_json::from_str(full_str);
-            let lichess_game_state = match lichess_game_state_parse_attempt {
-                Ok(g) => g,
-                Err(e) => {
-                    println!("Unable to parse lichess game state. Error: {}", e);
-                    continue;
+                // Checked before the turn, so we don't reconnect to a game that ended on the opponent's move.
+                if lichess_game.state.status != "started" {
+                    println!("Game is over by: {}", lichess_game.state.status);
+
+                    // The game is over, so the board is free to use for writing the PGN.
+                    game_log.finish(&lichess_game.state);
+                    if !config.game_log_dir.is_empty() {
+                        if let Err(e) = game_log.save(&config.game_log_dir, &mut game) {
+                            println!("{e}");
+                        }
+                    }
+                    return;
                 }
-            };
 
-            // Someone made a move. Update the local copy of our board.
-            let moves = lichess_game_state.moves_to_vec();
-            let last_move_str = match moves.last() {
-                Some(m) => m as &str,
-                None => {
-                    println!("Cannot get the most recent move, none exist!");
+                // Lichess lets either side abort until both have moved. Only start the clock while it's on them.
+                let is_opponent_to_move = game.white_to_move != is_bot_white;
+                abort_at = match config.abort_after_s {
+                    Some(s) if played_moves.len() < 2 && is_opponent_to_move => {
+                        abort_at.or(Some(Instant::now() + Duration::from_secs(s)))
+                    }
+                    _ => None,
+                };
+
+                // If we reach this point, see if it's our turn.
+                if is_opponent_to_move {
+                    println!("It is the opponents turn. Waiting for our turn.");
                     continue;
                 }
-            };
 
-            lichess_game.state = lichess_game_state;
+                // Budget our thinking time from the clock.
+                let (time_left_ms, increment_ms) = if is_bot_white {
+                    (lichess_game.state.wtime, lichess_game.state.winc)
+                } else {
+                    (lichess_game.state.btime, lichess_game.state.binc)
+                };
+                game.search_limits =
+                    TimeManager::new(time_left_ms as u64, increment_ms as u64, None)
+                        .search_limits();
+                if let Some(max_move_time_ms) = config.max_move_time_ms {
+                    for limit in [
+                        &mut game.search_limits.soft_time_limit_ms,
+                        &mut game.search_limits.hard_time_limit_ms,
+                    ] {
+                        *limit = limit.map(|l| u128::min(l, max_move_time_ms as u128));
+                    }
+                }
 
-            // TODO: Check for resignation?
+                // We know it is our turn. Run minimax to find a good move. It takes a while, so not on the async
+                // workers. The event stream and the other games keep going.
+                let search_start = Instant::now();
+                let (searched_game, (bot_move, evaluation)) =
+                    tokio::task::spawn_blocking(move || {
+                        let result = game.get_bot_move_debug();
+                        return (game, result);
+                    })
+                    .await
+                    .expect("Search thread panicked.");
+                game = searched_game;
+                our_evaluation = evaluation.map(|e| if is_bot_white { e } else { -e });
+                if let Some(evaluation) = evaluation {
+                    let stats = SearchStats {
+                        evaluation,
+                        depth: game.search_depth,
+                        nodes: game.search_nodes,
+                        time_ms: search_start.elapsed().as_millis() as u64,
+                    };
+                    game_log.set_pending_search(&game.move_to_uci(&bot_move), stats);
+                }
+                println!(
+                    "Bot thinks we should play: {} ({})",
+                    game.move_to_san(&bot_move, true),
+                    game.move_to_uci(&bot_move)
+                );
 
-            // Handle errors later...
-            let last_move = Move::str_to_move(&last_move_str)
-                .expect("API returned a move we could not convert.");
-            let cloned_legal_moves = game.legal_moves.clone();
-            for m in cloned_legal_moves.iter() {
-                if *m == last_move {
-                    game.make_move(m, true);
-                    break;
+                // Give up instead of playing on in a lost position. If that fails, keep playing.
+                if resign_counter.should_resign(our_evaluation, config) {
+                    println!("Position is hopeless. Resigning.");
+                    match client.post_game_action(&lichess_game.id, "resign").await {
+                        Ok(()) => continue,
+                        Err(e) => println!("{e}"),
+                    }
+                }
+
+                // The client already retried. Lichess won't send the position again for a move it never got, so
+                // reconnect. The gameFull we get back starts a new search if it's still our turn.
+                if let Err(e) = client
+                    .make_move(&lichess_game.id, &game.move_to_uci(&bot_move))
+                    .await
+                {
+                    println!("{e}\nReconnecting to try again.");
+                    break 'read;
                 }
             }
 
-            // Print our evaluation after each move.
-            println!("Our evaluation of the position: {}", game.evaluate_board());
-        } else {
-            println!("Unexpected event type. See what went wrong.\n{}", full_str);
-            continue;
+            // Only tried once each. Lichess sends the game over state when they work.
+            if claim_victory_at.is_some_and(|t| Instant::now() >= t) {
+                claim_victory_at = None;
+                println!("Opponent is still gone. Claiming victory.");
+                if let Err(e) = client
+                    .post_game_action(&lichess_game.id, "claim-victory")
+                    .await
+                {
+                    println!("{e}");
+                }
+            }
+            if abort_at.is_some_and(|t| Instant::now() >= t) {
+                abort_at = None;
+                println!("Opponent never made their first move. Aborting the game.");
+                if let Err(e) = client.post_game_action(&lichess_game.id, "abort").await {
+                    println!("{e}");
+                }
+            }
         }
 
-        // If we reach this point, see if it's our turn.
-        if game.white_to_move != is_bot_white {
-            println!("It is the opponents turn. Waiting for our turn.");
-            continue;
-        }
+        tokio::time::sleep(backoff.next_delay()).await;
+    }
+}
 
-        if lichess_game.state.status != "started" {
-            println!("Game is over by: {}", lichess_game.state.status);
-            break;
-        }
+// Sets up the board from a starting FEN and the long algebraic moves played since. Ex: `e2e4 e7e5 g1f3`.
+fn load_position(
+    game: &mut chess_game::ChessGame,
+    fen: &str,
+    moves: &[String],
+) -> Result<(), String> {
+    game.import_fen(fen)?;
+    game.set_legal_moves(None);
+
+    for move_str in moves.iter() {
+        play_move_str(game, move_str)?;
+    }
 
-        // We know it is our turn. Run minimax to find a good move.
-        let bot_move = game.get_bot_move();
-        println!("Bot thinks we should play: {}", bot_move.move_to_str());
+    return Ok(());
+}
 
-        // Try to make the move.
-        let move_result = make_move(token, &lichess_game.id, &bot_move.move_to_str()).await;
+// Brings our board up to date with the full move list from Lichess. Usually that's just the newest move. If our moves
+// aren't the start of theirs (a takeback, or an event we never got) the game is rebuilt from the start instead.
+// Returns whether the board changed. Errors if any move is illegal on our board, the position can't be trusted then.
+fn sync_moves(
+    game: &mut chess_game::ChessGame,
+    initial_fen: &str,
+    played_moves: &mut Vec<String>,
+    moves: &[String],
+) -> Result<bool, String> {
+    if moves == played_moves.as_slice() {
+        return Ok(false);
+    }
 
-        // Handle errors in the console.
-        let _ = match move_result {
-            Ok(_) => (),
-            Err(e) => {
-                println!("{e}");
-                break;
-            }
-        };
+    if moves.starts_with(played_moves) {
+        for move_str in moves[played_moves.len()..].iter() {
+            let san = play_move_str(game, move_str)?;
+            println!("Move played: {san}");
+            played_moves.push(move_str.clone());
+        }
+        return Ok(true);
     }
-}
 
-async fn make_move(token: &str, game_id: &str, r#move: &str) -> Result<(), String> {
-    let lichess_url = format!("https://lichess.org/api/bot/game/{game_id}/move/{move}");
-    let client: reqwest::Client = reqwest::Client::new();
-    let response_result: Result<reqwest::Response, reqwest::Error> =
-        client.post(lichess_url).bearer_auth(token).send().await;
+    println!(
+        "Lichess has {} moves, we have {} that don't match. Rebuilding the board from the move list.",
+        moves.len(),
+        played_moves.len()
+    );
+    load_position(game, initial_fen, moves)?;
+    *played_moves = moves.to_vec();
+    return Ok(true);
+}
 
-    let parsed_response = match response_result {
-        Ok(r) => r,
-        Err(e) => {
-            return Err(e.to_string());
+// Plays one long algebraic move, if it is legal on our board. Returns it as SAN, for logging.
+fn play_move_str(game: &mut chess_game::ChessGame, move_str: &str) -> Result<String, String> {
+    let raw_move = Move::str_to_move(move_str)?;
+    let m = match game.choose_move_from_legal_move(&raw_move) {
+        Some(m) => m,
+        None => {
+            return Err(format!(
+                "Lichess sent a move we think is illegal: `{move_str}`. Position: {}",
+                game.export_fen()
+            ))
         }
     };
+    let san = game.move_to_san(&m, true);
+    game.make_move(&m, true);
+    return Ok(san);
+}
 
-    if parsed_response.status() != 200 {
-        return Err(format!(
-            "Something went wrong trying to make a move: {}.\nAPI response: {:#?}.",
-            r#move,
-            parsed_response.text().await
-        ));
-    }
+// Games that count towards `max_concurrent_games`.
+#[derive(Default)]
+struct ActiveGames {
+    // Challenges we accepted, and when. Lichess hasn't started the game yet. The challenge id becomes the game id.
+    accepted: HashMap<String, Instant>,
 
-    return Ok(());
+    // Lichess sends gameStart again for these if the event stream reconnects.
+    playing: HashSet<String>,
 }
 
-async fn run(token: &str) {
-    let lichess_event_url = "https://lichess.org/api/stream/event";
-    let client: reqwest::Client = reqwest::Client::new();
-    let response_result: Result<reqwest::Response, reqwest::Error> = client
-        .get(lichess_event_url)
-        .bearer_auth(token)
-        .send()
-        .await;
+impl ActiveGames {
+    fn count(&mut self) -> usize {
+        // Challenges can be cancelled after we accept them, and the game never starts.
+        let timeout = Duration::from_secs(constants::ACCEPTED_CHALLENGE_TIMEOUT_S);
+        self.accepted
+            .retain(|_, accepted_at| accepted_at.elapsed() < timeout);
+        return self.accepted.len() + self.playing.len();
+    }
+}
 
-    let mut response = match response_result {
-        Ok(r) => r,
-        Err(e) => {
-            print!("Error: {}", e);
-            return;
-        }
-    };
+// Frees the game's slot when its task ends, even if it panicked.
+struct PlayingGuard {
+    active_games: Arc<Mutex<ActiveGames>>,
+    game_id: String,
+}
 
-    // The API will stream us data.
-    while let Some(chunk) = response.chunk().await.unwrap() {
-        // We just received the '\n' from the API to keep the connection alive. Ignore processing.
-        if chunk.len() == 1 {
-            println!("Main Thread: Waiting for new data...");
-            continue;
-        }
+impl Drop for PlayingGuard {
+    fn drop(&mut self) {
+        let mut active_games = self.active_games.lock().unwrap_or_else(|e| e.into_inner());
+        active_games.playing.remove(&self.game_id);
+    }
+}
 
-        // Attempt to convert bytes to string.
-        let full_str = match str::from_utf8(&chunk) {
-            Ok(s) => s,
-            Err(e) => {
-                println!(
-                    "Unable to convert byte array to string (utf8). Error: {}",
-                    e
-                );
-                continue;
-            }
+// Runs until Lichess rejects our token.
+async fn run(client: Arc<LichessClient>, config: Arc<BotConfig>) -> Result<(), String> {
+    // Play from the opening book while we can, it's better than our search this early. Shared by every game.
+    let mut opening_book: Option<Arc<OpeningBook>> = None;
+    if !config.book_path.is_empty() {
+        let selection = if config.book_best_move {
+            BookSelection::BestWeight
+        } else {
+            BookSelection::WeightedRandom
         };
+        match OpeningBook::load(&config.book_path, selection, config.max_book_depth_ply) {
+            Ok(book) => opening_book = Some(Arc::new(book)),
+            Err(e) => println!("Playing without an opening book. {e}"),
+        }
+    }
 
-        // Look through the bytes we got, se expect a few possible items here.
-        if full_str.contains("\"type\":\"challenge\"") {
-            // Attempt to parse accordingly.
-            let lichess_challenge_raw: Result<lichess_structs::Challenge, serde_json::Error> =
-                serde_json::from_str(full_str);
-            let lichess_challenge = match lichess_challenge_raw {
-                Ok(c) => c.challenge,
-                Err(e) => {
-                    println!("Unable to parse lichess challenge. Error: {}", e);
-                    continue;
+    let active_games: Arc<Mutex<ActiveGames>> = Arc::new(Mutex::new(ActiveGames::default()));
+
+    // The API will stream us data. Runs forever, reconnecting whenever the stream drops.
+    let event_stream_path = "/api/stream/event";
+    let mut backoff = Backoff::default();
+    loop {
+        let mut response = client.open_stream(event_stream_path, &mut backoff).await?;
+
+        let mut decoder = NdjsonDecoder::new();
+        loop {
+            // Not even a keep alive for a while means the connection is gone without telling us.
+            let inactivity_timeout = Duration::from_secs(constants::STREAM_INACTIVITY_TIMEOUT_S);
+            let chunk = match tokio::time::timeout(inactivity_timeout, response.chunk()).await {
+                Ok(Ok(Some(c))) => c,
+                Ok(Ok(None)) => {
+                    println!("Event stream closed. Reconnecting...");
+                    break;
+                }
+                Ok(Err(e)) => {
+                    println!("Event stream dropped. Reconnecting... Detail: {e}");
+                    break;
+                }
+                Err(_) => {
+                    println!("Event stream went quiet. Reconnecting...");
+                    break;
                 }
             };
+            backoff.reset();
+            let lines = decoder.push(&chunk);
 
-            // For now, only accept challenges from me.
-            if !constants::LICHESS_CHALLENGER_WHITELIST
-                .contains(&lichess_challenge.challenger.name.as_str())
-            {
-                println!(
-                    "Challenger {} is not on the whitelist. Ignoring for now.",
-                    lichess_challenge.challenger.name
-                );
+            // We just received the '\n' from the API to keep the connection alive. Nothing to process.
+            if lines.is_empty() {
+                println!("Main Thread: Waiting for new data...");
                 continue;
             }
 
-            // Accept the challenge! This will send another event to this function on success.
-            let _ = accept_challenge(token, &lichess_challenge.id).await;
-        } else if full_str.contains("\"type\":\"gameStart\"") {
-            // Attempt to parse accordingly.
-            let lichess_challenge_start: Result<
-                lichess_structs::ChallengeGameStart,
-                serde_json::Error,
-            > = serde_json::from_str(full_str);
-            let lichess_game_full = match lichess_challenge_start {
-                Ok(c) => c.game,
-                Err(e) => {
-                    println!("Unable to parse lichess challenge. Error: {}", e);
-                    continue;
+            for line in lines.iter() {
+                let event: LichessEvent = match serde_json::from_str(line) {
+                    Ok(e) => e,
+                    Err(e) => {
+                        println!("Unable to parse lichess event. Error: {e}\n{line}");
+                        continue;
+                    }
+                };
+
+                match event {
+                    LichessEvent::Challenge(c) => {
+                        let lichess_challenge = c.challenge;
+
+                        // Challenges we send show up here too.
+                        if config.is_bot_user_id(&lichess_challenge.challenger.id) {
+                            continue;
+                        }
+
+                        let decision = challenge_policy::evaluate_challenge(
+                            &lichess_challenge,
+                            &config,
+                            active_games
+                                .lock()
+                                .expect("Active games lock poisoned.")
+                                .count(),
+                        );
+                        let result = match decision {
+                            // Accept the challenge! This will send another event to this function on success. Until
+                            // then, the challenge takes up a game slot.
+                            ChallengeDecision::Accept => {
+                                let result = client.accept_challenge(&lichess_challenge.id).await;
+                                if result.is_ok() {
+                                    let mut active_games =
+                                        active_games.lock().expect("Active games lock poisoned.");
+                                    active_games
+                                        .accepted
+                                        .insert(lichess_challenge.id.clone(), Instant::now());
+                                }
+                                result
+                            }
+                            ChallengeDecision::Decline(reason) => {
+                                println!(
+                                    "Declining challenge from {}: {}.",
+                                    lichess_challenge.challenger.name,
+                                    reason.to_lichess_str()
+                                );
+                                client
+                                    .decline_challenge(&lichess_challenge.id, reason)
+                                    .await
+                            }
+                        };
+                        if let Err(e) = result {
+                            println!("{e}");
+                        }
+                    }
+                    LichessEvent::GameStart(game_start) => {
+                        let game_id = game_start.game.id;
+
+                        // Already playing it, we just reconnected.
+                        {
+                            let mut active_games =
+                                active_games.lock().expect("Active games lock poisoned.");
+                            active_games.accepted.remove(&game_id);
+                            if !active_games.playing.insert(game_id.clone()) {
+                                continue;
+                            }
+                        }
+
+                        // Make a copy of the client (and everything else) to pass to the thread.
+                        let cloned_client = client.clone();
+                        let cloned_config = config.clone();
+                        let cloned_opening_book = opening_book.clone();
+                        let guard = PlayingGuard {
+                            active_games: active_games.clone(),
+                            game_id: game_id.clone(),
+                        };
+                        tokio::spawn(async move {
+                            let _guard = guard;
+                            println!("Spawning thread to play game...");
+                            play_game(
+                                &cloned_client,
+                                &game_id,
+                                &cloned_config,
+                                cloned_opening_book,
+                            )
+                            .await;
+                        });
+                    }
+                    LichessEvent::GameFinish(game_finish) => {
+                        // The game's own stream sees the final state and ends the game thread.
+                        println!("Game {} finished.", game_finish.game.id);
+                    }
+                    LichessEvent::ChallengeCanceled(c) => {
+                        // Frees the slot, if we had accepted it.
+                        println!("Challenge {} cancelled.", c.challenge.id);
+                        active_games
+                            .lock()
+                            .expect("Active games lock poisoned.")
+                            .accepted
+                            .remove(&c.challenge.id);
+                    }
+                    LichessEvent::ChallengeDeclined(_) => {
+                        println!("Challenge declined event. Not coded to handle this yet.");
+                    }
+                    _ => {
+                        println!("Unexpected event type. See what went wrong.\n{line}");
+                    }
                 }
-            };
-
-            // Make a copy of the token to pass to the thread.
-            let cloned_token = token.to_string();
-            tokio::spawn(async move {
-                println!("Spawning thread to play game...");
-                play_game(&cloned_token, &lichess_game_full.id, &lichess_game_full.fen).await;
-            });
-
-            continue;
-        } else if full_str.contains("\"type\":\"gameFinish\"") {
-            println!("Game finish event. Not coded to handle this yet.");
-            continue;
-        } else if full_str.contains("\"type\":\"challengeCanceled\"") {
-            println!("Challenge cancelled event. Not coded to handle this yet.");
-            continue;
-        } else if full_str.contains("\"type\":\"challengeDeclined\"") {
-            println!("Challenge declined event. Not coded to handle this yet.");
-            continue;
-        } else {
-            println!("Unexpected event type. See what went wrong.\n{}", full_str);
-            continue;
+            }
         }
+
+        tokio::time::sleep(backoff.next_delay()).await;
     }
 }
 
-async fn accept_challenge(token: &str, game_id: &str) -> Result<(), String> {
-    let lichess_url = format!("https://lichess.org/api/challenge/{game_id}/accept");
-    let client: reqwest::Client = reqwest::Client::new();
-    let response_result: Result<reqwest::Response, reqwest::Error> =
-        client.post(lichess_url).bearer_auth(token).send().await;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_lichess::{self, MockLichess};
 
-    let parsed_response = match response_result {
-        Ok(r) => r,
-        Err(e) => {
-            return Err(e.to_string());
-        }
-    };
+    const MOCK_TIMEOUT: Duration = Duration::from_secs(30);
 
-    if parsed_response.status() != 200 {
-        return Err(format!(
-            "Something went wrong trying to accept challenge.\nAPI response: {:#?}.",
-            parsed_response.text().await
-        ));
+    fn to_moves(moves: &str) -> Vec<String> {
+        return moves.split_whitespace().map(|m| m.to_string()).collect();
     }
 
-    return Ok(());
-}
+    #[test]
+    fn sync_moves_follows_lichess() {
+        let runtime_constant = runtime_calculated_constants::Constants::new();
+        let mut game = chess_game::ChessGame::new(&runtime_constant);
+        let fen = constants::INITIAL_GAME_STATE_FEN;
+        let mut played_moves: Vec<String> = vec![];
+        load_position(&mut game, fen, &played_moves).unwrap();
+
+        // One new move, then the same list again.
+        assert!(sync_moves(&mut game, fen, &mut played_moves, &to_moves("e2e4")).unwrap());
+        assert!(!sync_moves(&mut game, fen, &mut played_moves, &to_moves("e2e4")).unwrap());
+
+        // Missed a couple events.
+        assert!(sync_moves(
+            &mut game,
+            fen,
+            &mut played_moves,
+            &to_moves("e2e4 e7e5 g1f3 b8c6")
+        )
+        .unwrap());
+        assert_eq!(played_moves, to_moves("e2e4 e7e5 g1f3 b8c6"));
+        assert_eq!(
+            game.export_fen(),
+            "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3"
+        );
+
+        // Takeback, then a different move.
+        assert!(sync_moves(
+            &mut game,
+            fen,
+            &mut played_moves,
+            &to_moves("e2e4 e7e5 g1f3 g8f6")
+        )
+        .unwrap());
+        assert_eq!(
+            game.export_fen(),
+            "rnbqkb1r/pppp1ppp/5n2/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3"
+        );
+
+        assert!(sync_moves(
+            &mut game,
+            fen,
+            &mut played_moves,
+            &to_moves("e2e4 e7e5 g1f3 g8f6 e1e3")
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn load_from_position() {
+        let runtime_constant = runtime_calculated_constants::Constants::new();
+        let mut game = chess_game::ChessGame::new(&runtime_constant);
+
+        // Lucena position, black to move.
+        let fen = "1K1k4/1P6/8/8/8/8/r7/2R5 b - - 0 1";
+        load_position(&mut game, fen, &to_moves("d8e7 c1e1 e7d6")).unwrap();
+        assert_eq!(game.export_fen(), "1K6/1P6/3k4/8/8/8/r7/4R3 w - - 3 3");
+
+        // Moves from the normal starting position don't work here.
+        assert!(load_position(&mut game, fen, &to_moves("e2e4")).is_err());
+    }
+
+    fn mock_config() -> BotConfig {
+        return BotConfig {
+            username: String::from("botmasterj"),
+            challenger_whitelist: vec![String::from("*")],
+            max_move_time_ms: Some(200),
+            game_log_dir: String::new(),
+            ..BotConfig::default()
+        };
+    }
+
+    async fn start_mock(streams: &[(&str, &str)]) -> MockLichess {
+        let streams = streams
+            .iter()
+            .map(|(path, ndjson)| (path.to_string(), mock_lichess::parse_script(ndjson)))
+            .collect();
+        return MockLichess::start(streams).await;
+    }
 
-async fn write_chat_message(token: &str, game_id: &str, message: &str) -> Result<(), String> {
-    let lichess_url = format!("https://lichess.org/api/bot/game/{game_id}/chat");
-    let mut params = HashMap::new();
-    params.insert("room", "player"); // player/spectator
-    params.insert("text", message);
-
-    let client: reqwest::Client = reqwest::Client::new();
-    let response_result: Result<reqwest::Response, reqwest::Error> = client
-        .post(lichess_url)
-        .bearer_auth(token)
-        .form(&params)
-        .send()
+    #[tokio::test(flavor = "multi_thread")]
+    async fn plays_a_game_against_mock() {
+        let mock = start_mock(&[(
+            "/api/bot/game/stream/game0001",
+            include_str!("../testdata/lichess/game_white_mates.ndjson"),
+        )])
         .await;
+        let client = LichessClient::new(mock.base_url(), "token");
+        let log_dir =
+            std::env::temp_dir().join(format!("chess-engine-games-{}", std::process::id()));
+        let config = BotConfig {
+            game_log_dir: log_dir.to_string_lossy().to_string(),
+            ..mock_config()
+        };
 
-    let parsed_response = match response_result {
-        Ok(r) => r,
-        Err(e) => {
-            return Err(e.to_string());
-        }
-    };
+        // Returns once the stream says the game is over.
+        let result =
+            tokio::time::timeout(MOCK_TIMEOUT, play_game(&client, "game0001", &config, None)).await;
+        assert!(
+            result.is_ok(),
+            "Game never ended. Posts: {:?}",
+            mock.posts()
+        );
+
+        let posts = mock.posts();
+        let paths: Vec<&str> = posts.iter().map(|p| p.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "/api/bot/game/game0001/move/a1a8",
+                "/api/bot/game/game0001/chat"
+            ]
+        );
+        assert!(posts[1].body.contains("room=player"));
+        assert!(posts[1].body.contains("text=Eval"), "{}", posts[1].body);
+
+        let pgn = std::fs::read_to_string(log_dir.join("game0001.pgn")).unwrap();
+        assert!(
+            pgn.contains("1. Ra8# {[%eval #1] [%clk 0:00:59]} 1-0"),
+            "{pgn}"
+        );
+        assert!(log_dir.join("game0001.json").exists());
+        let _ = std::fs::remove_dir_all(&log_dir);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn declines_draw_against_mock() {
+        let mock = start_mock(&[(
+            "/api/bot/game/stream/game0002",
+            include_str!("../testdata/lichess/game_black_declines_draw.ndjson"),
+        )])
+        .await;
+        let client = LichessClient::new(mock.base_url(), "token");
 
-    if parsed_response.status() != 200 {
-        return Err(format!(
-            "Something went wrong trying to write chat message.\nAPI response: {:#?}.",
-            parsed_response.text().await
-        ));
+        // No search yet when the offer comes, so nothing to accept it on.
+        let config = BotConfig {
+            draw_accept_eval_cp: Some(-50),
+            ..mock_config()
+        };
+        let result =
+            tokio::time::timeout(MOCK_TIMEOUT, play_game(&client, "game0002", &config, None)).await;
+        assert!(
+            result.is_ok(),
+            "Game never ended. Posts: {:?}",
+            mock.posts()
+        );
+
+        let paths: Vec<String> = mock.posts().into_iter().map(|p| p.path).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "/api/bot/game/game0002/draw/no",
+                "/api/bot/game/game0002/move/a8a7"
+            ]
+        );
     }
 
-    return Ok(());
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reconnects_after_a_failed_move() {
+        // Lichess turns the move down, and the stream goes quiet. The gameFull after reconnecting gets it searched and
+        // sent again.
+        let path = "/api/bot/game/stream/game0001";
+        let game = include_str!("../testdata/lichess/game_white_mates.ndjson");
+        let game_full = game.lines().find(|l| l.contains("gameFull")).unwrap();
+        let mock = start_mock(&[(path, &format!("{game_full}\n# await POST /never"))]).await;
+        mock.add_stream(path, mock_lichess::parse_script(game));
+        mock.fail_posts("/api/bot/game/game0001/move/a1a8", &[400]);
+
+        let client = LichessClient::new(mock.base_url(), "token");
+        let reconnect_timeout = Duration::from_secs(constants::STREAM_INACTIVITY_TIMEOUT_S / 2);
+        let result = tokio::time::timeout(
+            reconnect_timeout,
+            play_game(&client, "game0001", &mock_config(), None),
+        )
+        .await;
+        assert!(
+            result.is_ok(),
+            "Game never ended. Posts: {:?}",
+            mock.posts()
+        );
+
+        let paths: Vec<String> = mock.posts().into_iter().map(|p| p.path).collect();
+        assert_eq!(
+            paths[..2],
+            [
+                "/api/bot/game/game0001/move/a1a8",
+                "/api/bot/game/game0001/move/a1a8"
+            ]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn answers_challenges_against_mock() {
+        let mock = start_mock(&[(
+            "/api/stream/event",
+            include_str!("../testdata/lichess/events.ndjson"),
+        )])
+        .await;
+        let client = Arc::new(LichessClient::new(mock.base_url(), "token"));
+
+        // The mock only serves the stream once. Reconnecting gets a 401, which stops the bot.
+        let result = tokio::time::timeout(MOCK_TIMEOUT, run(client, Arc::new(mock_config()))).await;
+        assert!(
+            result.is_ok_and(|r| r.is_err()),
+            "Bot never stopped. Posts: {:?}",
+            mock.posts()
+        );
+
+        let posts = mock.posts();
+        assert_eq!(posts.len(), 3);
+        assert_eq!(posts[0].path, "/api/challenge/chal0001/accept");
+        assert_eq!(posts[1].path, "/api/challenge/chal0002/decline");
+        assert_eq!(posts[1].body, "reason=standard");
+
+        // The first game hasn't started, but it still takes our only slot.
+        assert_eq!(posts[2].path, "/api/challenge/chal0003/decline");
+        assert_eq!(posts[2].body, "reason=later");
+    }
 }
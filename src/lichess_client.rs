@@ -0,0 +1,209 @@
+use crate::backoff::Backoff;
+use crate::challenge_policy::DeclineReason;
+use crate::constants;
+use std::time::Duration;
+
+// Every call we make to the Lichess API. Requests go to `base_url`, so tests can point the bot at a mock server.
+pub struct LichessClient {
+    client: reqwest::Client,
+    base_url: String,
+    token: String,
+}
+
+impl LichessClient {
+    pub fn new(base_url: &str, token: &str) -> Self {
+        return LichessClient {
+            client: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+        };
+    }
+
+    fn url(&self, path: &str) -> String {
+        return format!("{}{path}", self.base_url);
+    }
+
+    // Opens one of the streamed endpoints. Connection errors and bad statuses are retried with backoff, and a 429 waits
+    // out the rate limit. Only gives up on a 401, retrying won't fix a bad token.
+    pub async fn open_stream(
+        &self,
+        path: &str,
+        backoff: &mut Backoff,
+    ) -> Result<reqwest::Response, String> {
+        let url = self.url(path);
+        loop {
+            let response = match self.client.get(&url).bearer_auth(&self.token).send().await {
+                Ok(r) => r,
+                Err(e) => {
+                    let delay = backoff.next_delay();
+                    println!("Unable to connect to {url}. Retrying in {delay:?}. Detail: {e}");
+                    tokio::time::sleep(delay).await;
+                    continue;
+                }
+            };
+
+            let status = response.status();
+            if status == reqwest::StatusCode::UNAUTHORIZED {
+                return Err(format!(
+                    "Lichess rejected our token for {url}. Check `LICHESS_BOT_API_TOKEN`.\nAPI response: {status} {:#?}.",
+                    response.text().await
+                ));
+            }
+            if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+                println!(
+                    "Rate limited by Lichess. Waiting {} seconds.",
+                    constants::RATE_LIMIT_WAIT_S
+                );
+                tokio::time::sleep(Duration::from_secs(constants::RATE_LIMIT_WAIT_S)).await;
+                continue;
+            }
+
+            // Even a 404 can come from a proxy having a bad moment.
+            if !status.is_success() {
+                let delay = backoff.next_delay();
+                println!("Lichess returned {status} for {url}. Retrying in {delay:?}.");
+                tokio::time::sleep(delay).await;
+                continue;
+            }
+
+            return Ok(response);
+        }
+    }
+
+    // Every POST goes through here. A 429 waits out the rate limit, and connection and server errors are retried with
+    // backoff, a few times at most. Anything else, like a move Lichess won't take, fails straight away.
+    async fn post(&self, path: &str, form: &[(&str, &str)], what: &str) -> Result<(), String> {
+        let url = self.url(path);
+        let mut backoff = Backoff::new(
+            constants::POST_RETRY_INITIAL_DELAY_MS,
+            constants::POST_RETRY_MAX_DELAY_MS,
+        );
+        let mut attempt = 1;
+        loop {
+            let response_result = self
+                .client
+                .post(&url)
+                .bearer_auth(&self.token)
+                .form(form)
+                .send()
+                .await;
+            let (retry_reason, delay) = match response_result {
+                Ok(response) => {
+                    let status = response.status();
+                    if status.is_success() {
+                        return Ok(());
+                    }
+                    if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+                        (
+                            "rate limited".to_string(),
+                            Duration::from_secs(constants::RATE_LIMIT_WAIT_S),
+                        )
+                    } else if status.is_server_error() {
+                        (format!("Lichess returned {status}"), backoff.next_delay())
+                    } else {
+                        return Err(format!(
+                            "Something went wrong trying to {what}.\nAPI response: {status} {:#?}.",
+                            response.text().await
+                        ));
+                    }
+                }
+                Err(e) => (
+                    format!("unable to connect. Detail: {e}"),
+                    backoff.next_delay(),
+                ),
+            };
+
+            if attempt >= constants::POST_MAX_ATTEMPTS {
+                return Err(format!(
+                    "Gave up trying to {what} after {attempt} attempts, {retry_reason}."
+                ));
+            }
+            attempt += 1;
+            println!("Unable to {what}, {retry_reason}. Retrying in {delay:?}.");
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    pub async fn make_move(&self, game_id: &str, r#move: &str) -> Result<(), String> {
+        return self
+            .post(
+                &format!("/api/bot/game/{game_id}/move/{move}"),
+                &[],
+                &format!("make a move: {move}"),
+            )
+            .await;
+    }
+
+    // Everything we can do to a game besides moving, like `resign`, `abort`, `claim-victory` or `draw/yes`.
+    // Ref: https://lichess.org/api#tag/Bot
+    pub async fn post_game_action(&self, game_id: &str, action: &str) -> Result<(), String> {
+        return self
+            .post(&format!("/api/bot/game/{game_id}/{action}"), &[], action)
+            .await;
+    }
+
+    pub async fn accept_challenge(&self, game_id: &str) -> Result<(), String> {
+        return self
+            .post(
+                &format!("/api/challenge/{game_id}/accept"),
+                &[],
+                "accept challenge",
+            )
+            .await;
+    }
+
+    pub async fn decline_challenge(
+        &self,
+        game_id: &str,
+        reason: DeclineReason,
+    ) -> Result<(), String> {
+        return self
+            .post(
+                &format!("/api/challenge/{game_id}/decline"),
+                &[("reason", reason.to_lichess_str())],
+                "decline challenge",
+            )
+            .await;
+    }
+
+    pub async fn write_chat_message(&self, game_id: &str, message: &str) -> Result<(), String> {
+        // player/spectator
+        return self
+            .post(
+                &format!("/api/bot/game/{game_id}/chat"),
+                &[("room", "player"), ("text", message)],
+                "write chat message",
+            )
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_lichess::MockLichess;
+    use std::collections::HashMap;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn retries_posts_on_server_errors() {
+        let mock = MockLichess::start(HashMap::new()).await;
+        let client = LichessClient::new(mock.base_url(), "token");
+
+        // Two server errors, then it goes through.
+        mock.fail_posts("/api/bot/game/game0001/move/e2e4", &[503, 500]);
+        assert_eq!(client.make_move("game0001", "e2e4").await, Ok(()));
+        assert_eq!(mock.posts().len(), 3);
+
+        // Retrying an illegal move won't help.
+        mock.fail_posts("/api/bot/game/game0001/move/e2e5", &[400]);
+        assert!(client.make_move("game0001", "e2e5").await.is_err());
+        assert_eq!(mock.posts().len(), 4);
+
+        // Forms still make it through.
+        client
+            .write_chat_message("game0001", "good game")
+            .await
+            .unwrap();
+        assert_eq!(mock.posts()[4].body, "room=player&text=good+game");
+    }
+}
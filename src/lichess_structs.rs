@@ -1,12 +1,16 @@
 // This will have all the struct definitions we will need to run the bot.
+use crate::constants;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct UserBasic {
     pub id: String,
     pub name: String,
     pub title: Option<String>,
     pub rating: u32,
+
+    // Lichess leaves this out unless it is true.
+    #[serde(default)]
     pub provisional: bool,
 }
 
@@ -25,13 +29,21 @@ impl Default for UserBasic {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct GameState {
-    pub r#type: String,
     pub moves: String,
     pub wtime: u32,
     pub btime: u32,
     pub winc: u32,
     pub binc: u32,
     pub status: String,
+
+    // True while that side is offering a draw.
+    #[serde(default)]
+    pub wdraw: bool,
+    #[serde(default)]
+    pub bdraw: bool,
+
+    // `white` or `black`, once someone has won.
+    pub winner: Option<String>,
 }
 
 impl GameState {
@@ -61,21 +73,23 @@ impl GameState {
 impl Default for GameState {
     fn default() -> Self {
         GameState {
-            r#type: String::new(),
             moves: String::new(),
             wtime: 0,
             btime: 0,
             winc: 0,
             binc: 0,
             status: String::new(),
+            wdraw: false,
+            bdraw: false,
+            winner: None,
         }
     }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct GameFull {
-    pub r#type: String,
     pub id: String,
+    pub variant: Variant,
     pub white: UserBasic,
     pub black: UserBasic,
     pub state: GameState,
@@ -83,25 +97,59 @@ pub struct GameFull {
     // Lichess API gives us CAMEL CASE. So we fix it.
     #[serde(rename = "initialFen")]
     pub initial_fen: String,
+
+    #[serde(default)]
+    pub rated: bool,
+
+    // ultraBullet, bullet, blitz, rapid, classical, correspondence.
+    #[serde(default)]
+    pub speed: String,
+
+    // Not set for correspondence and unlimited games.
+    pub clock: Option<GameClock>,
+
+    // Milliseconds since the Unix epoch.
+    #[serde(rename = "createdAt", default)]
+    pub created_at: u64,
+}
+
+// Both in milliseconds.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GameClock {
+    pub initial: u32,
+    pub increment: u32,
+}
+
+impl GameFull {
+    // Lichess sends `startpos` for the normal starting position, or the FEN the game started from.
+    pub fn initial_position_fen(&self) -> &str {
+        if self.initial_fen == "startpos" {
+            return constants::INITIAL_GAME_STATE_FEN;
+        }
+        return &self.initial_fen;
+    }
 }
 
 // Implement the Default trait for Piece
 impl Default for GameFull {
     fn default() -> Self {
         GameFull {
-            r#type: String::new(),
             id: String::new(),
+            variant: Variant::default(),
             white: UserBasic::default(),
             black: UserBasic::default(),
             state: GameState::default(),
             initial_fen: String::new(),
+            rated: false,
+            speed: String::new(),
+            clock: None,
+            created_at: 0,
         }
     }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Challenge {
-    pub r#type: String,
     pub challenge: InnerChallenge,
 }
 
@@ -117,11 +165,48 @@ pub struct InnerChallenge {
     // Lichess API gives us CAMEL CASE. So we fix it.
     #[serde(rename = "destUser")]
     pub dest_user: UserBasic,
+
+    pub variant: Variant,
+    pub rated: bool,
+
+    // ultraBullet, bullet, blitz, rapid, classical, correspondence.
+    pub speed: String,
+
+    #[serde(rename = "timeControl")]
+    pub time_control: TimeControl,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-pub struct ChallengeGameStart {
+pub struct Variant {
+    // standard, chess960, fromPosition, etc.
+    pub key: String,
+    pub name: String,
+}
+
+impl Default for Variant {
+    fn default() -> Self {
+        Variant {
+            key: String::from("standard"),
+            name: String::from("Standard"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TimeControl {
+    // clock, correspondence or unlimited. Only the fields for that type are set.
     pub r#type: String,
+
+    // Clock: initial time and increment, in seconds.
+    pub limit: Option<u32>,
+    pub increment: Option<u32>,
+
+    #[serde(rename = "daysPerTurn")]
+    pub days_per_turn: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChallengeGameStart {
     pub game: InnerChallengeGameStart,
 }
 
@@ -138,14 +223,123 @@ pub struct InnerChallengeGameStart {
     #[serde(rename = "isMyTurn")]
     pub is_my_turn: bool,
 
-    #[serde(rename = "lastMove")]
+    // Missing before the first move.
+    #[serde(rename = "lastMove", default)]
     pub last_move: String,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ChatLineEvent {
-    pub r#type: String,
     pub username: String,
     pub text: String,
     pub room: String,
 }
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OpponentGoneEvent {
+    pub gone: bool,
+
+    // Only set while the opponent is gone.
+    #[serde(rename = "claimWinInSeconds")]
+    pub claim_win_in_seconds: Option<u32>,
+}
+
+// Every event the Lichess event stream and game streams send. The `type` field says which one it is.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum LichessEvent {
+    // Game stream.
+    GameFull(GameFull),
+    GameState(GameState),
+    ChatLine(ChatLineEvent),
+    OpponentGone(OpponentGoneEvent),
+
+    // Event stream.
+    Challenge(Challenge),
+    GameStart(ChallengeGameStart),
+
+    // Same shape as gameStart.
+    GameFinish(ChallengeGameStart),
+    ChallengeCanceled(Challenge),
+    ChallengeDeclined(Challenge),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_events() {
+        let game_full = r#"{"type":"gameFull","id":"abc123","rated":false,"variant":{"key":"chess960","name":"Chess960","short":"960"},
+            "clock":{"initial":300000,"increment":3000},"speed":"blitz","createdAt":1700000000000,
+            "white":{"id":"botmasterj","name":"botmasterj","title":"BOT","rating":1800},
+            "black":{"id":"someone","name":"Someone","rating":1500,"provisional":true},
+            "initialFen":"startpos",
+            "state":{"type":"gameState","moves":"e2e4","wtime":300000,"btime":300000,"winc":0,"binc":0,"status":"started"}}"#;
+        match serde_json::from_str(game_full).unwrap() {
+            LichessEvent::GameFull(mut g) => {
+                assert_eq!(g.state.moves_to_vec(), vec!["e2e4"]);
+                assert!(g.black.provisional);
+                assert_eq!(g.variant.key, "chess960");
+                assert_eq!(g.clock.as_ref().map(|c| c.increment), Some(3000));
+                assert_eq!(g.initial_position_fen(), constants::INITIAL_GAME_STATE_FEN);
+                g.initial_fen = String::from("8/8/8/4k3/8/8/4P3/4K3 w - - 0 1");
+                assert_eq!(g.initial_position_fen(), "8/8/8/4k3/8/8/4P3/4K3 w - - 0 1");
+            }
+            e => panic!("Wrong event: {e:?}"),
+        }
+
+        let game_state = r#"{"type":"gameState","moves":"e2e4 e7e5","wtime":1,"btime":2,"winc":0,"binc":0,"status":"started","bdraw":true}"#;
+        match serde_json::from_str(game_state).unwrap() {
+            LichessEvent::GameState(s) => assert!(s.bdraw && !s.wdraw),
+            e => panic!("Wrong event: {e:?}"),
+        }
+
+        let chat_line =
+            r#"{"type":"chatLine","username":"someone","text":"debug","room":"player"}"#;
+        assert!(matches!(
+            serde_json::from_str(chat_line).unwrap(),
+            LichessEvent::ChatLine(_)
+        ));
+
+        let opponent_gone = r#"{"type":"opponentGone","gone":true,"claimWinInSeconds":8}"#;
+        match serde_json::from_str(opponent_gone).unwrap() {
+            LichessEvent::OpponentGone(o) => assert_eq!(o.claim_win_in_seconds, Some(8)),
+            e => panic!("Wrong event: {e:?}"),
+        }
+
+        let game_start = r#"{"type":"gameStart","game":{"id":"abc123","color":"white","fen":"rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "source":"friend","hasMoved":false,"isMyTurn":true}}"#;
+        assert!(matches!(
+            serde_json::from_str(game_start).unwrap(),
+            LichessEvent::GameStart(_)
+        ));
+        let game_finish = game_start.replace("gameStart", "gameFinish");
+        assert!(matches!(
+            serde_json::from_str(&game_finish).unwrap(),
+            LichessEvent::GameFinish(_)
+        ));
+
+        let challenge = r#"{"type":"challenge","challenge":{"id":"abc123","url":"https://lichess.org/abc123","status":"created",
+            "challenger":{"id":"someone","name":"Someone","rating":1500},
+            "destUser":{"id":"botmasterj","name":"botmasterj","title":"BOT","rating":1800},
+            "variant":{"key":"standard","name":"Standard"},"rated":false,"speed":"blitz",
+            "timeControl":{"type":"clock","limit":300,"increment":3,"show":"5+3"}}}"#;
+        assert!(matches!(
+            serde_json::from_str(challenge).unwrap(),
+            LichessEvent::Challenge(_)
+        ));
+        let canceled = challenge.replace("\"challenge\",", "\"challengeCanceled\",");
+        assert!(matches!(
+            serde_json::from_str(&canceled).unwrap(),
+            LichessEvent::ChallengeCanceled(_)
+        ));
+        let declined = challenge.replace("\"challenge\",", "\"challengeDeclined\",");
+        assert!(matches!(
+            serde_json::from_str(&declined).unwrap(),
+            LichessEvent::ChallengeDeclined(_)
+        ));
+
+        assert!(serde_json::from_str::<LichessEvent>(r#"{"type":"somethingNew"}"#).is_err());
+    }
+}
@@ -1,19 +1,79 @@
+pub mod backoff;
+pub mod bot_config;
 pub mod castle_sides;
+pub mod challenge_policy;
 pub mod chess_game;
 pub mod color;
 pub mod constants;
+pub mod evaluation_trace;
+pub mod game_end_policy;
+pub mod game_log;
 pub mod helpers;
+pub mod king_safety;
 pub mod lichess;
+pub mod lichess_client;
 pub mod lichess_structs;
+#[cfg(test)]
+pub mod mock_lichess;
 pub mod r#move;
+pub mod ndjson;
+pub mod opening_book;
+pub mod pawn_hash_table;
+pub mod pawn_structure;
+pub mod perft;
+pub mod pgn;
+pub mod piece_activity;
 pub mod piece_type;
+pub mod polyglot_keys;
 pub mod runtime_calculated_constants;
+pub mod score;
+pub mod search_limits;
+pub mod time_manager;
+pub mod transposition_table;
 pub mod transposition_table_entry;
+pub mod uci;
 
 #[tokio::main]
 async fn main() {
-    // Lichess bot.
-    let _ = lichess::main().await;
+    let args: Vec<String> = std::env::args().collect();
+
+    match args.get(1).map(|s| s.as_str()) {
+        // Move generator testing: `cargo run --release -- perft <depth> [fen]`.
+        Some("perft") => {
+            if let Err(e) = perft::main(&args[2..]) {
+                println!("{e}");
+            }
+        }
+
+        // Why the engine likes a position, term by term: `cargo run --release -- eval [fen]`.
+        Some("eval") => {
+            if let Err(e) = evaluation_trace::main(&args[2..]) {
+                println!("{e}");
+            }
+        }
+
+        // Replay a saved game: `cargo run --release -- pgn <file>`.
+        Some("pgn") => {
+            if let Err(e) = pgn::main(&args[2..]) {
+                println!("{e}");
+            }
+        }
+
+        // Run as a UCI engine, for chess GUIs: `cargo run --release -- uci`.
+        Some("uci") => uci::main(),
+
+        // Lichess bot: `cargo run --release -- [lichess] [--config <file>] [--<setting> <value>]...`.
+        Some("lichess") => {
+            if let Err(e) = lichess::main(&args[2..]).await {
+                println!("{e}");
+            }
+        }
+        _ => {
+            if let Err(e) = lichess::main(&args[1..]).await {
+                println!("{e}");
+            }
+        }
+    }
 
     // Testing iterative deepening.
     // let c = runtime_calculated_constants::Constants::new();
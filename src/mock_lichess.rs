@@ -0,0 +1,245 @@
+// A stand-in for the Lichess API, for testing the bot without a network. Serves recorded NDJSON streams and records the
+// bot's POSTs. Only speaks enough HTTP/1.1 for reqwest.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::TcpListener;
+use tokio::sync::Notify;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum StreamStep {
+    // One line of the stream. Empty for the keep alive Lichess sends.
+    Line(String),
+
+    // Hold the stream until the bot has posted to this path. Ex: waiting for its move before sending the next state.
+    AwaitPost(String),
+}
+
+// A recorded stream. Every line is sent as is, except `# await POST <path>` lines, which become `AwaitPost`.
+pub fn parse_script(ndjson: &str) -> Vec<StreamStep> {
+    let mut steps: Vec<StreamStep> = vec![];
+    for line in ndjson.lines() {
+        let line = line.trim();
+        if let Some(path) = line.strip_prefix("# await POST ") {
+            steps.push(StreamStep::AwaitPost(path.trim().to_string()));
+        } else if !line.starts_with('#') {
+            steps.push(StreamStep::Line(line.to_string()));
+        }
+    }
+    return steps;
+}
+
+#[derive(Clone, Debug)]
+pub struct RecordedPost {
+    pub path: String,
+
+    // Form encoded, like `room=player&text=hi`.
+    pub body: String,
+}
+
+struct MockState {
+    // Each script is served once, in order. Asking again gets a 401, which makes the bot stop.
+    streams: Mutex<HashMap<String, Vec<Vec<StreamStep>>>>,
+    posts: Mutex<Vec<RecordedPost>>,
+    new_post: Notify,
+
+    // Statuses to answer the next POSTs to a path with, instead of 200. Used up in order.
+    post_failures: Mutex<HashMap<String, Vec<u16>>>,
+}
+
+impl MockState {
+    fn has_post(&self, path: &str) -> bool {
+        return self.posts.lock().unwrap().iter().any(|p| p.path == path);
+    }
+
+    async fn wait_for_post(&self, path: &str) {
+        loop {
+            // Made before checking, so a post in between isn't missed.
+            let notified = self.new_post.notified();
+            if self.has_post(path) {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+pub struct MockLichess {
+    base_url: String,
+    state: Arc<MockState>,
+}
+
+impl MockLichess {
+    // Listens on a free local port. `streams` maps a path, like `/api/stream/event`, to what it sends.
+    pub async fn start(streams: HashMap<String, Vec<StreamStep>>) -> MockLichess {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let state = Arc::new(MockState {
+            streams: Mutex::new(
+                streams
+                    .into_iter()
+                    .map(|(path, steps)| (path, vec![steps]))
+                    .collect(),
+            ),
+            posts: Mutex::new(vec![]),
+            new_post: Notify::new(),
+            post_failures: Mutex::new(HashMap::new()),
+        });
+
+        let server_state = state.clone();
+        tokio::spawn(async move {
+            loop {
+                let (socket, _) = match listener.accept().await {
+                    Ok(s) => s,
+                    Err(_) => return,
+                };
+                let connection_state = server_state.clone();
+                tokio::spawn(async move {
+                    let (read_half, mut write_half) = socket.into_split();
+                    let mut reader = BufReader::new(read_half);
+
+                    // reqwest keeps connections alive, so keep reading requests until it hangs up.
+                    while let Some((method, path, body)) = read_request(&mut reader).await {
+                        let is_ok = if method == "POST" {
+                            handle_post(&connection_state, path, body, &mut write_half).await
+                        } else {
+                            handle_stream(&connection_state, &path, &mut write_half).await
+                        };
+                        if !is_ok {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+
+        return MockLichess { base_url, state };
+    }
+
+    pub fn base_url(&self) -> &str {
+        return &self.base_url;
+    }
+
+    pub fn posts(&self) -> Vec<RecordedPost> {
+        return self.state.posts.lock().unwrap().clone();
+    }
+
+    // Served the next time `path` is opened, after the ones before it. Ex: what the bot sees after reconnecting.
+    pub fn add_stream(&self, path: &str, steps: Vec<StreamStep>) {
+        self.state
+            .streams
+            .lock()
+            .unwrap()
+            .entry(path.to_string())
+            .or_default()
+            .push(steps);
+    }
+
+    // The next POSTs to `path` get these statuses, one each, before it goes back to 200. They're still recorded.
+    pub fn fail_posts(&self, path: &str, statuses: &[u16]) {
+        self.state
+            .post_failures
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), statuses.to_vec());
+    }
+}
+
+// Returns the method, path and body. `None` once the connection is closed.
+async fn read_request<R: AsyncBufReadExt + Unpin>(
+    reader: &mut R,
+) -> Option<(String, String, String)> {
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).await.ok()? == 0 {
+        return None;
+    }
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let path = parts.next()?.to_string();
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).await.ok()? == 0 {
+            return None;
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().ok()?;
+            }
+        }
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).await.ok()?;
+    return Some((method, path, String::from_utf8_lossy(&body).to_string()));
+}
+
+async fn handle_post(
+    state: &MockState,
+    path: String,
+    body: String,
+    writer: &mut OwnedWriteHalf,
+) -> bool {
+    let failure = match state.post_failures.lock().unwrap().get_mut(&path) {
+        Some(statuses) if !statuses.is_empty() => Some(statuses.remove(0)),
+        _ => None,
+    };
+    state
+        .posts
+        .lock()
+        .unwrap()
+        .push(RecordedPost { path, body });
+    state.new_post.notify_waiters();
+
+    let (status_line, body) = match failure {
+        Some(status) => (format!("{status} Mock Failure"), r#"{"error":"mock"}"#),
+        None => (String::from("200 OK"), r#"{"ok":true}"#),
+    };
+    let response = format!(
+        "HTTP/1.1 {status_line}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    );
+    return writer.write_all(response.as_bytes()).await.is_ok();
+}
+
+async fn handle_stream(state: &MockState, path: &str, writer: &mut OwnedWriteHalf) -> bool {
+    let steps = match state.streams.lock().unwrap().get_mut(path) {
+        Some(scripts) if !scripts.is_empty() => Some(scripts.remove(0)),
+        _ => None,
+    };
+    let steps = match steps {
+        Some(s) => s,
+        None => {
+            let response = "HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\n\r\n";
+            return writer.write_all(response.as_bytes()).await.is_ok();
+        }
+    };
+
+    let head = "HTTP/1.1 200 OK\r\nContent-Type: application/x-ndjson\r\nTransfer-Encoding: chunked\r\n\r\n";
+    if writer.write_all(head.as_bytes()).await.is_err() {
+        return false;
+    }
+
+    // One chunk per line, like Lichess sends them.
+    for step in steps.iter() {
+        match step {
+            StreamStep::Line(line) => {
+                let chunk = format!("{:x}\r\n{line}\n\r\n", line.len() + 1);
+                if writer.write_all(chunk.as_bytes()).await.is_err() {
+                    return false;
+                }
+                let _ = writer.flush().await;
+            }
+            StreamStep::AwaitPost(path) => state.wait_for_post(path).await,
+        }
+    }
+
+    // The end of the stream, the same as Lichess closing it.
+    return writer.write_all(b"0\r\n\r\n").await.is_ok();
+}
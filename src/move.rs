@@ -20,7 +20,7 @@ pub struct Move {
     // Pawn promotion.
     pub pawn_promoting_to: Option<PieceType>,
 
-    // Castling
+    // Castling. Stored as the king capturing its own rook (`to_square` is the rook), so Chess960 castling fits too.
     pub castle_side: Option<CastleSides>,
 
     // Populated later, used for move sorting.
@@ -51,6 +51,22 @@ impl Move {
         };
     }
 
+    // Where the moving piece ends up. The same as `to_square`, except castling, where the king lands next to the rook.
+    pub fn destination_square(&self) -> usize {
+        return match self.castle_side {
+            Some(side) => self.to_square / 8 * 8 + side.king_to_file(),
+            None => self.to_square,
+        };
+    }
+
+    // Where the rook ends up when castling.
+    pub fn castling_rook_destination_square(&self) -> Option<usize> {
+        return self
+            .castle_side
+            .map(|side| self.to_square / 8 * 8 + side.rook_to_file());
+    }
+
+    // Standard long algebraic notation. Castling is the king's move, `e1g1`.
     pub fn move_to_str(&self) -> String {
         let extra_char: String = match self.pawn_promoting_to {
             Some(t) => t.to_char_side_agnostic().to_string(),
@@ -59,11 +75,23 @@ impl Move {
         return format!(
             "{}{}{}",
             square_to_coord(self.from_square),
-            square_to_coord(self.to_square),
+            square_to_coord(self.destination_square()),
             extra_char
         );
     }
 
+    // Chess960 long algebraic notation. Castling is the king taking its own rook, `e1h1`. Otherwise the same.
+    pub fn move_to_str_chess960(&self) -> String {
+        if self.castle_side.is_none() {
+            return self.move_to_str();
+        }
+        return format!(
+            "{}{}",
+            square_to_coord(self.from_square),
+            square_to_coord(self.to_square)
+        );
+    }
+
     pub fn str_to_move(text: &str) -> Result<Move, String> {
         if text.len() != 4 && text.len() != 5 {
             return Err(format!(
@@ -103,6 +131,7 @@ impl Move {
 }
 
 // When comparing moves, we only care about the `from` and `to` and promotion. The other fields are for other parts of the program.
+// Castling only matches the king taking its own rook, use `ChessGame::choose_move_from_legal_move` to match `e1g1` too.
 impl PartialEq for Move {
     fn eq(&self, other: &Self) -> bool {
         return self.from_square == other.from_square
@@ -0,0 +1,58 @@
+// Lichess streams newline delimited JSON, one event per line. Network chunks don't line up with the lines: an event
+// can be split across chunks, and one chunk can hold several events. This puts the lines back together.
+pub struct NdjsonDecoder {
+    // Bytes after the last newline, waiting for the rest of their line.
+    buffer: Vec<u8>,
+}
+
+impl NdjsonDecoder {
+    pub fn new() -> Self {
+        return NdjsonDecoder { buffer: vec![] };
+    }
+
+    // Returns every line completed by this chunk. Empty lines (keep alives) are skipped.
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut lines: Vec<String> = vec![];
+        while let Some(newline_index) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line_bytes: Vec<u8> = self.buffer.drain(..=newline_index).collect();
+
+            // Only whole lines are decoded, so multi-byte characters can't be cut in half.
+            let line = String::from_utf8_lossy(&line_bytes).trim().to_string();
+            if !line.is_empty() {
+                lines.push(line);
+            }
+        }
+
+        return lines;
+    }
+}
+
+impl Default for NdjsonDecoder {
+    fn default() -> Self {
+        NdjsonDecoder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_and_joined_lines() {
+        let mut decoder = NdjsonDecoder::new();
+        assert!(decoder.push(b"\n").is_empty());
+        assert!(decoder.push(b"{\"type\":\"gameSt").is_empty());
+        assert_eq!(
+            decoder.push(b"ate\"}\n{\"a\":1}\n\n{\"b\":"),
+            vec!["{\"type\":\"gameState\"}", "{\"a\":1}"]
+        );
+        assert_eq!(decoder.push(b"2}\r\n"), vec!["{\"b\":2}"]);
+
+        // A character split across chunks.
+        let bytes = "\"é\"\n".as_bytes();
+        assert!(decoder.push(&bytes[..2]).is_empty());
+        assert_eq!(decoder.push(&bytes[2..]), vec!["\"é\""]);
+    }
+}
@@ -0,0 +1,328 @@
+use crate::chess_game::ChessGame;
+use crate::color::Color;
+use crate::constants;
+use crate::helpers::{get_lsb_index, pop_bit};
+use crate::piece_type::PieceType;
+use crate::polyglot_keys::*;
+use crate::r#move::Move;
+use rand::Rng;
+
+// Polyglot `.bin` opening books. Format: http://hgm.nubati.net/book_format.html
+
+// Each entry is 16 bytes, big endian: key (8), move (2), weight (2), learn (4).
+const ENTRY_SIZE: usize = 16;
+
+#[derive(Copy, Clone, Debug)]
+pub struct BookEntry {
+    pub key: u64,
+    pub raw_move: u16,
+    pub weight: u16,
+    pub learn: u32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BookSelection {
+    // Pick moves in proportion to their weight. Keeps the bot from playing the same game every time.
+    WeightedRandom,
+    BestWeight,
+}
+
+#[derive(Clone)]
+pub struct OpeningBook {
+    // Sorted by key, like the file.
+    pub entries: Vec<BookEntry>,
+    pub selection: BookSelection,
+
+    // Stop using the book after this many half moves from the start of the game.
+    pub max_depth_ply: u32,
+}
+
+// No moves. Used to hold the book options until a book is loaded.
+impl Default for OpeningBook {
+    fn default() -> Self {
+        OpeningBook {
+            entries: vec![],
+            selection: BookSelection::WeightedRandom,
+            max_depth_ply: constants::DEFAULT_MAX_BOOK_DEPTH_PLY,
+        }
+    }
+}
+
+impl OpeningBook {
+    pub fn load(path: &str, selection: BookSelection, max_depth_ply: u32) -> Result<Self, String> {
+        let bytes = match std::fs::read(path) {
+            Ok(b) => b,
+            Err(e) => return Err(format!("Unable to read opening book `{path}`. Detail: {e}")),
+        };
+        return OpeningBook::from_bytes(&bytes, selection, max_depth_ply);
+    }
+
+    pub fn from_bytes(
+        bytes: &[u8],
+        selection: BookSelection,
+        max_depth_ply: u32,
+    ) -> Result<Self, String> {
+        if !bytes.len().is_multiple_of(ENTRY_SIZE) {
+            return Err(format!(
+                "Opening book size should be a multiple of {ENTRY_SIZE} bytes. Got: {}.",
+                bytes.len()
+            ));
+        }
+
+        let mut entries: Vec<BookEntry> = bytes
+            .chunks_exact(ENTRY_SIZE)
+            .map(|chunk| BookEntry {
+                key: u64::from_be_bytes(chunk[0..8].try_into().expect("Chunk is 16 bytes.")),
+                raw_move: u16::from_be_bytes(chunk[8..10].try_into().expect("Chunk is 16 bytes.")),
+                weight: u16::from_be_bytes(chunk[10..12].try_into().expect("Chunk is 16 bytes.")),
+                learn: u32::from_be_bytes(chunk[12..16].try_into().expect("Chunk is 16 bytes.")),
+            })
+            .collect();
+
+        // Books should already be sorted, but the lookup depends on it.
+        entries.sort_by_key(|e| e.key);
+
+        return Ok(OpeningBook {
+            entries,
+            selection,
+            max_depth_ply,
+        });
+    }
+
+    // All entries for a position.
+    pub fn probe(&self, key: u64) -> &[BookEntry] {
+        let start = self.entries.partition_point(|e| e.key < key);
+        let end = self.entries.partition_point(|e| e.key <= key);
+        return &self.entries[start..end];
+    }
+
+    // A legal book move for the current position, if we have one and are still within the book depth.
+    pub fn choose_move(&self, game: &mut ChessGame) -> Option<Move> {
+        // Some FENs number the first move 0.
+        let ply =
+            game.fullmove_number.saturating_sub(1) * 2 + if game.white_to_move { 0 } else { 1 };
+        if ply >= self.max_depth_ply {
+            return None;
+        }
+
+        // Skip moves we can't play, a book can have entries for illegal moves if the key collides.
+        let mut candidates: Vec<(Move, u32)> = vec![];
+        for entry in self.probe(polyglot_key(game)).iter() {
+            if entry.weight == 0 {
+                continue;
+            }
+            if let Some(m) = decode_move(game, entry.raw_move) {
+                candidates.push((m, entry.weight as u32));
+            }
+        }
+
+        if candidates.is_empty() {
+            return None;
+        }
+
+        let chosen = match self.selection {
+            BookSelection::BestWeight => candidates.iter().max_by_key(|(_, weight)| *weight),
+            BookSelection::WeightedRandom => {
+                let total_weight: u32 = candidates.iter().map(|(_, weight)| weight).sum();
+                let mut roll = rand::thread_rng().gen_range(0..total_weight);
+                candidates.iter().find(|(_, weight)| {
+                    if roll < *weight {
+                        return true;
+                    }
+                    roll -= weight;
+                    return false;
+                })
+            }
+        };
+
+        return chosen.map(|(m, _)| *m);
+    }
+}
+
+// Polyglot counts squares from a1 = 0. We count from a8 = 0. Flipping the rank works both ways.
+fn flip_square_rank(square: usize) -> usize {
+    return (7 - square / 8) * 8 + square % 8;
+}
+
+// Computed from scratch, with the Polyglot keys instead of ours. Only needed once per move, at the root.
+pub fn polyglot_key(game: &ChessGame) -> u64 {
+    let mut key: u64 = 0;
+
+    for (bitboard_index, piece_bitboard) in game.piece_bitboards.iter().enumerate() {
+        let is_white = bitboard_index < 6;
+        let piece_type = PieceType::bitboard_index_to_piece_type(bitboard_index % 6);
+
+        // Polyglot piece order, with black before white for each.
+        let kind = match piece_type {
+            PieceType::Pawn => 0,
+            PieceType::Knight => 1,
+            PieceType::Bishop => 2,
+            PieceType::Rook => 3,
+            PieceType::Queen => 4,
+            PieceType::King => 5,
+        };
+        let kind_of_piece = kind * 2 + if is_white { 1 } else { 0 };
+
+        let mut bitboard = *piece_bitboard;
+        while let Some(square) = get_lsb_index(bitboard) {
+            key ^= POLYGLOT_RANDOM_64
+                [PIECE_KEYS_OFFSET + 64 * kind_of_piece + flip_square_rank(square)];
+            bitboard = pop_bit(bitboard, square);
+        }
+    }
+
+    let castling_rights = [
+        game.can_white_castle_short,
+        game.can_white_castle_long,
+        game.can_black_castle_short,
+        game.can_black_castle_long,
+    ];
+    for (i, can_castle) in castling_rights.iter().enumerate() {
+        if *can_castle {
+            key ^= POLYGLOT_RANDOM_64[CASTLING_KEYS_OFFSET + i];
+        }
+    }
+
+    // En-passant only counts if one of our pawns is next to the pawn that just moved.
+    if let Some(target) = game.en_passant_target {
+        let (our_pawns, their_color) = if game.white_to_move {
+            (
+                game.piece_bitboards[Color::White.piece_bitboard_offset()],
+                Color::Black,
+            )
+        } else {
+            (
+                game.piece_bitboards[Color::Black.piece_bitboard_offset()],
+                Color::White,
+            )
+        };
+        let capturing_squares = game.bitboard_constants.pawn_attacks[their_color.idx()][target];
+        if capturing_squares & our_pawns != 0 {
+            key ^= POLYGLOT_RANDOM_64[EN_PASSANT_KEYS_OFFSET + target % 8];
+        }
+    }
+
+    if game.white_to_move {
+        key ^= POLYGLOT_RANDOM_64[WHITE_TO_MOVE_KEY_OFFSET];
+    }
+
+    return key;
+}
+
+// Bits: to file (0-2), to row (3-5), from file (6-8), from row (9-11), promotion (12-14).
+fn decode_move(game: &mut ChessGame, raw_move: u16) -> Option<Move> {
+    let raw_move = raw_move as usize;
+    let to_square = flip_square_rank(raw_move & 0x3F);
+    let from_square = flip_square_rank((raw_move >> 6) & 0x3F);
+
+    let mut m = Move::new(from_square, to_square);
+    m.pawn_promoting_to = match (raw_move >> 12) & 0x7 {
+        1 => Some(PieceType::Knight),
+        2 => Some(PieceType::Bishop),
+        3 => Some(PieceType::Rook),
+        4 => Some(PieceType::Queen),
+        _ => None,
+    };
+
+    // Castling is written as the king taking its own rook, e1h1. The same way we store it.
+    return game.choose_move_from_legal_move(&m);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime_calculated_constants::Constants;
+
+    // Reference keys from the book format page.
+    #[test]
+    fn polyglot_keys_match_reference() {
+        let c = Constants::new();
+        let mut game = ChessGame::new(&c);
+        let reference_keys = [
+            (
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                0x463b96181691fc9c,
+            ),
+            (
+                "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1",
+                0x823c9b50fd114196,
+            ),
+            (
+                "rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 2",
+                0x0756b94461c50fb0,
+            ),
+            (
+                "rnbqkbnr/ppp1pppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR b KQkq - 0 2",
+                0x662fafb965db29d4,
+            ),
+            (
+                "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+                0x22a48b5a8e47ff78,
+            ),
+            (
+                "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPPKPPP/RNBQ1BNR b kq - 0 3",
+                0x652a607ca3f242c1,
+            ),
+            (
+                "rnbq1bnr/ppp1pkpp/8/3pPp2/8/8/PPPPKPPP/RNBQ1BNR w - - 0 4",
+                0x00fdd303c946bdd9,
+            ),
+            (
+                "rnbqkbnr/p1pppppp/8/8/PpP4P/8/1P1PPPP1/RNBQKBNR b KQkq c3 0 3",
+                0x3c8123ea7b067637,
+            ),
+            (
+                "rnbqkbnr/p1pppppp/8/8/P6P/R1p5/1P1PPPP1/1NBQKBNR b Kkq - 0 4",
+                0x5c3f9b829b279560,
+            ),
+        ];
+
+        for (fen, expected_key) in reference_keys.iter() {
+            game.import_fen(fen).unwrap();
+            assert_eq!(polyglot_key(&game), *expected_key, "{fen}");
+        }
+    }
+
+    #[test]
+    fn book_moves() {
+        let c = Constants::new();
+        let mut game = ChessGame::new(&c);
+        game.import_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1")
+            .unwrap();
+        game.set_legal_moves(None);
+        let key = polyglot_key(&game);
+
+        // e1h1 (castle short), weight 1. a1a8, weight 5. A move that isn't legal here, heavy weight.
+        let e1h1: u16 = (4 << 6) | 7;
+        let a1a8: u16 = (0 << 6) | (7 << 3);
+        let e1e3: u16 = (4 << 6) | (2 << 3) | 4;
+        let mut bytes: Vec<u8> = vec![];
+        for (raw_move, weight) in [(a1a8, 5u16), (e1h1, 1), (e1e3, 100)].iter() {
+            bytes.extend_from_slice(&key.to_be_bytes());
+            bytes.extend_from_slice(&raw_move.to_be_bytes());
+            bytes.extend_from_slice(&weight.to_be_bytes());
+            bytes.extend_from_slice(&0u32.to_be_bytes());
+        }
+
+        let book = OpeningBook::from_bytes(&bytes, BookSelection::BestWeight, 10).unwrap();
+        assert_eq!(book.probe(key).len(), 3);
+        assert_eq!(book.choose_move(&mut game).unwrap().move_to_str(), "a1a8");
+
+        let book = OpeningBook::from_bytes(&bytes, BookSelection::WeightedRandom, 10).unwrap();
+        for _ in 0..20 {
+            let m = book.choose_move(&mut game).unwrap().move_to_str();
+            assert!(m == "a1a8" || m == "e1g1", "{m}");
+        }
+
+        // Past the book depth.
+        let book = OpeningBook::from_bytes(&bytes, BookSelection::BestWeight, 0).unwrap();
+        assert!(book.choose_move(&mut game).is_none());
+
+        // Same position, with the first move numbered 0.
+        let book = OpeningBook::from_bytes(&bytes, BookSelection::BestWeight, 1).unwrap();
+        game.import_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 0")
+            .unwrap();
+        game.set_legal_moves(None);
+        assert_eq!(book.choose_move(&mut game).unwrap().move_to_str(), "a1a8");
+    }
+}
@@ -0,0 +1,71 @@
+// Pawn structure results, looked up by `ChessGame::pawn_zobrist_hash`. Only depends on where the pawns are.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PawnHashEntry {
+    pub pawn_zobrist_hash: u64,
+
+    // White minus black, in centipawns.
+    pub midgame: i64,
+    pub endgame: i64,
+
+    // [color]. Kept so the parts that depend on other pieces, like a free path, don't need the pawns worked out again.
+    pub passed_pawns: [u64; 2],
+}
+
+// A fixed-size cache, one entry per slot. A new entry always replaces whatever was in its slot. Never needs clearing,
+// the same pawns always get the same result.
+#[derive(Clone)]
+pub struct PawnHashTable {
+    entries: Vec<Option<PawnHashEntry>>,
+}
+
+impl PawnHashTable {
+    pub fn new(size_mb: usize) -> Self {
+        let size_bytes = size_mb * 1024 * 1024;
+        let entry_count = usize::max(size_bytes / std::mem::size_of::<Option<PawnHashEntry>>(), 1);
+        return PawnHashTable {
+            entries: vec![None; entry_count],
+        };
+    }
+
+    fn index(&self, pawn_zobrist_hash: u64) -> usize {
+        return (pawn_zobrist_hash % self.entries.len() as u64) as usize;
+    }
+
+    pub fn probe(&self, pawn_zobrist_hash: u64) -> Option<PawnHashEntry> {
+        return self.entries[self.index(pawn_zobrist_hash)]
+            .filter(|entry| entry.pawn_zobrist_hash == pawn_zobrist_hash);
+    }
+
+    pub fn store(&mut self, entry: PawnHashEntry) {
+        let index = self.index(entry.pawn_zobrist_hash);
+        self.entries[index] = Some(entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_and_probe() {
+        let mut table = PawnHashTable::new(1);
+        let entry = PawnHashEntry {
+            pawn_zobrist_hash: 12345,
+            midgame: -10,
+            endgame: 25,
+            passed_pawns: [1 << 8, 0],
+        };
+        table.store(entry);
+        assert_eq!(table.probe(12345), Some(entry));
+        assert_eq!(table.probe(12346), None);
+
+        // Same slot, different pawns.
+        let colliding = 12345 + table.entries.len() as u64;
+        assert_eq!(table.probe(colliding), None);
+        table.store(PawnHashEntry {
+            pawn_zobrist_hash: colliding,
+            ..entry
+        });
+        assert_eq!(table.probe(12345), None);
+    }
+}
@@ -0,0 +1,254 @@
+use crate::color::Color;
+use crate::evaluation_trace::{EvaluationTerm, EvaluationTrace};
+use crate::helpers::*;
+use crate::runtime_calculated_constants::Constants;
+
+// Midgame and endgame values, in centipawns, for each kind of pawn. Indexed by rank from the pawn's own side where
+// there is an array, so index 6 is one step from promoting.
+pub const PASSED_PAWN_BONUS_MIDGAME: [i64; 8] = [0, 0, 5, 10, 20, 35, 50, 0];
+pub const PASSED_PAWN_BONUS_ENDGAME: [i64; 8] = [0, 5, 10, 20, 35, 60, 90, 0];
+
+// On top of the passed pawn bonus, when nothing stands on the squares in front of it. Not cached, it depends on
+// every piece.
+pub const FREE_PASSED_PAWN_BONUS_MIDGAME: [i64; 8] = [0, 0, 0, 5, 10, 15, 20, 0];
+pub const FREE_PASSED_PAWN_BONUS_ENDGAME: [i64; 8] = [0, 0, 5, 10, 20, 35, 60, 0];
+
+// Defended by another pawn, or side by side with one.
+pub const CONNECTED_PAWN_BONUS_MIDGAME: [i64; 8] = [0, 5, 7, 10, 15, 25, 40, 0];
+pub const CONNECTED_PAWN_BONUS_ENDGAME: [i64; 8] = [0, 5, 7, 10, 15, 25, 40, 0];
+
+// For every pawn with another of ours in front of it on the same file.
+pub const DOUBLED_PAWN_PENALTY_MIDGAME: i64 = -10;
+pub const DOUBLED_PAWN_PENALTY_ENDGAME: i64 = -20;
+
+// No pawns of ours on either neighbouring file to ever defend it.
+pub const ISOLATED_PAWN_PENALTY_MIDGAME: i64 = -10;
+pub const ISOLATED_PAWN_PENALTY_ENDGAME: i64 = -15;
+
+// Its neighbours have all moved past it, and an enemy pawn stops it from catching up.
+pub const BACKWARD_PAWN_PENALTY_MIDGAME: i64 = -8;
+pub const BACKWARD_PAWN_PENALTY_ENDGAME: i64 = -10;
+
+// Each kind of pawn for both sides, indexed by `Color::idx`. A pawn can be more than one kind.
+#[derive(Debug, PartialEq)]
+pub struct PawnStructure {
+    pub passed: [u64; 2],
+    pub doubled: [u64; 2],
+    pub isolated: [u64; 2],
+    pub backward: [u64; 2],
+    pub connected: [u64; 2],
+}
+
+impl PawnStructure {
+    // `pawns` is indexed by `Color::idx`.
+    pub fn new(pawns: [u64; 2], c: &Constants) -> Self {
+        let mut structure = PawnStructure {
+            passed: [0; 2],
+            doubled: [0; 2],
+            isolated: [0; 2],
+            backward: [0; 2],
+            connected: [0; 2],
+        };
+
+        for (color, their_color) in [(Color::White, Color::Black), (Color::Black, Color::White)] {
+            let ours = pawns[color.idx()];
+            let theirs = pawns[their_color.idx()];
+
+            let mut remaining = ours;
+            while remaining != 0 {
+                let square = get_lsb_index(remaining).expect("Guard clause.");
+                remaining = pop_bit(remaining, square);
+                let file = square % 8;
+                let rank = 7 - square / 8;
+
+                // Only the front pawn of a doubled pair counts as passed.
+                let is_doubled = c.front_span_masks[color.idx()][square] & ours != 0;
+                if is_doubled {
+                    structure.doubled[color.idx()] =
+                        set_bit(structure.doubled[color.idx()], square);
+                } else if c.passed_pawn_masks[color.idx()][square] & theirs == 0 {
+                    structure.passed[color.idx()] = set_bit(structure.passed[color.idx()], square);
+                }
+
+                // Pawns defending this one sit where an enemy pawn here would attack.
+                let is_defended = c.pawn_attacks[their_color.idx()][square] & ours != 0;
+                let is_phalanx = c.rank_masks[rank] & c.adjacent_file_masks[file] & ours != 0;
+                if is_defended || is_phalanx {
+                    structure.connected[color.idx()] =
+                        set_bit(structure.connected[color.idx()], square);
+                }
+
+                let neighbours = c.adjacent_file_masks[file] & ours;
+                if neighbours == 0 {
+                    structure.isolated[color.idx()] =
+                        set_bit(structure.isolated[color.idx()], square);
+                    continue;
+                }
+
+                // Neighbours level with it or behind could still come up and defend it. The ones in front can't.
+                let neighbours_in_front = neighbours & c.passed_pawn_masks[color.idx()][square];
+                let stop_square = match color {
+                    Color::White => square.checked_sub(8),
+                    Color::Black => Some(square + 8).filter(|s| *s < 64),
+                };
+                let is_stop_square_attacked =
+                    stop_square.is_some_and(|s| c.pawn_attacks[color.idx()][s] & theirs != 0);
+                if neighbours_in_front == neighbours && is_stop_square_attacked {
+                    structure.backward[color.idx()] =
+                        set_bit(structure.backward[color.idx()], square);
+                }
+            }
+        }
+
+        return structure;
+    }
+
+    // White minus black, as (midgame, endgame). Fills in `trace` along the way, if there is one.
+    pub fn score(&self, mut trace: Option<&mut EvaluationTrace>) -> (i64, i64) {
+        let mut midgame: i64 = 0;
+        let mut endgame: i64 = 0;
+
+        for color in [Color::White, Color::Black] {
+            let sign = match color {
+                Color::White => 1,
+                Color::Black => -1,
+            };
+            let i = color.idx();
+
+            for (term, bitboard, bonus_midgame, bonus_endgame) in [
+                (
+                    EvaluationTerm::PassedPawns,
+                    self.passed[i],
+                    &PASSED_PAWN_BONUS_MIDGAME,
+                    &PASSED_PAWN_BONUS_ENDGAME,
+                ),
+                (
+                    EvaluationTerm::ConnectedPawns,
+                    self.connected[i],
+                    &CONNECTED_PAWN_BONUS_MIDGAME,
+                    &CONNECTED_PAWN_BONUS_ENDGAME,
+                ),
+            ] {
+                let mut remaining = bitboard;
+                while remaining != 0 {
+                    let square = get_lsb_index(remaining).expect("Guard clause.");
+                    remaining = pop_bit(remaining, square);
+                    let rank = relative_rank(square, color);
+                    midgame += sign * bonus_midgame[rank];
+                    endgame += sign * bonus_endgame[rank];
+                    if let Some(t) = trace.as_deref_mut() {
+                        t.add(color, term, bonus_midgame[rank], bonus_endgame[rank]);
+                    }
+                }
+            }
+
+            for (term, bitboard, penalty_midgame, penalty_endgame) in [
+                (
+                    EvaluationTerm::DoubledPawns,
+                    self.doubled[i],
+                    DOUBLED_PAWN_PENALTY_MIDGAME,
+                    DOUBLED_PAWN_PENALTY_ENDGAME,
+                ),
+                (
+                    EvaluationTerm::IsolatedPawns,
+                    self.isolated[i],
+                    ISOLATED_PAWN_PENALTY_MIDGAME,
+                    ISOLATED_PAWN_PENALTY_ENDGAME,
+                ),
+                (
+                    EvaluationTerm::BackwardPawns,
+                    self.backward[i],
+                    BACKWARD_PAWN_PENALTY_MIDGAME,
+                    BACKWARD_PAWN_PENALTY_ENDGAME,
+                ),
+            ] {
+                let count = count_bits(bitboard) as i64;
+                midgame += sign * count * penalty_midgame;
+                endgame += sign * count * penalty_endgame;
+                if let Some(t) = trace.as_deref_mut() {
+                    t.add(
+                        color,
+                        term,
+                        count * penalty_midgame,
+                        count * penalty_endgame,
+                    );
+                }
+            }
+        }
+
+        return (midgame, endgame);
+    }
+}
+
+// Ranks counted from this side's first rank, 0 to 7.
+pub fn relative_rank(square: usize, color: Color) -> usize {
+    return match color {
+        Color::White => 7 - square / 8,
+        Color::Black => square / 8,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess_game::ChessGame;
+    use crate::piece_type::PieceType;
+
+    fn structure_from_fen(c: &Constants, fen: &str) -> PawnStructure {
+        let mut game = ChessGame::new(c);
+        game.import_fen(fen).unwrap();
+        let pawns = [
+            game.piece_bitboards
+                [Color::White.piece_bitboard_offset() + PieceType::Pawn.bitboard_index()],
+            game.piece_bitboards
+                [Color::Black.piece_bitboard_offset() + PieceType::Pawn.bitboard_index()],
+        ];
+        return PawnStructure::new(pawns, c);
+    }
+
+    // a8 is 0, h1 is 63.
+    fn squares(names: &[&str]) -> u64 {
+        let mut bitboard = 0;
+        for name in names.iter() {
+            let bytes = name.as_bytes();
+            let file = (bytes[0] - b'a') as usize;
+            let rank = (bytes[1] - b'1') as usize;
+            bitboard = set_bit(bitboard, (7 - rank) * 8 + file);
+        }
+        return bitboard;
+    }
+
+    #[test]
+    fn finds_each_kind_of_pawn() {
+        let c = Constants::new();
+
+        // White: doubled and isolated on the a-file, d5 defended by c4, and f2/g2 side by side. e6 stops f2 being passed.
+        // Black: e6 defended by d7.
+        let structure = structure_from_fen(&c, "4k3/3p4/4p3/2pP4/P1P5/P7/5PP1/4K3 w - - 0 1");
+
+        assert_eq!(structure.doubled[0], squares(&["a3"]));
+        assert_eq!(structure.isolated[0], squares(&["a3", "a4"]));
+        assert_eq!(structure.passed[0], squares(&["a4", "g2"]));
+        assert_eq!(structure.connected[0], squares(&["d5", "f2", "g2"]));
+        assert_eq!(structure.passed[1], 0);
+        assert_eq!(structure.isolated[1], 0);
+        assert_eq!(structure.connected[1], squares(&["e6"]));
+    }
+
+    #[test]
+    fn backward_pawn() {
+        let c = Constants::new();
+
+        // d6's neighbours have gone to c5 and e5, and the white pawn on e4 covers d5.
+        let structure = structure_from_fen(&c, "4k3/8/3p4/2p1p3/4P3/8/8/4K3 w - - 0 1");
+        assert_eq!(structure.backward[1], squares(&["d6"]));
+        assert_eq!(structure.backward[0], 0);
+
+        // Mirrored, so the scores cancel out.
+        let mirrored = structure_from_fen(&c, "4k3/8/8/4p3/2P1P3/3P4/8/4K3 w - - 0 1");
+        assert_eq!(mirrored.backward[0], squares(&["d3"]));
+        let (midgame, endgame) = structure.score(None);
+        let (mirrored_midgame, mirrored_endgame) = mirrored.score(None);
+        assert_eq!((midgame, endgame), (-mirrored_midgame, -mirrored_endgame));
+    }
+}
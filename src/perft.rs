@@ -0,0 +1,143 @@
+use crate::chess_game::ChessGame;
+use crate::constants;
+use crate::runtime_calculated_constants::Constants;
+
+// Usage: `perft <depth> [fen]`. Prints the divide output, then the total node count.
+pub fn main(args: &[String]) -> Result<(), String> {
+    let depth: u32 = match args.first() {
+        Some(s) => match s.parse() {
+            Ok(d) => d,
+            Err(e) => return Err(format!("Unable to parse perft depth `{s}`. Detail: {e}")),
+        },
+        None => return Err("Usage: perft <depth> [fen]".to_string()),
+    };
+
+    // Everything after the depth is the FEN, it has spaces in it.
+    let fen = if args.len() > 1 {
+        args[1..].join(" ")
+    } else {
+        constants::INITIAL_GAME_STATE_FEN.to_string()
+    };
+
+    let c = Constants::new();
+    let mut game = ChessGame::new(&c);
+    game.import_fen(&fen)?;
+    game.is_chess960 = game.has_chess960_castling();
+
+    let start_time = std::time::Instant::now();
+    let results = game.divide(depth);
+    let time_spent_ms = start_time.elapsed().as_millis();
+
+    let mut total: u64 = 0;
+    for (m, nodes) in results.iter() {
+        println!("{}: {}", game.move_to_uci(m), nodes);
+        total += nodes;
+    }
+
+    // Perft of depth 0 is a single node, divide has no moves to list.
+    if depth == 0 {
+        total = 1;
+    }
+
+    println!();
+    println!("Nodes searched: {total}");
+    println!("Time: {time_spent_ms}ms");
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reference positions and node counts from https://www.chessprogramming.org/Perft_Results
+    const KIWIPETE_FEN: &str =
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+    const EN_PASSANT_FEN: &str = "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1";
+    const PROMOTION_FEN: &str = "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1";
+    const PROMOTION_MIRRORED_FEN: &str =
+        "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1";
+    const CASTLING_CHECKS_FEN: &str = "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8";
+
+    // From https://www.chessprogramming.org/Chess960_Perft_Results, in Shredder-FEN. In the last one, castling short
+    // leaves the rook where it is.
+    const CHESS960_FENS: [&str; 3] = [
+        "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9",
+        "2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9",
+        "1rqbkrbn/1ppppp1p/1n6/p1N3p1/8/2P4P/PP1PPPP1/1RQBKRBN w FBfb - 0 9",
+    ];
+
+    fn assert_perft(fen: &str, expected: &[u64]) {
+        let c = Constants::new();
+        let mut game = ChessGame::new(&c);
+        game.import_fen(fen).expect("Reference FEN should import.");
+        let hash_before = game.zobrist_hash;
+        let fen_before = game.export_fen();
+
+        for (i, expected_nodes) in expected.iter().enumerate() {
+            let depth = i as u32 + 1;
+            assert_eq!(
+                game.perft(depth),
+                *expected_nodes,
+                "Perft mismatch at depth {depth} for `{fen}`."
+            );
+        }
+
+        // Make/unmake should leave the board exactly as we found it.
+        assert_eq!(game.zobrist_hash, hash_before);
+        assert_eq!(game.export_fen(), fen_before);
+    }
+
+    #[test]
+    fn perft_startpos() {
+        assert_perft(
+            constants::INITIAL_GAME_STATE_FEN,
+            &[20, 400, 8_902, 197_281],
+        );
+    }
+
+    #[test]
+    fn perft_kiwipete() {
+        assert_perft(KIWIPETE_FEN, &[48, 2_039, 97_862]);
+    }
+
+    #[test]
+    fn perft_en_passant() {
+        assert_perft(EN_PASSANT_FEN, &[14, 191, 2_812, 43_238]);
+    }
+
+    #[test]
+    fn perft_promotion() {
+        assert_perft(PROMOTION_FEN, &[6, 264, 9_467]);
+    }
+
+    #[test]
+    fn perft_promotion_mirrored() {
+        assert_perft(PROMOTION_MIRRORED_FEN, &[6, 264, 9_467]);
+    }
+
+    #[test]
+    fn perft_castling_checks() {
+        assert_perft(CASTLING_CHECKS_FEN, &[44, 1_486, 62_379]);
+    }
+
+    #[test]
+    fn perft_chess960() {
+        assert_perft(CHESS960_FENS[0], &[21, 528, 12_189, 326_672]);
+        assert_perft(CHESS960_FENS[1], &[21, 807, 18_002]);
+        assert_perft(CHESS960_FENS[2], &[29, 502, 14_569, 287_739]);
+    }
+
+    #[test]
+    fn divide_adds_up_to_perft() {
+        let c = Constants::new();
+        let mut game = ChessGame::new(&c);
+        game.import_fen(KIWIPETE_FEN).unwrap();
+
+        let results = game.divide(2);
+        assert_eq!(results.len(), 48);
+
+        let total: u64 = results.iter().map(|(_, nodes)| nodes).sum();
+        assert_eq!(total, game.perft(2));
+    }
+}
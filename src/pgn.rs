@@ -0,0 +1,488 @@
+use crate::chess_game::ChessGame;
+use crate::constants;
+use crate::r#move::Move;
+use crate::runtime_calculated_constants::Constants;
+
+// Portable Game Notation, for saving and replaying games. Follows the 1994 PGN standard.
+
+// The Seven Tag Roster. Always exported, in this order.
+pub const SEVEN_TAG_ROSTER: [&str; 7] =
+    ["Event", "Site", "Date", "Round", "White", "Black", "Result"];
+
+// Usage: `pgn <file>`. Replays the game, prints the final position, then writes the game back out.
+pub fn main(args: &[String]) -> Result<(), String> {
+    let path = match args.first() {
+        Some(p) => p,
+        None => return Err("Usage: pgn <file>".to_string()),
+    };
+    let text = match std::fs::read_to_string(path) {
+        Ok(t) => t,
+        Err(e) => return Err(format!("Unable to read `{path}`. Detail: {e}")),
+    };
+
+    let c = Constants::new();
+    let mut game = ChessGame::new(&c);
+    let pgn = PgnGame::parse(&text, &mut game, true)?;
+
+    game.print_board();
+    println!("FEN: {}", game.export_fen());
+    println!();
+    print!("{}", pgn.export(&mut game)?);
+
+    return Ok(());
+}
+
+// Keep exported lines under this many characters, as the spec asks.
+const MAX_LINE_LENGTH: usize = 80;
+
+pub struct PgnMove {
+    pub r#move: Move,
+    pub comments: Vec<String>,
+    pub nags: Vec<u32>,
+
+    // Raw movetext of side lines played instead of this move. Only kept if asked for when parsing.
+    pub variations: Vec<String>,
+}
+
+impl PgnMove {
+    pub fn new(m: Move) -> Self {
+        return PgnMove {
+            r#move: m,
+            comments: vec![],
+            nags: vec![],
+            variations: vec![],
+        };
+    }
+}
+
+pub struct PgnGame {
+    // Kept in the order they were read or added.
+    pub tags: Vec<(String, String)>,
+
+    // Comments before the first move.
+    pub initial_comments: Vec<String>,
+    pub moves: Vec<PgnMove>,
+
+    // `1-0`, `0-1`, `1/2-1/2` or `*` (unfinished).
+    pub result: String,
+}
+
+impl Default for PgnGame {
+    fn default() -> Self {
+        PgnGame {
+            tags: vec![],
+            initial_comments: vec![],
+            moves: vec![],
+            result: String::from("*"),
+        }
+    }
+}
+
+enum MovetextToken {
+    Move(String),
+    Comment(String),
+    Nag(u32),
+    Variation(String),
+    Result(String),
+}
+
+impl PgnGame {
+    pub fn get_tag(&self, name: &str) -> Option<&str> {
+        return self
+            .tags
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str());
+    }
+
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(n, _)| n == name) {
+            Some(tag) => tag.1 = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    // Games that don't start from the normal position carry it in the FEN tag.
+    pub fn initial_fen(&self) -> &str {
+        return match self.get_tag("FEN") {
+            Some(fen) => fen,
+            None => constants::INITIAL_GAME_STATE_FEN,
+        };
+    }
+
+    // Reads a single game. The moves are played on `game`, which is left at the final position.
+    pub fn parse(
+        text: &str,
+        game: &mut ChessGame,
+        keep_variations: bool,
+    ) -> Result<PgnGame, String> {
+        let mut pgn = PgnGame::default();
+        let mut movetext = String::new();
+
+        for line in text.lines() {
+            let trimmed = line.trim();
+
+            // Lines starting with `%` are escaped, and ignored.
+            if line.starts_with('%') {
+                continue;
+            }
+
+            if trimmed.starts_with('[') && movetext.trim().is_empty() {
+                let (name, value) = parse_tag(trimmed)?;
+                pgn.tags.push((name, value));
+                continue;
+            }
+
+            movetext += line;
+            movetext += "\n";
+        }
+
+        if let Some(result) = pgn.get_tag("Result") {
+            pgn.result = result.to_string();
+        }
+
+        let initial_fen = pgn.initial_fen().to_string();
+        game.import_fen(&initial_fen)?;
+        game.set_legal_moves(None);
+
+        for token in tokenize_movetext(&movetext)? {
+            match token {
+                MovetextToken::Move(san) => {
+                    let m = game.san_to_move(&san)?;
+                    game.make_move(&m, true);
+                    pgn.moves.push(PgnMove::new(m));
+                }
+                MovetextToken::Comment(comment) => match pgn.moves.last_mut() {
+                    Some(last) => last.comments.push(comment),
+                    None => pgn.initial_comments.push(comment),
+                },
+                MovetextToken::Nag(nag) => {
+                    if let Some(last) = pgn.moves.last_mut() {
+                        last.nags.push(nag);
+                    }
+                }
+                MovetextToken::Variation(variation) => {
+                    if let (true, Some(last)) = (keep_variations, pgn.moves.last_mut()) {
+                        last.variations.push(variation);
+                    }
+                }
+                MovetextToken::Result(result) => {
+                    pgn.result = result;
+                    break;
+                }
+            }
+        }
+
+        return Ok(pgn);
+    }
+
+    // Writes the game out. `game` is only used as a scratch board to work out the SAN of each move.
+    pub fn export(&self, game: &mut ChessGame) -> Result<String, String> {
+        let mut pgn = String::new();
+
+        // Tag pairs. The Seven Tag Roster comes first, with `?` for anything we don't know.
+        for name in SEVEN_TAG_ROSTER.iter() {
+            let value = match *name {
+                "Result" => self.result.as_str(),
+                _ => self.get_tag(name).unwrap_or("?"),
+            };
+            pgn += &format!("[{} \"{}\"]\n", name, escape_tag_value(value));
+        }
+        for (name, value) in self.tags.iter() {
+            if !SEVEN_TAG_ROSTER.contains(&name.as_str()) {
+                pgn += &format!("[{} \"{}\"]\n", name, escape_tag_value(value));
+            }
+        }
+        pgn += "\n";
+
+        // Movetext.
+        game.import_fen(self.initial_fen())?;
+        game.set_legal_moves(None);
+
+        let mut words: Vec<String> = vec![];
+        for comment in self.initial_comments.iter() {
+            words.push(format!("{{{comment}}}"));
+        }
+
+        // Black's move needs its own move number if anything was written in between.
+        let mut needs_move_number = true;
+        for pgn_move in self.moves.iter() {
+            let m = match game.choose_move_from_legal_move(&pgn_move.r#move) {
+                Some(m) => m,
+                None => {
+                    return Err(format!(
+                        "Illegal move `{}` in position `{}`.",
+                        pgn_move.r#move.move_to_str(),
+                        game.export_fen()
+                    ))
+                }
+            };
+
+            // The move number stays on the same line as its move.
+            let move_number = if game.white_to_move {
+                format!("{}. ", game.fullmove_number)
+            } else if needs_move_number {
+                format!("{}... ", game.fullmove_number)
+            } else {
+                String::new()
+            };
+
+            words.push(move_number + &game.move_to_san(&m, false));
+            game.make_move(&m, true);
+            needs_move_number = false;
+
+            for nag in pgn_move.nags.iter() {
+                words.push(format!("${nag}"));
+            }
+            for comment in pgn_move.comments.iter() {
+                words.push(format!("{{{comment}}}"));
+                needs_move_number = true;
+            }
+            for variation in pgn_move.variations.iter() {
+                words.push(format!("({variation})"));
+                needs_move_number = true;
+            }
+        }
+        words.push(self.result.clone());
+
+        // Wrap the movetext.
+        let mut line_length = 0;
+        for word in words.iter() {
+            if line_length > 0 && line_length + 1 + word.len() > MAX_LINE_LENGTH {
+                pgn += "\n";
+                line_length = 0;
+            } else if line_length > 0 {
+                pgn += " ";
+                line_length += 1;
+            }
+            pgn += word;
+            line_length += word.len();
+        }
+        pgn += "\n";
+
+        return Ok(pgn);
+    }
+}
+
+// [Name "Value"]
+fn parse_tag(line: &str) -> Result<(String, String), String> {
+    let inner = match line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+        Some(s) => s.trim(),
+        None => return Err(format!("Badly formed tag pair: `{line}`.")),
+    };
+
+    let (name, quoted_value) = match inner.split_once(char::is_whitespace) {
+        Some((n, v)) => (n, v.trim()),
+        None => return Err(format!("Tag pair has no value: `{line}`.")),
+    };
+
+    let value = match quoted_value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+    {
+        Some(v) => v.replace("\\\"", "\"").replace("\\\\", "\\"),
+        None => return Err(format!("Tag value should be quoted: `{line}`.")),
+    };
+
+    return Ok((name.to_string(), value));
+}
+
+fn escape_tag_value(value: &str) -> String {
+    return value.replace('\\', "\\\\").replace('"', "\\\"");
+}
+
+fn tokenize_movetext(movetext: &str) -> Result<Vec<MovetextToken>, String> {
+    let mut tokens: Vec<MovetextToken> = vec![];
+    let chars: Vec<char> = movetext.chars().collect();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '{' {
+            // Brace comments can span lines, and don't nest.
+            let end = match chars[i..].iter().position(|ch| *ch == '}') {
+                Some(offset) => i + offset,
+                None => return Err("Comment is missing its closing `}`.".to_string()),
+            };
+            let comment: String = chars[i + 1..end].iter().collect();
+            tokens.push(MovetextToken::Comment(comment.trim().to_string()));
+            i = end + 1;
+        } else if c == ';' {
+            // Rest of line comments.
+            let end = chars[i..]
+                .iter()
+                .position(|ch| *ch == '\n')
+                .map_or(chars.len(), |offset| i + offset);
+            let comment: String = chars[i + 1..end].iter().collect();
+            tokens.push(MovetextToken::Comment(comment.trim().to_string()));
+            i = end;
+        } else if c == '(' {
+            // Variations nest. Skip over comments inside them, they may contain parentheses.
+            let mut depth = 0;
+            let mut in_comment = false;
+            let mut end = None;
+            for (offset, ch) in chars[i..].iter().enumerate() {
+                match (*ch, in_comment) {
+                    ('{', false) => in_comment = true,
+                    ('}', true) => in_comment = false,
+                    ('(', false) => depth += 1,
+                    (')', false) => {
+                        depth -= 1;
+                        if depth == 0 {
+                            end = Some(i + offset);
+                            break;
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            let end = match end {
+                Some(e) => e,
+                None => return Err("Variation is missing its closing `)`.".to_string()),
+            };
+            let variation: String = chars[i + 1..end].iter().collect();
+            tokens.push(MovetextToken::Variation(variation.trim().to_string()));
+            i = end + 1;
+        } else if c == ')' {
+            return Err("Unexpected `)` in movetext.".to_string());
+        } else {
+            // A word: move number, move, NAG, or result.
+            let end = chars[i..]
+                .iter()
+                .position(|ch| ch.is_whitespace() || "{};()".contains(*ch))
+                .map_or(chars.len(), |offset| i + offset);
+            let word: String = chars[i..end].iter().collect();
+            i = end;
+
+            push_word_tokens(&word, &mut tokens)?;
+        }
+    }
+
+    return Ok(tokens);
+}
+
+fn push_word_tokens(word: &str, tokens: &mut Vec<MovetextToken>) -> Result<(), String> {
+    if ["1-0", "0-1", "1/2-1/2", "*"].contains(&word) {
+        tokens.push(MovetextToken::Result(word.to_string()));
+        return Ok(());
+    }
+
+    if let Some(nag) = word.strip_prefix('$') {
+        match nag.parse() {
+            Ok(n) => tokens.push(MovetextToken::Nag(n)),
+            Err(_) => return Err(format!("Invalid NAG: `{word}`.")),
+        }
+        return Ok(());
+    }
+
+    // Some writers mark en-passant captures. It isn't part of SAN.
+    if word == "e.p." {
+        return Ok(());
+    }
+
+    // Move numbers (`12.` or `12...`), sometimes stuck to the move (`12.e4`). Castling can be written with zeros.
+    let mut san = word;
+    if !san.starts_with("0-0") {
+        san = san.trim_start_matches(|c: char| c.is_ascii_digit());
+        san = san.trim_start_matches('.');
+    }
+    if san.is_empty() {
+        return Ok(());
+    }
+
+    // Move suffix annotations are short for NAGs.
+    let without_annotation = san.trim_end_matches(['!', '?']);
+    let annotation = &san[without_annotation.len()..];
+    tokens.push(MovetextToken::Move(without_annotation.to_string()));
+    match annotation {
+        "" => (),
+        "!" => tokens.push(MovetextToken::Nag(1)),
+        "?" => tokens.push(MovetextToken::Nag(2)),
+        "!!" => tokens.push(MovetextToken::Nag(3)),
+        "??" => tokens.push(MovetextToken::Nag(4)),
+        "!?" => tokens.push(MovetextToken::Nag(5)),
+        "?!" => tokens.push(MovetextToken::Nag(6)),
+        _ => return Err(format!("Unknown move annotation: `{word}`.")),
+    }
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPERA_GAME: &str = r#"[Event "Paris"]
+[Site "Paris FRA"]
+[Date "1858.??.??"]
+[Round "?"]
+[White "Paul Morphy"]
+[Black "Duke Karl / Count Isouard"]
+[Result "1-0"]
+
+1. e4 e5 2. Nf3 d6 3. d4 Bg4 {This is a weak move already.} 4. dxe5 Bxf3 5. Qxf3
+dxe5 6. Bc4 Nf6 7. Qb3 qe7 8. Nc3 c6 9. Bg5 b5 10. Nxb5 cxb5 11. Bxb5+ Nbd7
+12. O-O-O Rd8 13. Rxd7 Rxd7 14. Rd1 Qe6 15. Bxd7+ Nxd7 16. Qb8+ Nxb8 17. Rd8# 1-0
+"#;
+
+    #[test]
+    fn parse_and_export_round_trip() {
+        let c = Constants::new();
+        let mut game = ChessGame::new(&c);
+
+        // Lower case piece letters aren't valid SAN.
+        assert!(PgnGame::parse(OPERA_GAME, &mut game, false).is_err());
+
+        let text = OPERA_GAME.replace("qe7", "Qe7");
+        let pgn = PgnGame::parse(&text, &mut game, false).unwrap();
+        assert_eq!(pgn.moves.len(), 33);
+        assert_eq!(pgn.result, "1-0");
+        assert_eq!(pgn.get_tag("White"), Some("Paul Morphy"));
+        assert_eq!(pgn.moves[5].comments, vec!["This is a weak move already."]);
+        assert!(game.is_checkmate());
+
+        let exported = pgn.export(&mut game).unwrap();
+        assert!(exported.lines().all(|l| l.len() <= MAX_LINE_LENGTH));
+        let exported_line = exported.replace('\n', " ");
+        assert!(exported_line.contains("12. O-O-O Rd8"));
+        assert!(exported_line.contains("3. d4 Bg4 {This is a weak move already.} 4. dxe5"));
+        assert!(exported_line.contains("17. Rd8# 1-0"));
+
+        let reparsed = PgnGame::parse(&exported, &mut game, false).unwrap();
+        assert_eq!(reparsed.moves.len(), 33);
+        assert_eq!(reparsed.export(&mut game).unwrap(), exported);
+    }
+
+    #[test]
+    fn parse_variations_nags_and_setup() {
+        let c = Constants::new();
+        let mut game = ChessGame::new(&c);
+        let text = r#"[SetUp "1"]
+[FEN "4k3/P7/8/8/8/8/8/4K2R b K - 0 40"]
+
+40... Kd7 (40... Kf7 {or this} 41. a8=Q) 41. a8=N!? $14 ; knight!
+Ke6 42. O-O *"#;
+
+        let pgn = PgnGame::parse(text, &mut game, true).unwrap();
+        assert_eq!(pgn.moves.len(), 4);
+        assert_eq!(
+            pgn.moves[0].variations,
+            vec!["40... Kf7 {or this} 41. a8=Q"]
+        );
+        assert_eq!(pgn.moves[1].nags, vec![5, 14]);
+        assert_eq!(pgn.moves[1].comments, vec!["knight!"]);
+        assert_eq!(pgn.result, "*");
+
+        let exported = pgn.export(&mut game).unwrap().replace('\n', " ");
+        assert!(
+            exported.contains("40... Kd7 (40... Kf7 {or this} 41. a8=Q) 41. a8=N $5 $14 {knight!}")
+        );
+        assert!(exported.contains("41... Ke6 42. O-O *"));
+
+        let skipped = PgnGame::parse(text, &mut game, false).unwrap();
+        assert!(skipped.moves[0].variations.is_empty());
+    }
+}
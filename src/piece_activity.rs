@@ -0,0 +1,78 @@
+// A midgame and an endgame value, in centipawns.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Weight {
+    pub midgame: i64,
+    pub endgame: i64,
+}
+
+impl Weight {
+    pub const fn new(midgame: i64, endgame: i64) -> Self {
+        return Weight { midgame, endgame };
+    }
+}
+
+// Everything the evaluation knows about piece activity, in one place so it can be tuned without touching the code.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PieceActivityWeights {
+    // Per safe square, above or below the baseline. Safe squares are empty or hold an enemy piece, and aren't attacked
+    // by an enemy pawn.
+    pub knight_mobility: Weight,
+    pub bishop_mobility: Weight,
+    pub rook_mobility: Weight,
+    pub queen_mobility: Weight,
+
+    // Roughly what each piece has on an average square, so a typical piece scores about 0.
+    pub knight_mobility_baseline: i64,
+    pub bishop_mobility_baseline: i64,
+    pub rook_mobility_baseline: i64,
+    pub queen_mobility_baseline: i64,
+
+    pub bishop_pair: Weight,
+
+    // No pawns on the file, or only theirs.
+    pub rook_open_file: Weight,
+    pub rook_semi_open_file: Weight,
+
+    // Only when there are pawns of theirs there to eat, or their king is stuck on the back rank.
+    pub rook_seventh_rank: Weight,
+
+    // On the 4th to 6th rank, defended by a pawn and out of reach of their pawns.
+    pub knight_outpost: Weight,
+
+    // A bishop on a7/h7 shut in by a pawn on b6/g6, and the same for black.
+    pub trapped_bishop: Weight,
+
+    // A rook boxed into the corner by its own king, which can't castle any more.
+    pub trapped_rook: Weight,
+
+    // Safe squares at or below this count as boxed in for `trapped_rook`.
+    pub trapped_rook_max_mobility: i64,
+}
+
+impl Default for PieceActivityWeights {
+    fn default() -> Self {
+        return PieceActivityWeights {
+            knight_mobility: Weight::new(4, 4),
+            bishop_mobility: Weight::new(5, 5),
+            rook_mobility: Weight::new(2, 4),
+            queen_mobility: Weight::new(1, 2),
+
+            knight_mobility_baseline: 4,
+            bishop_mobility_baseline: 6,
+            rook_mobility_baseline: 6,
+            queen_mobility_baseline: 12,
+
+            bishop_pair: Weight::new(30, 50),
+
+            rook_open_file: Weight::new(25, 10),
+            rook_semi_open_file: Weight::new(12, 6),
+            rook_seventh_rank: Weight::new(10, 25),
+
+            knight_outpost: Weight::new(20, 10),
+
+            trapped_bishop: Weight::new(-100, -100),
+            trapped_rook: Weight::new(-40, -10),
+            trapped_rook_max_mobility: 3,
+        };
+    }
+}
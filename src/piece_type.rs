@@ -23,28 +23,65 @@ impl PieceType {
         };
     }
 
-    pub fn piece_happy_square_value(&self, square: usize, is_white_piece: bool) -> i64 {
-        return match self {
-            Self::King => constants::KING_HAPPY_SQUARES_NON_ENDGAME[square],
-            Self::Queen => constants::QUEEN_HAPPY_SQUARES[square],
-            Self::Rook => constants::ROOK_HAPPY_SQUARES[square],
-            Self::Bishop => constants::BISHOP_HAPPY_SQUARES[square],
-            Self::Knight => constants::KNIGHT_HAPPY_SQUARES[square],
-            Self::Pawn => {
-                if is_white_piece {
-                    return constants::PAWN_HAPPY_SQUARES[square];
-                } else {
-                    // Break the square into it's x and y components; and negate the y component.
-                    let rank: usize = 7 - (square / 8);
-                    let file_number: usize = square % 8;
+    // Midgame and endgame piece-square values. The tables are from white's side, so black's square is mirrored.
+    pub fn piece_happy_square_values(&self, square: usize, is_white_piece: bool) -> (i64, i64) {
+        let table_square = if is_white_piece {
+            square
+        } else {
+            // Break the square into it's x and y components; and negate the y component.
+            let rank: usize = 7 - (square / 8);
+            let file_number: usize = square % 8;
+            rank * 8 + file_number
+        };
 
-                    // Rebuild the coordinate.
-                    let new_square: usize = rank * 8 + file_number;
+        let (midgame_table, endgame_table) = match self {
+            Self::King => (
+                &constants::KING_HAPPY_SQUARES_MIDGAME,
+                &constants::KING_HAPPY_SQUARES_ENDGAME,
+            ),
+            Self::Queen => (
+                &constants::QUEEN_HAPPY_SQUARES_MIDGAME,
+                &constants::QUEEN_HAPPY_SQUARES_ENDGAME,
+            ),
+            Self::Rook => (
+                &constants::ROOK_HAPPY_SQUARES_MIDGAME,
+                &constants::ROOK_HAPPY_SQUARES_ENDGAME,
+            ),
+            Self::Bishop => (
+                &constants::BISHOP_HAPPY_SQUARES_MIDGAME,
+                &constants::BISHOP_HAPPY_SQUARES_ENDGAME,
+            ),
+            Self::Knight => (
+                &constants::KNIGHT_HAPPY_SQUARES_MIDGAME,
+                &constants::KNIGHT_HAPPY_SQUARES_ENDGAME,
+            ),
+            Self::Pawn => (
+                &constants::PAWN_HAPPY_SQUARES_MIDGAME,
+                &constants::PAWN_HAPPY_SQUARES_ENDGAME,
+            ),
+        };
 
-                    // Get the value.
-                    return constants::PAWN_HAPPY_SQUARES[new_square];
-                }
-            }
+        return (midgame_table[table_square], endgame_table[table_square]);
+    }
+
+    // How much this piece counts towards `GAME_PHASE_MIDGAME`.
+    pub fn game_phase_weight(&self) -> i64 {
+        return match self {
+            Self::Queen => 4,
+            Self::Rook => 2,
+            Self::Bishop | Self::Knight => 1,
+            Self::King | Self::Pawn => 0,
+        };
+    }
+
+    // Attack units for each square of the enemy king zone this piece hits.
+    pub fn king_attack_weight(&self) -> usize {
+        return match self {
+            Self::Queen => 5,
+            Self::Rook => 3,
+            Self::Bishop | Self::Knight => 2,
+            Self::Pawn => 1,
+            Self::King => 0,
         };
     }
 
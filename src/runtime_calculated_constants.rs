@@ -27,6 +27,15 @@ pub struct Constants {
     pub zobrist_castling_rights: [u64; 4],
     pub zobrist_en_passant: [u64; 8],
     pub zobrist_to_move: u64,
+
+    // Pawn structure. Files are a to h. Ranks are from white's side, 0 is the first rank.
+    pub file_masks: [u64; 8],
+    pub rank_masks: [u64; 8],
+    pub adjacent_file_masks: [u64; 8],
+
+    // [color][square]. Squares ahead of a pawn on its own file, and ahead on its own and both adjacent files.
+    pub front_span_masks: [[u64; 64]; 2],
+    pub passed_pawn_masks: [[u64; 64]; 2],
 }
 
 impl Constants {
@@ -50,6 +59,42 @@ impl Constants {
             king_attacks[square] = mask_king_attacks(square);
         }
 
+        let mut file_masks: [u64; 8] = [0; 8];
+        let mut rank_masks: [u64; 8] = [0; 8];
+        for square in 0..64 {
+            file_masks[square % 8] = set_bit(file_masks[square % 8], square);
+            rank_masks[7 - square / 8] = set_bit(rank_masks[7 - square / 8], square);
+        }
+
+        let mut adjacent_file_masks: [u64; 8] = [0; 8];
+        for file in 0..8 {
+            if file > 0 {
+                adjacent_file_masks[file] |= file_masks[file - 1];
+            }
+            if file < 7 {
+                adjacent_file_masks[file] |= file_masks[file + 1];
+            }
+        }
+
+        let mut front_span_masks: [[u64; 64]; 2] = [[0; 64]; 2];
+        let mut passed_pawn_masks: [[u64; 64]; 2] = [[0; 64]; 2];
+        for square in 0..64 {
+            let rank = 7 - square / 8;
+            let file = square % 8;
+
+            // Every rank in front of the pawn, from each side.
+            let ranks_ahead_white: u64 = rank_masks[rank + 1..].iter().fold(0, |all, r| all | r);
+            let ranks_ahead_black: u64 = rank_masks[..rank].iter().fold(0, |all, r| all | r);
+            for (color, ranks_ahead) in [
+                (Color::White, ranks_ahead_white),
+                (Color::Black, ranks_ahead_black),
+            ] {
+                front_span_masks[color.idx()][square] = ranks_ahead & file_masks[file];
+                passed_pawn_masks[color.idx()][square] =
+                    ranks_ahead & (file_masks[file] | adjacent_file_masks[file]);
+            }
+        }
+
         init_slider_attacks(true, &mut bishop_attacks, &mut rook_attacks);
         init_slider_attacks(false, &mut bishop_attacks, &mut rook_attacks);
 
@@ -94,6 +139,11 @@ impl Constants {
             zobrist_castling_rights,
             zobrist_en_passant,
             zobrist_to_move: rng.gen(),
+            file_masks,
+            rank_masks,
+            adjacent_file_masks,
+            front_span_masks,
+            passed_pawn_masks,
         };
     }
 }
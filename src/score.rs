@@ -0,0 +1,138 @@
+// Evaluations are in centipawns, from white's point of view. Checkmates get their own range at the very top and bottom.
+
+// Being checkmated right now. Each ply to the mate takes one off, so shorter mates score better.
+pub const MATE_SCORE: i64 = 1_000_000;
+
+// Longest mate we can express. Anything closer than this to MATE_SCORE is a mate score.
+pub const MAX_MATE_PLY: i64 = 1_000;
+
+// Bigger than any real score, but still safe to negate. Use instead of i64::MIN/MAX for alpha/beta.
+pub const INFINITY: i64 = MATE_SCORE + 1;
+
+// Score for the side to move being checkmated, `ply` half moves from the search root.
+pub fn checkmated(is_white_mated: bool, ply: u32) -> i64 {
+    let score = MATE_SCORE - ply as i64;
+    return if is_white_mated { -score } else { score };
+}
+
+pub fn is_mate_score(score: i64) -> bool {
+    return score.abs() >= MATE_SCORE - MAX_MATE_PLY;
+}
+
+// Full moves until mate. Positive if white mates, negative if black does.
+pub fn mate_in_moves(score: i64) -> Option<i64> {
+    if !is_mate_score(score) {
+        return None;
+    }
+
+    let plies = MATE_SCORE - score.abs();
+    let moves = (plies + 1) / 2;
+    return Some(if score > 0 { moves } else { -moves });
+}
+
+// The transposition table is shared by nodes at different plies. Store mates as distance from the node, not the root.
+pub fn to_transposition_table(score: i64, ply: u32) -> i64 {
+    if !is_mate_score(score) {
+        return score;
+    }
+    return if score > 0 {
+        score + ply as i64
+    } else {
+        score - ply as i64
+    };
+}
+
+pub fn from_transposition_table(score: i64, ply: u32) -> i64 {
+    if !is_mate_score(score) {
+        return score;
+    }
+    return if score > 0 {
+        score - ply as i64
+    } else {
+        score + ply as i64
+    };
+}
+
+// For logs and debug output, from white's point of view. Ex: `+0.35`, `mate 3`, `mate -2`.
+pub fn to_display_str(score: i64) -> String {
+    return match mate_in_moves(score) {
+        Some(moves) => format!("mate {moves}"),
+        None => format!("{:+.2}", score as f64 / 100.0),
+    };
+}
+
+// For `[%eval]` PGN comments, in pawns from white's point of view. Ex: `0.35`, `#3`, `#-2`.
+pub fn to_pgn_eval_str(score: i64) -> String {
+    return match mate_in_moves(score) {
+        Some(moves) => format!("#{moves}"),
+        None => format!("{:.2}", score as f64 / 100.0),
+    };
+}
+
+// UCI wants the score from the point of view of the side to move. Ex: `cp 35`, `mate -2`.
+pub fn to_uci_str(score: i64, white_to_move: bool) -> String {
+    let our_score = if white_to_move { score } else { -score };
+    return match mate_in_moves(our_score) {
+        Some(moves) => format!("mate {moves}"),
+        None => format!("cp {our_score}"),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transposition_table::TranspositionTable;
+    use crate::transposition_table_entry::{TranspositionTableEntry, TranspositionTableNodeType};
+
+    #[test]
+    fn mate_distance_survives_the_transposition_table() {
+        let mut table = TranspositionTable::new(1);
+
+        // Found at one ply, mate 4 plies further on. Probed from another ply, the mate is still 4 plies away.
+        for (store_ply, probe_ply) in [(0, 0), (3, 7), (7, 2), (10, 11)] {
+            for is_white_mated in [false, true] {
+                let found = checkmated(is_white_mated, store_ply + 4);
+                table.store(TranspositionTableEntry {
+                    zobrist_hash: 42,
+                    best_move: None,
+                    depth: 6,
+                    evaluation: to_transposition_table(found, store_ply),
+                    node_type: TranspositionTableNodeType::Exact,
+                    generation: 0,
+                });
+
+                let stored = table.probe(42).unwrap().evaluation;
+                assert_eq!(
+                    from_transposition_table(stored, probe_ply),
+                    checkmated(is_white_mated, probe_ply + 4)
+                );
+            }
+        }
+
+        // Everything else is stored as is.
+        assert_eq!(to_transposition_table(-250, 9), -250);
+        assert_eq!(from_transposition_table(35, 4), 35);
+    }
+
+    #[test]
+    fn score_strings() {
+        // White mates in 1 and black mates in 2 (3 plies).
+        let white_mates = checkmated(false, 1);
+        let black_mates = checkmated(true, 3);
+        assert_eq!(mate_in_moves(white_mates), Some(1));
+        assert_eq!(mate_in_moves(black_mates), Some(-2));
+        assert_eq!(mate_in_moves(-420), None);
+
+        assert_eq!(to_display_str(white_mates), "mate 1");
+        assert_eq!(to_display_str(black_mates), "mate -2");
+        assert_eq!(to_display_str(-35), "-0.35");
+        assert_eq!(to_pgn_eval_str(black_mates), "#-2");
+
+        // UCI is from the side to move.
+        assert_eq!(to_uci_str(white_mates, true), "mate 1");
+        assert_eq!(to_uci_str(white_mates, false), "mate -1");
+        assert_eq!(to_uci_str(black_mates, false), "mate 2");
+        assert_eq!(to_uci_str(black_mates, true), "mate -2");
+        assert_eq!(to_uci_str(35, false), "cp -35");
+    }
+}
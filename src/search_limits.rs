@@ -0,0 +1,47 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+// Tells iterative deepening when to stop. `None` means no limit of that kind.
+#[derive(Clone)]
+pub struct SearchLimits {
+    pub max_depth: Option<u32>,
+    pub max_nodes: Option<u64>,
+
+    // Soft: don't start a new depth after this. Hard: abort the search, even part way through a depth.
+    pub soft_time_limit_ms: Option<u128>,
+    pub hard_time_limit_ms: Option<u128>,
+
+    // Can be set from another thread to end the search early (UCI `stop`).
+    pub stop_flag: Arc<AtomicBool>,
+}
+
+impl SearchLimits {
+    // Search until told to stop.
+    pub fn infinite() -> Self {
+        return SearchLimits {
+            max_depth: None,
+            max_nodes: None,
+            soft_time_limit_ms: None,
+            hard_time_limit_ms: None,
+            stop_flag: Arc::new(AtomicBool::new(false)),
+        };
+    }
+
+    // Nothing but the stop flag will end the search.
+    pub fn is_infinite(&self) -> bool {
+        return self.max_depth.is_none()
+            && self.max_nodes.is_none()
+            && self.hard_time_limit_ms.is_none();
+    }
+}
+
+// Without a clock, think for about 5 seconds per move.
+impl Default for SearchLimits {
+    fn default() -> Self {
+        SearchLimits {
+            soft_time_limit_ms: Some(5_000),
+            hard_time_limit_ms: Some(10_000),
+            ..SearchLimits::infinite()
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use crate::search_limits::SearchLimits;
+
+// Kept in reserve for network lag and the time it takes to send the move.
+pub const MOVE_OVERHEAD_MS: u64 = 100;
+
+// If we don't know how many moves are left until the next time control, assume this many.
+pub const DEFAULT_MOVES_TO_GO: u64 = 30;
+
+// Splits the clock into a time budget for the current move.
+pub struct TimeManager {
+    pub time_left_ms: u64,
+    pub increment_ms: u64,
+    pub moves_to_go: Option<u64>,
+}
+
+impl TimeManager {
+    pub fn new(time_left_ms: u64, increment_ms: u64, moves_to_go: Option<u64>) -> Self {
+        return TimeManager {
+            time_left_ms,
+            increment_ms,
+            moves_to_go,
+        };
+    }
+
+    fn available_time_ms(&self) -> u64 {
+        return u64::max(self.time_left_ms.saturating_sub(MOVE_OVERHEAD_MS), 1);
+    }
+
+    // What we would like to spend on an average move.
+    fn base_time_ms(&self) -> u64 {
+        let moves_to_go = u64::max(self.moves_to_go.unwrap_or(DEFAULT_MOVES_TO_GO), 1);
+        return self.available_time_ms() / moves_to_go + self.increment_ms * 3 / 4;
+    }
+
+    // Past the soft deadline, we finish the current depth but don't start another one.
+    pub fn soft_limit_ms(&self) -> u128 {
+        let soft_limit = u64::min(self.base_time_ms(), self.hard_limit_ms() as u64);
+        return u64::max(soft_limit, 1) as u128;
+    }
+
+    // Past the hard deadline, the search is stopped, even in the middle of a depth.
+    pub fn hard_limit_ms(&self) -> u128 {
+        // Never bet more than a chunk of the remaining clock on a single move.
+        let hard_limit = u64::min(self.base_time_ms() * 3, self.available_time_ms() * 3 / 4);
+        return u64::max(hard_limit, 1) as u128;
+    }
+
+    pub fn search_limits(&self) -> SearchLimits {
+        let mut limits = SearchLimits::infinite();
+        limits.soft_time_limit_ms = Some(self.soft_limit_ms());
+        limits.hard_time_limit_ms = Some(self.hard_limit_ms());
+
+        return limits;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uses_increment_and_moves_to_go() {
+        let sudden_death = TimeManager::new(60_000, 0, None);
+        let with_increment = TimeManager::new(60_000, 2_000, None);
+        let before_time_control = TimeManager::new(60_000, 0, Some(5));
+
+        assert_eq!(
+            sudden_death.soft_limit_ms(),
+            (60_000 - MOVE_OVERHEAD_MS as u128) / 30
+        );
+        assert!(with_increment.soft_limit_ms() > sudden_death.soft_limit_ms());
+        assert!(before_time_control.soft_limit_ms() > sudden_death.soft_limit_ms());
+        assert!(sudden_death.soft_limit_ms() <= sudden_death.hard_limit_ms());
+    }
+
+    #[test]
+    fn hard_limit_stays_inside_the_clock() {
+        for time_left_ms in [150, 1_000, 5_000, 60_000, 3_600_000] {
+            for increment_ms in [0, 1_000, 30_000] {
+                for moves_to_go in [None, Some(1), Some(40)] {
+                    let manager = TimeManager::new(time_left_ms, increment_ms, moves_to_go);
+                    let available = (time_left_ms - MOVE_OVERHEAD_MS) as u128;
+                    assert!(
+                        manager.hard_limit_ms() <= available,
+                        "{time_left_ms} {increment_ms} {moves_to_go:?}"
+                    );
+                    assert!(manager.soft_limit_ms() <= manager.hard_limit_ms());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn nearly_out_of_time() {
+        // Less than the overhead left, or nothing at all. Still a tiny search, not a panic.
+        for time_left_ms in [0, 1, MOVE_OVERHEAD_MS - 1, MOVE_OVERHEAD_MS] {
+            let manager = TimeManager::new(time_left_ms, 0, Some(0));
+            assert_eq!(manager.soft_limit_ms(), 1);
+            assert_eq!(manager.hard_limit_ms(), 1);
+        }
+    }
+}
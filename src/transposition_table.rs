@@ -0,0 +1,190 @@
+use crate::transposition_table_entry::TranspositionTableEntry;
+
+// Entries per bucket. Positions that hash to the same bucket compete for these slots.
+pub const BUCKET_SIZE: usize = 4;
+
+type Bucket = [Option<TranspositionTableEntry>; BUCKET_SIZE];
+
+// A fixed-size cache of search results. All the memory is allocated up front, so it never grows during a game.
+#[derive(Clone)]
+pub struct TranspositionTable {
+    buckets: Vec<Bucket>,
+
+    // Goes up once per search, so we can tell which entries are left over from older moves.
+    pub generation: u8,
+}
+
+impl TranspositionTable {
+    pub fn new(size_mb: usize) -> Self {
+        return TranspositionTable {
+            buckets: vec![[None; BUCKET_SIZE]; Self::bucket_count(size_mb)],
+            generation: 0,
+        };
+    }
+
+    fn bucket_count(size_mb: usize) -> usize {
+        let size_bytes = size_mb * 1024 * 1024;
+        return usize::max(size_bytes / std::mem::size_of::<Bucket>(), 1);
+    }
+
+    // Throws away everything stored so far.
+    pub fn resize(&mut self, size_mb: usize) {
+        self.buckets = vec![[None; BUCKET_SIZE]; Self::bucket_count(size_mb)];
+        self.generation = 0;
+    }
+
+    pub fn clear(&mut self) {
+        self.buckets.fill([None; BUCKET_SIZE]);
+        self.generation = 0;
+    }
+
+    // Call at the start of every search.
+    pub fn new_search(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+    }
+
+    fn bucket_index(&self, zobrist_hash: u64) -> usize {
+        return (zobrist_hash % self.buckets.len() as u64) as usize;
+    }
+
+    pub fn probe(&self, zobrist_hash: u64) -> Option<TranspositionTableEntry> {
+        let bucket = &self.buckets[self.bucket_index(zobrist_hash)];
+        for entry in bucket.iter().flatten() {
+            if entry.zobrist_hash == zobrist_hash {
+                return Some(*entry);
+            }
+        }
+
+        return None;
+    }
+
+    pub fn store(&mut self, mut entry: TranspositionTableEntry) {
+        entry.generation = self.generation;
+        let generation = self.generation;
+        let index = self.bucket_index(entry.zobrist_hash);
+        let bucket = &mut self.buckets[index];
+
+        // Same position already stored. Keep a deeper result from this search, otherwise overwrite it.
+        for slot in bucket.iter_mut() {
+            if let Some(existing) = slot {
+                if existing.zobrist_hash == entry.zobrist_hash {
+                    if entry.depth >= existing.depth || existing.generation != generation {
+                        *slot = Some(entry);
+                    }
+                    return;
+                }
+            }
+        }
+
+        // Use an empty slot if there is one.
+        for slot in bucket.iter_mut() {
+            if slot.is_none() {
+                *slot = Some(entry);
+                return;
+            }
+        }
+
+        // Otherwise replace the least useful entry. Shallow entries from old searches go first.
+        let mut replace_index = 0;
+        let mut lowest_value = i64::MAX;
+        for (i, slot) in bucket.iter().enumerate() {
+            let existing = slot.expect("Bucket is full.");
+            let age = generation.wrapping_sub(existing.generation) as i64;
+            let value = existing.depth as i64 - 8 * age;
+            if value < lowest_value {
+                lowest_value = value;
+                replace_index = i;
+            }
+        }
+        bucket[replace_index] = Some(entry);
+    }
+
+    pub fn capacity(&self) -> usize {
+        return self.buckets.len() * BUCKET_SIZE;
+    }
+
+    // Slow, walks the whole table. Only for debug output.
+    pub fn used_entries(&self) -> usize {
+        return self.buckets.iter().flatten().flatten().count();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transposition_table_entry::TranspositionTableNodeType;
+
+    fn entry(zobrist_hash: u64, depth: u32) -> TranspositionTableEntry {
+        return TranspositionTableEntry {
+            zobrist_hash,
+            best_move: None,
+            depth,
+            evaluation: 0,
+            node_type: TranspositionTableNodeType::Exact,
+            generation: 0,
+        };
+    }
+
+    fn stored_depth(table: &TranspositionTable, zobrist_hash: u64) -> Option<u32> {
+        return table.probe(zobrist_hash).map(|e| e.depth);
+    }
+
+    #[test]
+    fn store_probe_and_replace() {
+        let mut table = TranspositionTable::new(1);
+        // Every one of these lands in the same bucket.
+        let hash = |i: u64| 7 + i * table.buckets.len() as u64;
+        let hashes: Vec<u64> = (0..8).map(hash).collect();
+
+        // The same position keeps the deeper result from this search, but not from an older one.
+        table.store(entry(hashes[0], 5));
+        table.store(entry(hashes[0], 3));
+        assert_eq!(stored_depth(&table, hashes[0]), Some(5));
+        assert_eq!(stored_depth(&table, hashes[1]), None);
+
+        // A full bucket gives up its shallowest entry.
+        for (i, depth) in [(1, 2), (2, 8), (3, 4)] {
+            table.store(entry(hashes[i], depth));
+        }
+        table.store(entry(hashes[4], 1));
+        assert_eq!(stored_depth(&table, hashes[1]), None);
+        assert_eq!(stored_depth(&table, hashes[4]), Some(1));
+
+        // Next search. Entries from the last one are worth less than their depth says.
+        table.new_search();
+        assert_eq!(table.generation, 1);
+        table.store(entry(hashes[0], 3));
+        assert_eq!(stored_depth(&table, hashes[0]), Some(3));
+        assert_eq!(table.probe(hashes[0]).unwrap().generation, 1);
+
+        // Old depth 1 goes first, then old depth 4 before the fresh depth 3.
+        table.store(entry(hashes[5], 10));
+        assert_eq!(stored_depth(&table, hashes[4]), None);
+        table.store(entry(hashes[6], 1));
+        assert_eq!(stored_depth(&table, hashes[3]), None);
+        assert_eq!(stored_depth(&table, hashes[0]), Some(3));
+        assert_eq!(stored_depth(&table, hashes[2]), Some(8));
+        assert_eq!(table.used_entries(), BUCKET_SIZE);
+    }
+
+    #[test]
+    fn clear_and_resize() {
+        let mut table = TranspositionTable::new(1);
+        table.new_search();
+        table.store(entry(12345, 4));
+
+        table.clear();
+        assert_eq!(table.used_entries(), 0);
+        assert_eq!(table.generation, 0);
+        assert_eq!(table.probe(12345).map(|e| e.depth), None);
+
+        let capacity = table.capacity();
+        table.store(entry(12345, 4));
+        table.new_search();
+        table.resize(2);
+        assert_eq!(table.capacity(), TranspositionTable::new(2).capacity());
+        assert!(table.capacity() > capacity);
+        assert_eq!(table.used_entries(), 0);
+        assert_eq!(table.generation, 0);
+    }
+}
@@ -1,16 +1,18 @@
 use crate::r#move::Move;
 
-#[derive(Clone)]
+#[derive(Copy, Clone)]
 pub struct TranspositionTableEntry {
     pub zobrist_hash: u64,
     pub best_move: Option<Move>,
     pub depth: u32,
     pub evaluation: i64,
     pub node_type: TranspositionTableNodeType,
-    pub age: u128,
+
+    // Which search stored this entry. Set by the table.
+    pub generation: u8,
 }
 
-#[derive(Clone)]
+#[derive(Copy, Clone)]
 pub enum TranspositionTableNodeType {
     Exact,
     LowerBound,
@@ -0,0 +1,383 @@
+use crate::chess_game::ChessGame;
+use crate::constants;
+use crate::opening_book::{BookSelection, OpeningBook};
+use crate::r#move::Move;
+use crate::runtime_calculated_constants::Constants;
+use crate::search_limits::SearchLimits;
+use crate::time_manager::TimeManager;
+use std::io;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+// Speaks the UCI protocol over stdin/stdout, so the engine can be used from any chess GUI.
+// Reference: https://www.wbec-ridderkerk.nl/html/UCIProtocol.html
+pub fn main() {
+    let c = Constants::new();
+    let mut game = ChessGame::new(&c);
+    game.transposition_table
+        .resize(constants::DEFAULT_TRANSPOSITION_TABLE_SIZE_MB);
+    let _ = game.import_fen(constants::INITIAL_GAME_STATE_FEN);
+    game.set_legal_moves(None);
+    game.print_uci_search_info = true;
+
+    // Stdin is read on its own thread. The search runs on this one, so `stop` has to reach it through the flag, and
+    // `isready` is answered straight away. GUIs ping with it mid search and give up if nothing comes back.
+    let stop_flag = Arc::new(AtomicBool::new(false));
+    let (sender, receiver) = mpsc::channel::<String>();
+    let reader_stop_flag = stop_flag.clone();
+    thread::spawn(move || {
+        for line in io::stdin().lines() {
+            let command = match line {
+                Ok(l) => l.trim().to_string(),
+                Err(_) => break,
+            };
+
+            if command == "isready" {
+                println!("readyok");
+                continue;
+            }
+
+            if command == "stop" || command == "quit" {
+                reader_stop_flag.store(true, Ordering::Relaxed);
+            }
+
+            if sender.send(command).is_err() {
+                return;
+            }
+        }
+
+        // The GUI went away, treat it like `quit`.
+        reader_stop_flag.store(true, Ordering::Relaxed);
+        let _ = sender.send(String::from("quit"));
+    });
+
+    while let Ok(command) = receiver.recv() {
+        let tokens: Vec<&str> = command.split_whitespace().collect();
+        let (name, args) = match tokens.split_first() {
+            Some((name, args)) => (*name, args),
+            None => continue,
+        };
+
+        match name {
+            "uci" => {
+                println!("id name chess-engine");
+                println!("id author master14270");
+                println!(
+                    "option name Hash type spin default {} min 1 max 4096",
+                    constants::DEFAULT_TRANSPOSITION_TABLE_SIZE_MB
+                );
+                println!("option name BookFile type string default <empty>");
+                println!(
+                    "option name BookDepth type spin default {} min 0 max 1000",
+                    constants::DEFAULT_MAX_BOOK_DEPTH_PLY
+                );
+                println!("option name BookBestMove type check default false");
+                println!("option name UCI_Chess960 type check default false");
+                println!("uciok");
+            }
+            "ucinewgame" => {
+                game.transposition_table.clear();
+                let _ = game.import_fen(constants::INITIAL_GAME_STATE_FEN);
+                game.set_legal_moves(None);
+            }
+            "position" => {
+                if let Err(e) = set_position(&mut game, args) {
+                    println!("info string {e}");
+                }
+            }
+            "setoption" => {
+                if let Err(e) = set_option(&mut game, args) {
+                    println!("info string {e}");
+                }
+            }
+            "go" => go(&mut game, args, &stop_flag),
+
+            // Any search that this was meant for has finished by now, so the flag can be reset.
+            "stop" => stop_flag.store(false, Ordering::Relaxed),
+            "quit" => break,
+
+            // Not supported, but part of the protocol. Ignore quietly.
+            "debug" | "register" | "ponderhit" => (),
+            _ => println!("info string Unknown command: {command}"),
+        }
+    }
+}
+
+// position [startpos | fen <fen>] [moves <move1> ... <moveN>]
+fn set_position(game: &mut ChessGame, args: &[&str]) -> Result<(), String> {
+    let moves_index = args.iter().position(|a| *a == "moves");
+    let (position_args, move_args) = match moves_index {
+        Some(i) => (&args[..i], &args[i + 1..]),
+        None => (args, &args[args.len()..]),
+    };
+
+    let fen = match position_args.split_first() {
+        Some((&"startpos", _)) => constants::INITIAL_GAME_STATE_FEN.to_string(),
+        Some((&"fen", fen_parts)) => fen_parts.join(" "),
+        _ => {
+            return Err(format!(
+                "Expected `startpos` or `fen`. Got: `{}`.",
+                args.join(" ")
+            ))
+        }
+    };
+
+    // Try it on a scratch board first. A bad command leaves the current position alone.
+    let mut scratch = ChessGame::new(game.bitboard_constants);
+    scratch.is_chess960 = game.is_chess960;
+    load_position(&mut scratch, &fen, move_args)?;
+
+    return load_position(game, &fen, move_args);
+}
+
+fn load_position(game: &mut ChessGame, fen: &str, move_args: &[&str]) -> Result<(), String> {
+    game.import_fen(fen)?;
+    game.set_legal_moves(None);
+
+    for move_str in move_args.iter() {
+        let raw_move = Move::str_to_move(move_str)?;
+        let m = match game.choose_move_from_legal_move(&raw_move) {
+            Some(m) => m,
+            None => return Err(format!("Illegal move in position command: `{move_str}`.")),
+        };
+        game.make_move(&m, true);
+    }
+
+    return Ok(());
+}
+
+// setoption name <id> [value <x>]
+fn set_option(game: &mut ChessGame, args: &[&str]) -> Result<(), String> {
+    let value_index = args.iter().position(|a| *a == "value");
+    let (name_args, value_args) = match value_index {
+        Some(i) => (&args[..i], &args[i + 1..]),
+        None => (args, &args[args.len()..]),
+    };
+
+    let name = match name_args.split_first() {
+        Some((&"name", name_parts)) => name_parts.join(" "),
+        _ => return Err(format!("Expected `name`. Got: `{}`.", args.join(" "))),
+    };
+    let value = value_args.join(" ");
+
+    match name.to_ascii_lowercase().as_str() {
+        "hash" => {
+            let size_mb: usize = match value.parse() {
+                Ok(n) if n >= 1 => n,
+                _ => return Err(format!("Invalid Hash size: `{value}`.")),
+            };
+            game.transposition_table.resize(size_mb);
+        }
+        "bookfile" => {
+            if value.is_empty() || value == "<empty>" {
+                game.opening_book = None;
+                return Ok(());
+            }
+
+            // Keep the other book options, they may have been set first.
+            let options = game.opening_book.clone().unwrap_or_default();
+            let book = OpeningBook::load(&value, options.selection, options.max_depth_ply)?;
+            game.opening_book = Some(Arc::new(book));
+        }
+        "bookdepth" => {
+            let max_depth_ply: u32 = match value.parse() {
+                Ok(n) => n,
+                _ => return Err(format!("Invalid BookDepth: `{value}`.")),
+            };
+            let book = game.opening_book.get_or_insert_with(Default::default);
+            Arc::make_mut(book).max_depth_ply = max_depth_ply;
+        }
+        "bookbestmove" => {
+            let selection = match value.as_str() {
+                "true" => BookSelection::BestWeight,
+                "false" => BookSelection::WeightedRandom,
+                _ => return Err(format!("Invalid BookBestMove: `{value}`.")),
+            };
+            let book = game.opening_book.get_or_insert_with(Default::default);
+            Arc::make_mut(book).selection = selection;
+        }
+        "uci_chess960" => {
+            game.is_chess960 = match value.as_str() {
+                "true" => true,
+                "false" => false,
+                _ => return Err(format!("Invalid UCI_Chess960: `{value}`.")),
+            };
+        }
+        _ => return Err(format!("Unknown option: `{name}`.")),
+    }
+
+    return Ok(());
+}
+
+// go [wtime <ms>] [btime <ms>] [winc <ms>] [binc <ms>] [movestogo <n>] [movetime <ms>] [depth <n>] [nodes <n>] [infinite]
+fn go(game: &mut ChessGame, args: &[&str], stop_flag: &Arc<AtomicBool>) {
+    let mut limits = parse_go_limits(args, game.white_to_move);
+    limits.stop_flag = stop_flag.clone();
+
+    if game.legal_moves.is_empty() {
+        println!("bestmove 0000");
+        return;
+    }
+
+    // Under `go infinite` and `go ponder` the GUI waits for `stop` before any bestmove. Search instead of answering from
+    // the book straight away.
+    if !limits.is_infinite() && !args.contains(&"ponder") {
+        if let Some(m) = game.get_book_move() {
+            println!("bestmove {}", game.move_to_uci(&m));
+            return;
+        }
+    }
+
+    game.search_limits = limits;
+    let (_, best_move) = game.iterative_deepening_minimax();
+    game.search_limits = SearchLimits::default();
+
+    match best_move {
+        Some(m) => println!("bestmove {}", game.move_to_uci(&m)),
+        None => println!("bestmove 0000"),
+    }
+}
+
+// The search limits from the arguments of `go`, for whoever is to move. Everything left out is unlimited.
+fn parse_go_limits(args: &[&str], white_to_move: bool) -> SearchLimits {
+    let mut limits = SearchLimits::infinite();
+
+    let mut wtime: Option<i64> = None;
+    let mut btime: Option<i64> = None;
+    let mut winc: i64 = 0;
+    let mut binc: i64 = 0;
+    let mut moves_to_go: Option<u64> = None;
+    let mut move_time: Option<u128> = None;
+
+    for (i, arg) in args.iter().enumerate() {
+        match *arg {
+            "wtime" => wtime = parse_go_value(args, i),
+            "btime" => btime = parse_go_value(args, i),
+            "winc" => winc = parse_go_value(args, i).unwrap_or(0),
+            "binc" => binc = parse_go_value(args, i).unwrap_or(0),
+            "movestogo" => moves_to_go = parse_go_value(args, i),
+            "movetime" => move_time = parse_go_value(args, i),
+            "depth" => limits.max_depth = parse_go_value(args, i),
+            "nodes" => limits.max_nodes = parse_go_value(args, i),
+            _ => (),
+        }
+    }
+
+    // Turn the clock into a time budget for this move, unless we were given one directly.
+    let (our_time, our_increment) = if white_to_move {
+        (wtime, winc)
+    } else {
+        (btime, binc)
+    };
+    if let Some(t) = move_time {
+        limits.soft_time_limit_ms = Some(t);
+        limits.hard_time_limit_ms = Some(t);
+    } else if let Some(time_left) = our_time {
+        // Some GUIs send negative times once the flag falls.
+        let time_manager = TimeManager::new(
+            time_left.max(0) as u64,
+            our_increment.max(0) as u64,
+            moves_to_go,
+        );
+        limits.soft_time_limit_ms = Some(time_manager.soft_limit_ms());
+        limits.hard_time_limit_ms = Some(time_manager.hard_limit_ms());
+    }
+
+    return limits;
+}
+
+fn parse_go_value<T: FromStr>(args: &[&str], name_index: usize) -> Option<T> {
+    return args.get(name_index + 1).and_then(|s| s.parse().ok());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(command: &str) -> Vec<&str> {
+        return command.split_whitespace().collect();
+    }
+
+    // The FEN as our board writes it.
+    fn exported_fen(c: &Constants, fen: &str) -> String {
+        let mut game = ChessGame::new(c);
+        game.import_fen(fen).unwrap();
+        return game.export_fen();
+    }
+
+    #[test]
+    fn position_command() {
+        let c = Constants::new();
+        let mut game = ChessGame::new(&c);
+
+        set_position(&mut game, &args("startpos")).unwrap();
+        assert_eq!(
+            game.export_fen(),
+            exported_fen(&c, constants::INITIAL_GAME_STATE_FEN)
+        );
+
+        set_position(&mut game, &args("startpos moves e2e4 e7e5 g1f3")).unwrap();
+        let expected = "rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2";
+        assert_eq!(game.export_fen(), exported_fen(&c, expected));
+
+        set_position(
+            &mut game,
+            &args("fen 4k3/1P6/8/8/8/8/8/4K3 w - - 0 1 moves b7b8q e8d7"),
+        )
+        .unwrap();
+        assert_eq!(
+            game.export_fen(),
+            exported_fen(&c, "1Q6/3k4/8/8/8/8/8/4K3 w - - 1 2")
+        );
+
+        // A FEN without moves, and the position stays put when a command is bad.
+        set_position(&mut game, &args("fen 4k3/8/8/8/8/8/8/4K3 b - - 0 1")).unwrap();
+        let before = game.export_fen();
+        assert!(set_position(&mut game, &args("startpos moves e2e4 e7e5 e1e3")).is_err());
+        assert_eq!(game.export_fen(), before);
+        assert!(set_position(
+            &mut game,
+            &args("fen 4k3/8/8/8/8/8/8/4K3 b - - 0 1 moves e8e9")
+        )
+        .is_err());
+        assert!(set_position(&mut game, &args("moves e2e4")).is_err());
+        assert_eq!(game.export_fen(), before);
+    }
+
+    #[test]
+    fn go_command() {
+        let movetime = parse_go_limits(&args("movetime 1500"), true);
+        assert_eq!(movetime.soft_time_limit_ms, Some(1500));
+        assert_eq!(movetime.hard_time_limit_ms, Some(1500));
+        assert_eq!(movetime.max_depth, None);
+
+        let depth = parse_go_limits(&args("depth 6"), true);
+        assert_eq!(depth.max_depth, Some(6));
+        assert_eq!(depth.soft_time_limit_ms, None);
+        assert_eq!(depth.hard_time_limit_ms, None);
+
+        let infinite = parse_go_limits(&args("infinite"), true);
+        assert_eq!(infinite.max_depth, None);
+        assert_eq!(infinite.max_nodes, None);
+        assert_eq!(infinite.hard_time_limit_ms, None);
+        assert!(infinite.is_infinite());
+        assert!(!depth.is_infinite() && !movetime.is_infinite());
+
+        // The clock of whoever is to move, and `movetime` wins over the clock.
+        let clock = "wtime 60000 btime 10000 winc 1000 binc 0 movestogo 20 nodes 5000";
+        let black = parse_go_limits(&args(clock), false);
+        let expected = TimeManager::new(10_000, 0, Some(20));
+        assert_eq!(black.soft_time_limit_ms, Some(expected.soft_limit_ms()));
+        assert_eq!(black.hard_time_limit_ms, Some(expected.hard_limit_ms()));
+        assert_eq!(black.max_nodes, Some(5000));
+        let white = parse_go_limits(&args(clock), true);
+        assert_eq!(
+            white.soft_time_limit_ms,
+            Some(TimeManager::new(60_000, 1_000, Some(20)).soft_limit_ms())
+        );
+        let both = parse_go_limits(&args("wtime 60000 movetime 200"), true);
+        assert_eq!(both.hard_time_limit_ms, Some(200));
+    }
+}